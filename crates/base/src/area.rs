@@ -0,0 +1,543 @@
+use std::ops::RangeInclusive;
+
+use crate::{
+    chunk::{CHUNK_BITS, ChunkPosition},
+    position::Position,
+};
+
+/// Shape of tiles on a floor
+pub trait Area {
+    /// Returns the smallest rect containing the area
+    fn bounds(&self) -> Rect;
+
+    /// Returns true if the tile at (x, y) is in the area
+    fn contains(&self, x: u16, y: u16) -> bool;
+
+    /// Returns true if any tile of the rect is in the area
+    fn intersects(&self, rect: &Rect) -> bool;
+
+    /// Returns an iterator of the tiles in the area, row by row
+    fn tiles(&self) -> Tiles<'_, Self> where Self: Sized {
+        Tiles { area: self, rect: self.bounds().iter() }
+    }
+
+    /// Returns an iterator of the chunks with tiles in the area, row by row
+    fn chunks(&self) -> Chunks<'_, Self> where Self: Sized {
+        let bounds = self.bounds();
+        let chunks = Rect::new(
+            bounds.min_x >> CHUNK_BITS, bounds.min_y >> CHUNK_BITS,
+            bounds.max_x >> CHUNK_BITS, bounds.max_y >> CHUNK_BITS,
+        );
+        Chunks { area: self, chunks: chunks.iter() }
+    }
+}
+
+/// Rectangle of tiles, the bounds are inclusive so it can reach the edges of the map
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub min_x: u16,
+    pub min_y: u16,
+    pub max_x: u16,
+    pub max_y: u16,
+}
+
+impl Rect {
+    /// Creates a rect between the corners, the corners are swapped as needed
+    pub fn new(x1: u16, y1: u16, x2: u16, y2: u16) -> Self {
+        Self {
+            min_x: x1.min(x2),
+            min_y: y1.min(y2),
+            max_x: x1.max(x2),
+            max_y: y1.max(y2),
+        }
+    }
+
+    /// Creates a rect of the tiles within range of the center, clipped at the edges
+    pub fn around(center: Position, range_x: u16, range_y: u16) -> Self {
+        Self {
+            min_x: center.x.saturating_sub(range_x),
+            min_y: center.y.saturating_sub(range_y),
+            max_x: center.x.saturating_add(range_x),
+            max_y: center.y.saturating_add(range_y),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        (self.max_x - self.min_x) as u32 + 1
+    }
+
+    pub fn height(&self) -> u32 {
+        (self.max_y - self.min_y) as u32 + 1
+    }
+
+    /// Returns the number of tiles in the rect
+    pub fn len(&self) -> u64 {
+        self.width() as u64 * self.height() as u64
+    }
+
+    /// Always false, a rect has at least one tile
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Returns the tiles in both rects, None if they don't overlap
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let rect = Rect {
+            min_x: self.min_x.max(other.min_x),
+            min_y: self.min_y.max(other.min_y),
+            max_x: self.max_x.min(other.max_x),
+            max_y: self.max_y.min(other.max_y),
+        };
+        if rect.min_x <= rect.max_x && rect.min_y <= rect.max_y {
+            Some(rect)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the tile of the rect closest to (x, y)
+    pub fn clamp(&self, x: u16, y: u16) -> (u16, u16) {
+        (x.clamp(self.min_x, self.max_x), y.clamp(self.min_y, self.max_y))
+    }
+
+    /// Returns an iterator of the tiles, row by row
+    pub fn iter(&self) -> RectIter {
+        RectIter { rect: *self, next: Some((self.min_x, self.min_y)) }
+    }
+
+    /// Returns an iterator of the positions of the tiles on floor z, row by row
+    pub fn positions(&self, z: u8) -> impl Iterator<Item = Position> {
+        self.iter().map(move |(x, y)| Position { x, y, z })
+    }
+}
+
+impl Area for Rect {
+    fn bounds(&self) -> Rect {
+        *self
+    }
+
+    fn contains(&self, x: u16, y: u16) -> bool {
+        (self.min_x..=self.max_x).contains(&x) && (self.min_y..=self.max_y).contains(&y)
+    }
+
+    fn intersects(&self, rect: &Rect) -> bool {
+        self.intersection(rect).is_some()
+    }
+}
+
+/// Iterator of the tiles in a rect, row by row
+#[derive(Debug, Clone)]
+pub struct RectIter {
+    rect: Rect,
+    next: Option<(u16, u16)>,
+}
+
+impl Iterator for RectIter {
+    type Item = (u16, u16);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (x, y) = self.next?;
+        self.next = if x < self.rect.max_x {
+            Some((x + 1, y))
+        } else if y < self.rect.max_y {
+            Some((self.rect.min_x, y + 1))
+        } else {
+            None
+        };
+        Some((x, y))
+    }
+}
+
+/// A rect on a range of floors
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cube {
+    pub rect: Rect,
+    pub floors: RangeInclusive<u8>,
+}
+
+impl Cube {
+    pub fn new(rect: Rect, floors: RangeInclusive<u8>) -> Self {
+        Self { rect, floors }
+    }
+
+    /// Creates a cube of the positions within range of the center, clipped at the edges
+    pub fn around(center: Position, range_x: u16, range_y: u16, range_z: u8) -> Self {
+        Self {
+            rect: Rect::around(center, range_x, range_y),
+            floors: center.z.saturating_sub(range_z)..=center.z.saturating_add(range_z),
+        }
+    }
+
+    pub fn contains(&self, pos: &Position) -> bool {
+        self.floors.contains(&pos.z) && self.rect.contains(pos.x, pos.y)
+    }
+
+    /// Returns the number of positions in the cube
+    pub fn len(&self) -> u64 {
+        self.rect.len() * self.floors.clone().count() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.floors.is_empty()
+    }
+
+    /// Returns an iterator of the positions, floor by floor
+    pub fn positions(&self) -> impl Iterator<Item = Position> {
+        let rect = self.rect;
+        self.floors.clone().flat_map(move |z| rect.positions(z))
+    }
+
+    /// Returns an iterator of the chunks with positions in the cube, chunks include all floors
+    pub fn chunks(&self) -> Chunks<'_, Rect> {
+        self.rect.chunks()
+    }
+}
+
+/// Tiles within a euclidean distance of the center
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Circle {
+    pub center: Position,
+    pub radius: u16,
+}
+
+impl Circle {
+    pub fn new(center: Position, radius: u16) -> Self {
+        Self { center, radius }
+    }
+
+    /// Returns an iterator of the positions in the circle, on the floor of the center
+    pub fn positions(&self) -> impl Iterator<Item = Position> + '_ {
+        let z = self.center.z;
+        self.tiles().map(move |(x, y)| Position { x, y, z })
+    }
+}
+
+impl Area for Circle {
+    fn bounds(&self) -> Rect {
+        Rect::around(self.center, self.radius, self.radius)
+    }
+
+    fn contains(&self, x: u16, y: u16) -> bool {
+        let dx = self.center.x.abs_diff(x) as u64;
+        let dy = self.center.y.abs_diff(y) as u64;
+        dx * dx + dy * dy <= self.radius as u64 * self.radius as u64
+    }
+
+    fn intersects(&self, rect: &Rect) -> bool {
+        let (x, y) = rect.clamp(self.center.x, self.center.y);
+        self.contains(x, y)
+    }
+}
+
+/// Tiles within a manhattan distance of the center
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Diamond {
+    pub center: Position,
+    pub radius: u16,
+}
+
+impl Diamond {
+    pub fn new(center: Position, radius: u16) -> Self {
+        Self { center, radius }
+    }
+
+    /// Returns an iterator of the positions in the diamond, on the floor of the center
+    pub fn positions(&self) -> impl Iterator<Item = Position> + '_ {
+        let z = self.center.z;
+        self.tiles().map(move |(x, y)| Position { x, y, z })
+    }
+}
+
+impl Area for Diamond {
+    fn bounds(&self) -> Rect {
+        Rect::around(self.center, self.radius, self.radius)
+    }
+
+    fn contains(&self, x: u16, y: u16) -> bool {
+        self.center.x.abs_diff(x) as u32 + self.center.y.abs_diff(y) as u32 <= self.radius as u32
+    }
+
+    fn intersects(&self, rect: &Rect) -> bool {
+        let (x, y) = rect.clamp(self.center.x, self.center.y);
+        self.contains(x, y)
+    }
+}
+
+/// Iterator of the tiles in an area, row by row
+#[derive(Debug, Clone)]
+pub struct Tiles<'a, A> {
+    area: &'a A,
+    rect: RectIter,
+}
+
+impl<'a, A: Area> Iterator for Tiles<'a, A> {
+    type Item = (u16, u16);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let area = self.area;
+        self.rect.find(|&(x, y)| area.contains(x, y))
+    }
+}
+
+/// Iterator of the chunks with tiles in an area, row by row
+#[derive(Debug, Clone)]
+pub struct Chunks<'a, A> {
+    area: &'a A,
+    chunks: RectIter,
+}
+
+impl<'a, A: Area> Iterator for Chunks<'a, A> {
+    type Item = ChunkPosition;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let area = self.area;
+        self.chunks.by_ref()
+            .map(|(x, y)| ChunkPosition::new(x, y))
+            .find(|chunk| area.intersects(&chunk.rect()))
+    }
+}
+
+/// Iterator of the positions around a center in rings of growing distance, starting with the center
+///
+/// Each ring starts at its top left corner and goes clockwise. Positions outside of the map edges are skipped.
+#[derive(Debug, Clone)]
+pub struct Spiral {
+    center: Position,
+    radius: u16,
+    ring: u32,
+    index: u32,
+}
+
+impl Spiral {
+    /// Creates a spiral of the positions within a chebyshev distance of radius
+    pub fn new(center: Position, radius: u16) -> Self {
+        Self { center, radius, ring: 0, index: 0 }
+    }
+
+    /// Returns the offset of the position at index in the ring
+    fn ring_offset(ring: i32, index: i32) -> (i32, i32) {
+        let side = ring * 2;
+        match index / side {
+            0 => (-ring + index, -ring),
+            1 => (ring, -ring + index - side),
+            2 => (ring - (index - side * 2), ring),
+            _ => (-ring, ring - (index - side * 3)),
+        }
+    }
+}
+
+impl Iterator for Spiral {
+    type Item = Position;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.ring > self.radius as u32 {
+                return None;
+            }
+
+            if self.ring == 0 {
+                self.ring = 1;
+                return Some(self.center);
+            }
+
+            let ring = self.ring as i32;
+            let (dx, dy) = Spiral::ring_offset(ring, self.index as i32);
+            self.index += 1;
+            if self.index == self.ring * 8 {
+                self.index = 0;
+                self.ring += 1;
+            }
+
+            let (x, y) = (self.center.x as i32 + dx, self.center.y as i32 + dy);
+            if (0..=u16::MAX as i32).contains(&x) && (0..=u16::MAX as i32).contains(&y) {
+                return Some(Position { x: x as u16, y: y as u16, z: self.center.z });
+            }
+        }
+    }
+}
+
+/// Iterator of the positions on a straight line from start to end, both included, on the floor of start
+///
+/// Uses Bresenham's algorithm, so each step moves to a neighbouring tile, diagonally too.
+/// The line from end to start may take other tiles when there are ties.
+#[derive(Debug, Clone)]
+pub struct Line {
+    x: i32,
+    y: i32,
+    z: u8,
+    end_x: i32,
+    end_y: i32,
+    dx: i32,
+    dy: i32,
+    step_x: i32,
+    step_y: i32,
+    error: i32,
+    done: bool,
+}
+
+impl Line {
+    pub fn new(start: Position, end: Position) -> Self {
+        let dx = (end.x as i32 - start.x as i32).abs();
+        let dy = -(end.y as i32 - start.y as i32).abs();
+        Self {
+            x: start.x as i32,
+            y: start.y as i32,
+            z: start.z,
+            end_x: end.x as i32,
+            end_y: end.y as i32,
+            dx,
+            dy,
+            step_x: if start.x < end.x { 1 } else { -1 },
+            step_y: if start.y < end.y { 1 } else { -1 },
+            error: dx + dy,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for Line {
+    type Item = Position;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let pos = Position { x: self.x as u16, y: self.y as u16, z: self.z };
+        if self.x == self.end_x && self.y == self.end_y {
+            self.done = true;
+            return Some(pos);
+        }
+
+        let error = self.error * 2;
+        if error >= self.dy {
+            self.error += self.dy;
+            self.x += self.step_x;
+        }
+        if error <= self.dx {
+            self.error += self.dx;
+            self.y += self.step_y;
+        }
+        Some(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_rect() {
+        let rect = Rect::new(5, 5, 2, 3);
+        assert_eq!(rect, Rect { min_x: 2, min_y: 3, max_x: 5, max_y: 5 });
+        assert_eq!((rect.width(), rect.height(), rect.len()), (4, 3, 12));
+        assert_eq!(rect.iter().count(), 12);
+        assert_eq!(rect.iter().take(5).collect::<Vec<_>>(), vec![(2, 3), (3, 3), (4, 3), (5, 3), (2, 4)]);
+        assert_eq!(rect.iter().last(), Some((5, 5)));
+
+        assert_eq!(rect.intersection(&Rect::new(4, 0, 10, 3)), Some(Rect::new(4, 3, 5, 3)));
+        assert_eq!(rect.intersection(&Rect::new(6, 0, 10, 3)), None);
+
+        // Reaching the edges of the coordinate range
+        let edge = Rect::around(Position::new(u16::MAX, 0, 7), 1, 1);
+        assert_eq!(edge, Rect::new(u16::MAX - 1, 0, u16::MAX, 1));
+        assert_eq!(edge.iter().count(), 4);
+        assert_eq!(Rect::new(0, 0, u16::MAX, u16::MAX).len(), 1 << 32);
+    }
+
+    #[test]
+    fn test_cube() {
+        let cube = Cube::around(Position::new(100, 100, 7), 1, 1, 1);
+        assert_eq!(cube.len(), 27);
+        assert_eq!(cube.positions().count(), 27);
+        assert!(cube.contains(&Position::new(101, 99, 6)));
+        assert!(!cube.contains(&Position::new(101, 99, 9)));
+        assert!(cube.positions().all(|pos| cube.contains(&pos)));
+    }
+
+    #[test]
+    fn test_circle_and_diamond() {
+        let center = Position::new(100, 100, 7);
+        let circle = Circle::new(center, 2);
+        assert_eq!(circle.tiles().count(), 13);
+        assert!(circle.contains(102, 100) && !circle.contains(102, 102));
+        assert!(circle.positions().all(|pos| pos.z == 7 && pos.euclidean_distance(&center) <= 2.0));
+
+        let diamond = Diamond::new(center, 2);
+        assert_eq!(diamond.tiles().count(), 13);
+        assert!(diamond.positions().all(|pos| pos.manhattan_distance(&center) <= 2));
+
+        // Larger circles cover more than the diamond
+        assert_eq!(Circle::new(center, 3).tiles().count(), 29);
+        assert_eq!(Diamond::new(center, 3).tiles().count(), 25);
+    }
+
+    #[test]
+    fn test_chunks() {
+        let rect = Rect::new(7, 7, 8, 16);
+        let chunks: Vec<_> = rect.chunks().collect();
+        assert_eq!(chunks, vec![
+            ChunkPosition::new(0, 0), ChunkPosition::new(1, 0),
+            ChunkPosition::new(0, 1), ChunkPosition::new(1, 1),
+            ChunkPosition::new(0, 2), ChunkPosition::new(1, 2),
+        ]);
+
+        // The corners of the bounds of a circle may be outside of it
+        let circle = Circle::new(Position::new(104, 104, 7), 12);
+        let chunks: HashSet<_> = circle.chunks().collect();
+        let expected: HashSet<_> = circle.positions().map(ChunkPosition::from).collect();
+        assert_eq!(chunks, expected);
+        assert!(circle.bounds().chunks().count() > chunks.len());
+
+        let diamond = Diamond::new(Position::new(3, 3, 7), 20);
+        let chunks: HashSet<_> = diamond.chunks().collect();
+        let expected: HashSet<_> = diamond.positions().map(ChunkPosition::from).collect();
+        assert_eq!(chunks, expected);
+    }
+
+    #[test]
+    fn test_spiral() {
+        let center = Position::new(100, 100, 7);
+        let spiral: Vec<_> = Spiral::new(center, 2).collect();
+        assert_eq!(spiral.len(), 25);
+        assert_eq!(spiral[0], center);
+        assert_eq!(&spiral[1..4], &[Position::new(99, 99, 7), Position::new(100, 99, 7), Position::new(101, 99, 7)]);
+        assert_eq!(spiral[8], Position::new(99, 100, 7));
+
+        // Rings of growing distance, each position once
+        assert!(spiral.windows(2).all(|pair| pair[0].chebyshev_distance(&center) <= pair[1].chebyshev_distance(&center)));
+        assert_eq!(spiral.iter().collect::<HashSet<_>>().len(), 25);
+        assert_eq!(spiral.iter().collect::<HashSet<_>>(), Rect::around(center, 2, 2).positions(7).collect::<Vec<_>>().iter().collect());
+
+        // Clipped at the edges
+        assert_eq!(Spiral::new(Position::new(0, 0, 7), 2).count(), 9);
+        assert_eq!(Spiral::new(Position::new(u16::MAX, u16::MAX, 7), u16::MAX).take(10).count(), 10);
+    }
+
+    #[test]
+    fn test_line() {
+        let line = |from: (u16, u16), to: (u16, u16)| -> Vec<(u16, u16)> {
+            Line::new(Position::new(from.0, from.1, 7), Position::new(to.0, to.1, 7)).map(|pos| (pos.x, pos.y)).collect()
+        };
+
+        assert_eq!(line((5, 5), (5, 5)), vec![(5, 5)]);
+        assert_eq!(line((5, 5), (8, 5)), vec![(5, 5), (6, 5), (7, 5), (8, 5)]);
+        assert_eq!(line((5, 5), (5, 3)), vec![(5, 5), (5, 4), (5, 3)]);
+        assert_eq!(line((5, 5), (2, 8)), vec![(5, 5), (4, 6), (3, 7), (2, 8)]);
+        assert_eq!(line((0, 0), (4, 2)), vec![(0, 0), (1, 1), (2, 1), (3, 2), (4, 2)]);
+        assert_eq!(line((0, 0), (1, 5)), vec![(0, 0), (0, 1), (0, 2), (1, 3), (1, 4), (1, 5)]);
+        // At the edges of the coordinate range
+        assert_eq!(line((u16::MAX, 0), (u16::MAX - 2, 1)), vec![(u16::MAX, 0), (u16::MAX - 1, 1), (u16::MAX - 2, 1)]);
+
+        // One step per tile of chebyshev distance, to neighbouring tiles
+        let start = Position::new(100, 100, 7);
+        for end in Spiral::new(start, 6) {
+            let positions: Vec<_> = Line::new(start, end).collect();
+            assert_eq!(positions.len(), start.chebyshev_distance(&end) as usize + 1);
+            assert_eq!(positions.last(), Some(&end));
+            assert!(positions.windows(2).all(|pair| pair[0].chebyshev_distance(&pair[1]) == 1));
+        }
+        assert!(Line::new(start, Position::new(103, 101, 5)).all(|pos| pos.z == 7));
+    }
+}
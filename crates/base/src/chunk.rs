@@ -0,0 +1,41 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::{area::Rect, position::Position};
+
+/// Chunks are CHUNK_SIZE x CHUNK_SIZE tiles, on all floors
+pub const CHUNK_BITS: u16 = 3;
+pub const CHUNK_SIZE: u16 = 1 << CHUNK_BITS;
+pub const CHUNK_MASK: u16 = CHUNK_SIZE - 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct ChunkPosition {
+    pub x: u16,
+    pub y: u16,
+}
+
+impl ChunkPosition {
+    pub const fn new(x: u16, y: u16) -> Self {
+        Self { x, y }
+    }
+
+    /// Returns the tiles covered by the chunk
+    pub fn rect(&self) -> Rect {
+        let (x, y) = (self.x << CHUNK_BITS, self.y << CHUNK_BITS);
+        Rect::new(x, y, x + CHUNK_MASK, y + CHUNK_MASK)
+    }
+}
+
+impl From<Position> for ChunkPosition {
+    fn from(pos: Position) -> Self {
+        Self {
+            x: pos.x >> CHUNK_BITS,
+            y: pos.y >> CHUNK_BITS,
+        }
+    }
+}
+
+impl Display for ChunkPosition {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "({}, {})", self.x, self.y)
+    }
+}
@@ -1,9 +1,147 @@
-#[derive(Debug, Eq, PartialEq)]
+use std::{convert::TryFrom, fmt};
+
+use crate::position::Offset;
+
+/// Direction of a step or of where a creature is looking, the values are as sent on the wire
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
 pub enum Direction {
     North = 0,
     East = 1,
+    #[default]
     South = 2,
     West = 3,
-    // ...
-    //None = 8,
+    SouthWest = 4,
+    SouthEast = 5,
+    NorthWest = 6,
+    NorthEast = 7,
+    None = 8,
+}
+
+/// Directions clockwise starting at north, for rotating
+const CLOCKWISE: [Direction; 8] = [
+    Direction::North,
+    Direction::NorthEast,
+    Direction::East,
+    Direction::SouthEast,
+    Direction::South,
+    Direction::SouthWest,
+    Direction::West,
+    Direction::NorthWest,
+];
+
+impl Direction {
+    /// Returns the direction of a step by the offset, None if it doesn't move on the floor
+    ///
+    /// Only the signs of x and y are used, so the direction of any offset points towards it.
+    pub fn from_offset(offset: Offset) -> Direction {
+        match (offset.x.signum(), offset.y.signum()) {
+            (0, -1) => Direction::North,
+            (1, 0) => Direction::East,
+            (0, 1) => Direction::South,
+            (-1, 0) => Direction::West,
+            (-1, 1) => Direction::SouthWest,
+            (1, 1) => Direction::SouthEast,
+            (-1, -1) => Direction::NorthWest,
+            (1, -1) => Direction::NorthEast,
+            _ => Direction::None,
+        }
+    }
+
+    pub fn is_diagonal(self) -> bool {
+        matches!(self, Direction::SouthWest | Direction::SouthEast | Direction::NorthWest | Direction::NorthEast)
+    }
+
+    /// Returns the opposite direction, None is its own opposite
+    pub fn opposite(self) -> Direction {
+        self.rotate(4)
+    }
+
+    /// Rotates clockwise in steps of 45 degrees, negative steps rotate counter clockwise
+    pub fn rotate(self, steps: i32) -> Direction {
+        match CLOCKWISE.iter().position(|&direction| direction == self) {
+            Some(index) => CLOCKWISE[(index as i32 + steps).rem_euclid(8) as usize],
+            None => Direction::None,
+        }
+    }
+
+    /// Rotates 90 degrees clockwise
+    pub fn rotate_cw(self) -> Direction {
+        self.rotate(2)
+    }
+
+    /// Rotates 90 degrees counter clockwise
+    pub fn rotate_ccw(self) -> Direction {
+        self.rotate(-2)
+    }
+}
+
+/// Error of converting an invalid wire value to a Direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidDirection(pub u8);
+
+impl fmt::Display for InvalidDirection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid direction {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidDirection {}
+
+impl TryFrom<u8> for Direction {
+    type Error = InvalidDirection;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Direction::North,
+            1 => Direction::East,
+            2 => Direction::South,
+            3 => Direction::West,
+            4 => Direction::SouthWest,
+            5 => Direction::SouthEast,
+            6 => Direction::NorthWest,
+            7 => Direction::NorthEast,
+            8 => Direction::None,
+            _ => return Err(InvalidDirection(value)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wire_values() {
+        for value in 0..=8 {
+            assert_eq!(Direction::try_from(value).unwrap() as u8, value);
+        }
+        assert_eq!(Direction::try_from(9), Err(InvalidDirection(9)));
+    }
+
+    #[test]
+    fn test_from_offset() {
+        for &direction in CLOCKWISE.iter() {
+            assert_eq!(Direction::from_offset(direction.into()), direction);
+            assert_eq!(Direction::from_offset(Offset::from(direction) + direction.into()), direction);
+        }
+        assert_eq!(Direction::from_offset(Offset::new(0, 0, 1)), Direction::None);
+        assert_eq!(Direction::from_offset(Offset::new(5, -20, 0)), Direction::NorthEast);
+    }
+
+    #[test]
+    fn test_rotate() {
+        assert_eq!(Direction::North.opposite(), Direction::South);
+        assert_eq!(Direction::SouthWest.opposite(), Direction::NorthEast);
+        assert_eq!(Direction::None.opposite(), Direction::None);
+        assert_eq!(Direction::West.rotate_cw(), Direction::North);
+        assert_eq!(Direction::North.rotate_ccw(), Direction::West);
+        assert_eq!(Direction::NorthWest.rotate(1), Direction::North);
+        assert_eq!(Direction::East.rotate(-9), Direction::NorthEast);
+
+        for &direction in CLOCKWISE.iter() {
+            assert_eq!(direction.opposite().opposite(), direction);
+            assert_eq!(Offset::from(direction.opposite()), -Offset::from(direction));
+            assert_eq!(direction.rotate(1).is_diagonal(), !direction.is_diagonal());
+        }
+    }
 }
@@ -1,5 +1,9 @@
+pub mod area;
+pub mod chunk;
 pub mod position;
 pub mod constants;
 
+pub use area::*;
+pub use chunk::*;
 pub use constants::*;
 pub use position::*;
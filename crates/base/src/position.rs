@@ -1,48 +1,282 @@
+use std::ops::{Add, Neg, Sub};
+
 use crate::constants::Direction;
 
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Position {
     pub x: u16,
     pub y: u16,
     pub z: u8,
 }
 
+/// Signed difference between two positions
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Offset {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+impl Offset {
+    pub const fn new(x: i32, y: i32, z: i32) -> Self {
+        Self { x, y, z }
+    }
+}
+
+impl Add for Offset {
+    type Output = Self;
+
+    fn add(self, other: Offset) -> Self::Output {
+        Offset::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl Neg for Offset {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Offset::new(-self.x, -self.y, -self.z)
+    }
+}
+
+impl From<Direction> for Offset {
+    fn from(direction: Direction) -> Self {
+        match direction {
+            Direction::North => Offset::new(0, -1, 0),
+            Direction::East  => Offset::new(1, 0, 0),
+            Direction::South => Offset::new(0, 1, 0),
+            Direction::West  => Offset::new(-1, 0, 0),
+            Direction::SouthWest => Offset::new(-1, 1, 0),
+            Direction::SouthEast => Offset::new(1, 1, 0),
+            Direction::NorthWest => Offset::new(-1, -1, 0),
+            Direction::NorthEast => Offset::new(1, -1, 0),
+            Direction::None => Offset::new(0, 0, 0),
+        }
+    }
+}
+
 impl Position {
+    pub const fn new(x: u16, y: u16, z: u8) -> Self {
+        Self { x, y, z }
+    }
+
+    /// Returns the offset from self to other
+    pub fn offset_to(&self, other: &Position) -> Offset {
+        *other - *self
+    }
+
+    /// Returns the direction of a step from self towards other
+    pub fn direction_to(&self, other: &Position) -> Direction {
+        Direction::from_offset(self.offset_to(other))
+    }
+
+    /// Adds the offset, None if the result is outside of the coordinate range
+    pub fn checked_add(self, offset: Offset) -> Option<Position> {
+        use std::convert::TryFrom;
+        Some(Position {
+            x: u16::try_from(self.x as i32 + offset.x).ok()?,
+            y: u16::try_from(self.y as i32 + offset.y).ok()?,
+            z: u8::try_from(self.z as i32 + offset.z).ok()?,
+        })
+    }
+
+    /// Adds the offset, clamping each coordinate to its range
+    pub fn saturating_add(self, offset: Offset) -> Position {
+        Position {
+            x: (self.x as i32).saturating_add(offset.x).clamp(0, u16::MAX as i32) as u16,
+            y: (self.y as i32).saturating_add(offset.y).clamp(0, u16::MAX as i32) as u16,
+            z: (self.z as i32).saturating_add(offset.z).clamp(0, u8::MAX as i32) as u8,
+        }
+    }
+
+    /// Returns true if other is within range, on a floor seen from self
+    ///
+    /// The surface doesn't see underground, underground sees 2 floors up and down.
+    /// Floors are offset diagonally, one tile per floor.
     pub fn can_see(&self, other: &Position, range_x: u16, range_y: u16) -> bool {
         if (self.z <= 7 && other.z > 7)
-        || (self.z >= 8 && self.distance_y(other) > 2) {
+        || (self.z >= 8 && self.distance_z(other) > 2) {
             return false;
         }
 
-        let offset_z = self.y - other.y;
-        (other.x >= self.x - range_x + offset_z)
-        && (other.x <= self.x + range_x + offset_z)
-        && (other.y >= self.y - range_y + offset_z)
-        && (other.y <= self.y + range_y + offset_z)
+        let offset = self.offset_to(other);
+        (offset.x + offset.z).abs() <= range_x as i32 && (offset.y + offset.z).abs() <= range_y as i32
     }
 
-    pub fn distance_x(&self, other: &Position) -> u8 {
-        i8::abs(self.x as i8 - other.x as i8) as u8
+    pub fn distance_x(&self, other: &Position) -> u16 {
+        self.x.abs_diff(other.x)
     }
 
-    pub fn distance_y(&self, other: &Position) -> u8 {
-        i8::abs(self.y as i8 - other.y as i8) as u8
+    pub fn distance_y(&self, other: &Position) -> u16 {
+        self.y.abs_diff(other.y)
     }
 
     pub fn distance_z(&self, other: &Position) -> u8 {
-        i8::abs(self.z as i8 - other.z as i8) as u8
+        self.z.abs_diff(other.z)
+    }
+
+    /// Returns the number of steps between the positions when walking diagonally, floors are ignored
+    pub fn chebyshev_distance(&self, other: &Position) -> u16 {
+        self.distance_x(other).max(self.distance_y(other))
+    }
+
+    /// Returns the number of steps between the positions when walking straight, floors are ignored
+    pub fn manhattan_distance(&self, other: &Position) -> u32 {
+        self.distance_x(other) as u32 + self.distance_y(other) as u32
+    }
+
+    /// Returns the straight line distance between the positions, floors are ignored
+    pub fn euclidean_distance(&self, other: &Position) -> f64 {
+        (self.distance_x(other) as f64).hypot(self.distance_y(other) as f64)
+    }
+}
+
+impl Sub for Position {
+    type Output = Offset;
+
+    fn sub(self, other: Position) -> Self::Output {
+        Offset::new(
+            self.x as i32 - other.x as i32,
+            self.y as i32 - other.y as i32,
+            self.z as i32 - other.z as i32,
+        )
     }
 }
 
-impl std::ops::Add<Direction> for Position {
+/// Moves one step in the direction, staying at the edge of the coordinate range
+impl Add<Direction> for Position {
     type Output = Self;
 
     fn add(self, direction: Direction) -> Self::Output {
-        match direction {
-            Direction::North => Position { y: self.y - 1, ..self },
-            Direction::East  => Position { x: self.x + 1, ..self },
-            Direction::South => Position { y: self.y + 1, ..self },
-            Direction::West  => Position { x: self.x - 1, ..self },
+        self.saturating_add(direction.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::convert::TryFrom;
+
+    fn position() -> impl Strategy<Value = Position> {
+        (any::<u16>(), any::<u16>(), 0u8..16).prop_map(|(x, y, z)| Position::new(x, y, z))
+    }
+
+    fn offset() -> impl Strategy<Value = Offset> {
+        (-0x2_0000..0x2_0000, -0x2_0000..0x2_0000, -32..32).prop_map(|(x, y, z)| Offset::new(x, y, z))
+    }
+
+    #[test]
+    fn test_edges() {
+        let origin = Position::new(0, 0, 0);
+        assert_eq!(origin + Direction::North, origin);
+        assert_eq!(origin + Direction::West, origin);
+        assert_eq!(origin + Direction::South, Position::new(0, 1, 0));
+        assert_eq!(Position::new(u16::MAX, 5, 7) + Direction::East, Position::new(u16::MAX, 5, 7));
+
+        assert_eq!(origin.checked_add(Offset::new(-1, 0, 0)), None);
+        assert_eq!(origin.checked_add(Offset::new(0, 0, 256)), None);
+        assert_eq!(origin.saturating_add(Offset::new(-5, 70000, 3)), Position::new(0, u16::MAX, 3));
+
+        let far = Position::new(1000, 200, 7);
+        assert_eq!(origin.distance_x(&far), 1000);
+        assert_eq!(origin.chebyshev_distance(&far), 1000);
+        assert_eq!(origin.manhattan_distance(&far), 1200);
+        assert_eq!(Position::new(3, 4, 7).euclidean_distance(&origin), 5.0);
+    }
+
+    #[test]
+    fn test_can_see() {
+        let pos = Position::new(100, 100, 7);
+        assert!(pos.can_see(&Position::new(108, 106, 7), 8, 6));
+        assert!(!pos.can_see(&Position::new(109, 100, 7), 8, 6));
+        // Floors above are offset towards the bottom right
+        assert!(pos.can_see(&Position::new(109, 107, 6), 8, 6));
+        assert!(!pos.can_see(&Position::new(92, 94, 6), 8, 6));
+        assert!(!pos.can_see(&Position::new(100, 100, 8), 8, 6));
+
+        let cave = Position::new(100, 100, 10);
+        assert!(cave.can_see(&Position::new(98, 98, 12), 8, 6));
+        assert!(!cave.can_see(&Position::new(100, 100, 13), 8, 6));
+
+        // Near the edges
+        let corner = Position::new(0, 0, 7);
+        assert!(corner.can_see(&Position::new(8, 6, 7), 8, 6));
+        assert!(Position::new(5, 5, 7).can_see(&corner, 8, 6));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_offset_round_trip(a in position(), b in position()) {
+            prop_assert_eq!(a.checked_add(a.offset_to(&b)), Some(b));
+            prop_assert_eq!(a.checked_add(b - a), Some(b));
+            prop_assert_eq!(b - a, -(a - b));
+        }
+
+        #[test]
+        fn prop_checked_add(pos in position(), offset in offset()) {
+            let x = pos.x as i32 + offset.x;
+            let y = pos.y as i32 + offset.y;
+            let z = pos.z as i32 + offset.z;
+            let in_range = (0..=u16::MAX as i32).contains(&x) && (0..=u16::MAX as i32).contains(&y) && (0..=u8::MAX as i32).contains(&z);
+            match pos.checked_add(offset) {
+                Some(result) => {
+                    prop_assert!(in_range);
+                    prop_assert_eq!(result - pos, offset);
+                    prop_assert_eq!(pos.saturating_add(offset), result);
+                },
+                None => prop_assert!(!in_range),
+            }
+        }
+
+        #[test]
+        fn prop_saturating_add(pos in position(), offset in offset()) {
+            let result = pos.saturating_add(offset);
+            prop_assert_eq!(result.x as i32, (pos.x as i32 + offset.x).clamp(0, u16::MAX as i32));
+            prop_assert_eq!(result.y as i32, (pos.y as i32 + offset.y).clamp(0, u16::MAX as i32));
+        }
+
+        #[test]
+        fn prop_distances(a in position(), b in position()) {
+            let (dx, dy) = ((a.x as i32 - b.x as i32).abs(), (a.y as i32 - b.y as i32).abs());
+            prop_assert_eq!(a.distance_x(&b) as i32, dx);
+            prop_assert_eq!(a.distance_y(&b) as i32, dy);
+            prop_assert_eq!(a.distance_z(&b) as i32, (a.z as i32 - b.z as i32).abs());
+
+            prop_assert_eq!(a.chebyshev_distance(&b), b.chebyshev_distance(&a));
+            prop_assert_eq!(a.chebyshev_distance(&b) as i32, dx.max(dy));
+            prop_assert_eq!(a.manhattan_distance(&b) as i32, dx + dy);
+
+            let euclidean = a.euclidean_distance(&b);
+            prop_assert!(euclidean >= a.chebyshev_distance(&b) as f64);
+            prop_assert!(euclidean <= a.manhattan_distance(&b) as f64);
+        }
+
+        #[test]
+        fn prop_distance_triangle(a in position(), b in position(), c in position()) {
+            prop_assert!(a.chebyshev_distance(&c) as u32 <= a.chebyshev_distance(&b) as u32 + b.chebyshev_distance(&c) as u32);
+            prop_assert!(a.manhattan_distance(&c) <= a.manhattan_distance(&b) + b.manhattan_distance(&c));
+        }
+
+        #[test]
+        fn prop_can_see(pos in position(), offset in offset()) {
+            let other = pos.saturating_add(Offset::new(offset.x % 20, offset.y % 20, 0));
+            prop_assert!(pos.can_see(&pos, 8, 6));
+            prop_assert_eq!(pos.can_see(&other, 8, 6), pos.distance_x(&other) <= 8 && pos.distance_y(&other) <= 6);
+        }
+
+        #[test]
+        fn prop_direction_step(pos in position()) {
+            for value in 0..=8 {
+                let direction = Direction::try_from(value).unwrap();
+                let next = pos + direction;
+                prop_assert!(pos.chebyshev_distance(&next) <= 1);
+                if let Some(stepped) = pos.checked_add(direction.into()) {
+                    prop_assert_eq!(next, stepped);
+                    prop_assert_eq!(next.checked_add(-Offset::from(direction)), Some(pos));
+                    prop_assert_eq!(pos.direction_to(&next), direction);
+                }
+            }
         }
     }
 }
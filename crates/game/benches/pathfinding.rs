@@ -0,0 +1,61 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use base::Position;
+use rustia_game::{
+    map::{Map, Tile},
+    pathfinding::{PathTarget, Pathfinder, is_walkable},
+    test_util::{self, GRASS, WALL, item_types},
+};
+
+const SIZE: u16 = 512;
+const WALL_SPACING: u16 = 16;
+
+/// A SIZE x SIZE grass floor split by walls every WALL_SPACING columns, each with a gap at a random row
+fn maze() -> Map {
+    let mut map = Map::new(SIZE, SIZE);
+    let mut seed = 0x2545_f491_u32;
+    let mut gap = 0;
+
+    for x in 0..SIZE {
+        let is_wall = x % WALL_SPACING == WALL_SPACING - 1;
+        if is_wall {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            gap = (seed % SIZE as u32) as u16;
+        }
+
+        for y in 0..SIZE {
+            let items: &[u16] = if is_wall && !(gap..gap + 3).contains(&y) { &[GRASS, WALL] } else { &[GRASS] };
+            test_util::set_tile(&mut map, Position::new(x, y, 7), items);
+        }
+    }
+    map
+}
+
+fn bench_pathfinding(c: &mut Criterion) {
+    let item_types = item_types();
+    let map = maze();
+    let walkable = |_, tile: &Tile, things: &_| is_walkable(tile, things, &item_types);
+
+    let pathfinder = Pathfinder::new();
+    c.bench_function("path 16 steps", |b| b.iter(|| {
+        pathfinder.find(&map, Position::new(20, 20, 7), black_box(Position::new(28, 28, 7)), PathTarget::Exact, walkable)
+    }));
+    c.bench_function("path 16 steps adjacent", |b| b.iter(|| {
+        pathfinder.find(&map, Position::new(20, 20, 7), black_box(Position::new(28, 28, 7)), PathTarget::Adjacent, walkable)
+    }));
+
+    // Outside of the map, gives up after the max nodes
+    c.bench_function("path unreachable", |b| b.iter(|| {
+        pathfinder.find(&map, Position::new(20, 20, 7), black_box(Position::new(SIZE + 20, 20, 7)), PathTarget::Exact, walkable)
+    }));
+
+    let pathfinder = Pathfinder::new().with_max_nodes(usize::MAX);
+    c.bench_function("path through the maze", |b| b.iter(|| {
+        pathfinder.find(&map, Position::new(0, SIZE / 2, 7), black_box(Position::new(SIZE - 1, SIZE / 2, 7)), PathTarget::Exact, walkable)
+    }));
+}
+
+criterion_group!(benches, bench_pathfinding);
+criterion_main!(benches);
@@ -0,0 +1,57 @@
+use base::{Direction, Position};
+
+/// Looks of a creature, either a look type with colors and addons or an item
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Outfit {
+    pub look_type: u16,
+    pub head: u8,
+    pub body: u8,
+    pub legs: u8,
+    pub feet: u8,
+    pub addons: u8,
+    /// Server id of the item the creature looks like, used when look_type is 0
+    pub look_item: u16,
+    pub mount: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Creature {
+    /// Creature id, as known by the client
+    pub id: u32,
+    pub name: String,
+    /// Position of the tile the creature is on, set by the map
+    pub position: Position,
+    pub health: u32,
+    pub max_health: u32,
+    pub direction: Direction,
+    pub outfit: Outfit,
+    pub speed: u16,
+    pub light_level: u8,
+    pub light_color: u8,
+}
+
+impl Creature {
+    /// Creates a creature with full health, looking south
+    pub fn new(id: u32, name: String) -> Self {
+        Self {
+            id,
+            name,
+            position: Position::default(),
+            health: 100,
+            max_health: 100,
+            direction: Direction::South,
+            outfit: Outfit::default(),
+            speed: 220,
+            light_level: 0,
+            light_color: 0,
+        }
+    }
+
+    /// Returns the health in percent, as shown to the client
+    pub fn health_percent(&self) -> u8 {
+        if self.max_health == 0 {
+            return 0;
+        }
+        (self.health.min(self.max_health) as u64 * 100 / self.max_health as u64) as u8
+    }
+}
@@ -0,0 +1,384 @@
+use base::Position;
+use protocol::packet::GameServerPacket;
+use protocol::packet::game::{
+    Creature as ClientCreature, CreatureKnown, FloorChangeDown, FloorChangeUp, FullWorld, Item as ClientItem,
+    LightInfo, Outfit as ClientOutfit, Thing as ClientThing, Tile as ClientTile, VIEWPORT_HEIGHT, VIEWPORT_WIDTH,
+    WorldData, WorldRowEast, WorldRowNorth, WorldRowSouth, WorldRowWest,
+};
+
+use crate::{
+    creature::Creature,
+    item::{Item, ItemTypes},
+    map::Map,
+    thing::{Thing, ThingId},
+};
+
+/// Offset of the player from the top left corner of the viewport
+const VIEWPORT_OFFSET_X: i32 = 8;
+const VIEWPORT_OFFSET_Y: i32 = 6;
+
+/// Floors sent in a map description, above ground sees all floors down to sea level, underground 2 floors up and down
+///
+/// The floors are in the order they are described, from the top for the surface and from the bottom underground.
+pub fn description_floors(z: u8) -> Vec<u8> {
+    if z > 7 {
+        (z - 2..=(z + 2).min(15)).collect()
+    } else {
+        (0..=7).rev().collect()
+    }
+}
+
+/// Returns true if pos is within the viewport of a player at player, on a floor that is described
+///
+/// The viewport reaches one tile further east and south than west and north.
+pub fn in_viewport(player: Position, pos: Position) -> bool {
+    if (player.z <= 7 && pos.z > 7) || (player.z >= 8 && player.distance_z(&pos) > 2) {
+        return false;
+    }
+
+    let offset = player.offset_to(&pos);
+    let (x, y) = (offset.x + offset.z, offset.y + offset.z);
+    (-VIEWPORT_OFFSET_X..VIEWPORT_WIDTH as i32 - VIEWPORT_OFFSET_X).contains(&x)
+        && (-VIEWPORT_OFFSET_Y..VIEWPORT_HEIGHT as i32 - VIEWPORT_OFFSET_Y).contains(&y)
+}
+
+/// Builds the map descriptions sent to a client, the viewport and the rows entering it
+pub struct MapDescriber<'a> {
+    map: &'a Map,
+    item_types: &'a ItemTypes,
+}
+
+impl<'a> MapDescriber<'a> {
+    pub fn new(map: &'a Map, item_types: &'a ItemTypes) -> Self {
+        Self { map, item_types }
+    }
+
+    /// Describes a thing of the map, None if it was removed
+    pub fn thing(&self, id: ThingId) -> Option<ClientThing> {
+        self.map.things().get(id).map(|thing| client_thing(self.item_types, thing))
+    }
+
+    /// Describes the whole viewport of a player at pos
+    pub fn full_world(&self, pos: Position) -> FullWorld {
+        FullWorld {
+            player_position: pos,
+            world_chunk: self.viewport_area(pos, 0, 0, VIEWPORT_WIDTH, VIEWPORT_HEIGHT),
+        }
+    }
+
+    /// Describes the row entering the viewport when the player moved north to pos
+    pub fn row_north(&self, pos: Position) -> WorldRowNorth {
+        WorldRowNorth { world_chunk: self.viewport_area(pos, 0, 0, VIEWPORT_WIDTH, 1) }
+    }
+
+    /// Describes the column entering the viewport when the player moved east to pos
+    pub fn row_east(&self, pos: Position) -> WorldRowEast {
+        WorldRowEast { world_chunk: self.viewport_area(pos, VIEWPORT_WIDTH as i32 - 1, 0, 1, VIEWPORT_HEIGHT) }
+    }
+
+    /// Describes the row entering the viewport when the player moved south to pos
+    pub fn row_south(&self, pos: Position) -> WorldRowSouth {
+        WorldRowSouth { world_chunk: self.viewport_area(pos, 0, VIEWPORT_HEIGHT as i32 - 1, VIEWPORT_WIDTH, 1) }
+    }
+
+    /// Describes the column entering the viewport when the player moved west to pos
+    pub fn row_west(&self, pos: Position) -> WorldRowWest {
+        WorldRowWest { world_chunk: self.viewport_area(pos, 0, 0, 1, VIEWPORT_HEIGHT) }
+    }
+
+    /// Describes the floors coming into sight when the player moves from old to new, e.g on stairs
+    ///
+    /// The packets are sent after the MoveCreature. The floors above are offset one tile towards the bottom right,
+    /// so rows on the new floor are sent as well to keep the client in sync. Moving more than one floor is like
+    /// a teleport, the whole viewport is sent.
+    pub fn floor_change(&self, old: Position, new: Position) -> Vec<GameServerPacket> {
+        let old_floors = description_floors(old.z);
+        let new_floors = description_floors(new.z).into_iter().filter(|floor| !old_floors.contains(floor));
+        let (x, y) = (old.x as i32 - VIEWPORT_OFFSET_X, old.y as i32 - VIEWPORT_OFFSET_Y);
+        let (width, height) = (VIEWPORT_WIDTH, VIEWPORT_HEIGHT);
+
+        if new.z + 1 == old.z {
+            vec![
+                FloorChangeUp { world_chunk: self.floors_area(x, y, old.z, new_floors, width, height) }.into(),
+                WorldRowWest { world_chunk: self.area(x, y + 1, new.z, 1, height) }.into(),
+                WorldRowNorth { world_chunk: self.area(x, y, new.z, width, 1) }.into(),
+            ]
+        } else if new.z == old.z + 1 {
+            vec![
+                FloorChangeDown { world_chunk: self.floors_area(x, y, old.z, new_floors, width, height) }.into(),
+                WorldRowEast { world_chunk: self.area(x + width as i32 - 1, y - 1, new.z, 1, height) }.into(),
+                WorldRowSouth { world_chunk: self.area(x, y + height as i32 - 1, new.z, width, 1) }.into(),
+            ]
+        } else if new.z != old.z {
+            vec![self.full_world(new).into()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Describes an area of the viewport of a player at pos, (x, y) is relative to the top left corner
+    fn viewport_area(&self, pos: Position, x: i32, y: i32, width: usize, height: usize) -> Vec<WorldData> {
+        let x = pos.x as i32 - VIEWPORT_OFFSET_X + x;
+        let y = pos.y as i32 - VIEWPORT_OFFSET_Y + y;
+        self.area(x, y, pos.z, width, height)
+    }
+
+    /// Describes an area of width x height tiles with the top left corner at (x, y), on all floors seen from floor z
+    ///
+    /// Floors are offset diagonally, one tile per floor away from z. Tiles are described column by column,
+    /// tiles outside of the map are empty.
+    pub fn area(&self, x: i32, y: i32, z: u8, width: usize, height: usize) -> Vec<WorldData> {
+        self.floors_area(x, y, z, description_floors(z), width, height)
+    }
+
+    /// Describes an area on the floors, offset diagonally like seen from floor z
+    fn floors_area(&self, x: i32, y: i32, z: u8, floors: impl IntoIterator<Item = u8>, width: usize, height: usize) -> Vec<WorldData> {
+        let view = self.map.view();
+        let mut world_chunk = Vec::new();
+        let mut empty = 0;
+
+        for floor in floors {
+            let offset = z as i32 - floor as i32;
+            for nx in 0..width as i32 {
+                for ny in 0..height as i32 {
+                    let (tile_x, tile_y) = (x + nx + offset, y + ny + offset);
+                    let mut tile = ClientTile::default();
+                    let mut count = 0;
+                    if (0..=u16::MAX as i32).contains(&tile_x) && (0..=u16::MAX as i32).contains(&tile_y) {
+                        let pos = Position { x: tile_x as u16, y: tile_y as u16, z: floor };
+                        if let Some(map_tile) = view.tile_at(pos) {
+                            let visible = map_tile.things_iter().filter_map(|id| view.things().get(*id)).take(tile.things.len());
+                            for thing in visible {
+                                tile.things[count] = Some(client_thing(self.item_types, thing));
+                                count += 1;
+                            }
+                        }
+                    }
+
+                    if count == 0 {
+                        empty += 1;
+                        continue;
+                    }
+
+                    if empty > 0 {
+                        world_chunk.push(WorldData::Empty(empty));
+                        empty = 0;
+                    }
+                    world_chunk.push(WorldData::Tile(tile));
+                }
+            }
+        }
+
+        if empty > 0 {
+            world_chunk.push(WorldData::Empty(empty));
+        }
+        world_chunk
+    }
+}
+
+/// Converts a map item to the item sent to the client, the server id is sent if its type is unknown
+pub fn client_item(item_types: &ItemTypes, item: &Item) -> ClientItem {
+    match item_types.get(item.id) {
+        Some(item_type) => ClientItem {
+            client_id: item_type.client_id,
+            stack_size: if item_type.is_stackable() { Some(item.count()) } else { None },
+            fluid: if item_type.is_fluid() { Some(item.count()) } else { None },
+            // Random animation phase
+            animation: if item_type.is_animated() { Some(0xFE) } else { None },
+        },
+        None => ClientItem { client_id: item.id, ..ClientItem::default() },
+    }
+}
+
+/// Converts a creature to the creature sent to the client, always as an unknown creature
+pub fn client_creature(item_types: &ItemTypes, creature: &Creature) -> ClientCreature {
+    let outfit = &creature.outfit;
+    ClientCreature {
+        id: creature.id,
+        known: CreatureKnown::No {
+            remove: 0,
+            creature_type: 0,
+            creature_name: creature.name.clone(),
+            guild_emblem: 0,
+        },
+        health: creature.health_percent(),
+        direction: creature.direction,
+        outfit: if outfit.look_type != 0 {
+            ClientOutfit::LookType {
+                look_type: outfit.look_type,
+                head: outfit.head,
+                body: outfit.body,
+                legs: outfit.legs,
+                feet: outfit.feet,
+                addons: outfit.addons,
+                mount: outfit.mount,
+            }
+        } else {
+            ClientOutfit::Item {
+                client_id: item_types.get(outfit.look_item).map_or(outfit.look_item, |item_type| item_type.client_id),
+                mount: outfit.mount,
+            }
+        },
+        light: LightInfo { light_level: creature.light_level, light_color: creature.light_color },
+        speed: creature.speed,
+        ..ClientCreature::default()
+    }
+}
+
+/// Converts a thing on a tile to the thing sent to the client
+pub fn client_thing(item_types: &ItemTypes, thing: &Thing) -> ClientThing {
+    match thing {
+        Thing::Item(item) => ClientThing::Item(client_item(item_types, item)),
+        Thing::Creature(creature) => ClientThing::Creature(Box::new(client_creature(item_types, creature))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{item::ItemType, map::Tile};
+    use protocol::packet::game::description_floors as floor_count;
+
+    fn ground_map(positions: &[Position]) -> Map {
+        let mut map = Map::new(200, 200);
+        for pos in positions {
+            let mut tile = Tile::default();
+            tile.push(map.add_item(Item::new(102)));
+            map.set_tile(*pos, tile);
+        }
+        map
+    }
+
+    fn item_types() -> ItemTypes {
+        let mut item_types = ItemTypes::new();
+        item_types.insert(ItemType { id: 102, client_id: 4526, ..ItemType::default() });
+        item_types
+    }
+
+    #[test]
+    fn test_description_floors() {
+        assert_eq!(description_floors(7), vec![7, 6, 5, 4, 3, 2, 1, 0]);
+        assert_eq!(description_floors(0), vec![7, 6, 5, 4, 3, 2, 1, 0]);
+        assert_eq!(description_floors(8), vec![6, 7, 8, 9, 10]);
+        assert_eq!(description_floors(14), vec![12, 13, 14, 15]);
+        for z in 0..16 {
+            assert_eq!(description_floors(z).len(), floor_count(z));
+        }
+    }
+
+    #[test]
+    fn test_full_world() {
+        let spawn = Position { x: 100, y: 100, z: 7 };
+        let map = ground_map(&[spawn, Position { x: 92, y: 94, z: 7 }]);
+        let item_types = item_types();
+
+        let world = MapDescriber::new(&map, &item_types).full_world(spawn);
+        assert_eq!(world.player_position, spawn);
+        assert_eq!(WorldData::tile_count(&world.world_chunk), 2016);
+
+        // The top left corner comes first, then the player tile at (8, 6)
+        let world_chunk = &world.world_chunk;
+        assert!(matches!(world_chunk[0], WorldData::Tile(_)));
+        assert!(matches!(world_chunk[1], WorldData::Empty(n) if n == 8 * VIEWPORT_HEIGHT + 6 - 1));
+        match &world_chunk[2] {
+            WorldData::Tile(tile) => assert!(matches!(tile.things[0], Some(ClientThing::Item(ClientItem { client_id: 4526, .. })))),
+            data => panic!("expected spawn tile, got {:?}", data),
+        }
+    }
+
+    #[test]
+    fn test_floor_offset() {
+        // One floor up is offset one tile towards the bottom right
+        let spawn = Position { x: 100, y: 100, z: 7 };
+        let map = ground_map(&[Position { x: 93, y: 95, z: 6 }]);
+        let item_types = item_types();
+
+        let world_chunk = MapDescriber::new(&map, &item_types).full_world(spawn).world_chunk;
+        assert!(matches!(world_chunk[0], WorldData::Empty(n) if n == VIEWPORT_WIDTH * VIEWPORT_HEIGHT));
+        assert!(matches!(world_chunk[1], WorldData::Tile(_)));
+
+        // Underground the floors below are seen, from the top
+        let cave = Position { x: 100, y: 100, z: 9 };
+        let map = ground_map(&[Position { x: 91, y: 93, z: 10 }]);
+        let world = MapDescriber::new(&map, &item_types).full_world(cave);
+        assert_eq!(WorldData::tile_count(&world.world_chunk), VIEWPORT_WIDTH * VIEWPORT_HEIGHT * 5);
+        assert!(matches!(world.world_chunk[0], WorldData::Empty(n) if n == VIEWPORT_WIDTH * VIEWPORT_HEIGHT * 3));
+        assert!(matches!(world.world_chunk[1], WorldData::Tile(_)));
+    }
+
+    #[test]
+    fn test_floor_change() {
+        let item_types = item_types();
+        let viewport = VIEWPORT_WIDTH * VIEWPORT_HEIGHT;
+
+        // Up to the surface, floors 5 to 0 come into sight, offset from the old floor
+        let cave = Position { x: 100, y: 100, z: 8 };
+        let map = ground_map(&[Position { x: 95, y: 97, z: 5 }]);
+        let describer = MapDescriber::new(&map, &item_types);
+        let packets = describer.floor_change(cave, Position { z: 7, ..cave });
+        match &packets[..] {
+            [GameServerPacket::FloorChangeUp(up), GameServerPacket::WorldRowWest(west), GameServerPacket::WorldRowNorth(north)] => {
+                assert_eq!(WorldData::tile_count(&up.world_chunk), viewport * 6);
+                assert!(matches!(up.world_chunk[..], [WorldData::Tile(_), WorldData::Empty(_)]));
+                assert_eq!(WorldData::tile_count(&west.world_chunk), VIEWPORT_HEIGHT * 8);
+                assert_eq!(WorldData::tile_count(&north.world_chunk), VIEWPORT_WIDTH * 8);
+            },
+            packets => panic!("unexpected packets {:?}", packets),
+        }
+
+        // Down from the surface, floors 8 to 10 come into sight
+        let surface = Position { x: 100, y: 100, z: 7 };
+        let map = ground_map(&[Position { x: 91, y: 93, z: 8 }]);
+        let describer = MapDescriber::new(&map, &item_types);
+        match &describer.floor_change(surface, Position { z: 8, ..surface })[..] {
+            [GameServerPacket::FloorChangeDown(down), GameServerPacket::WorldRowEast(east), GameServerPacket::WorldRowSouth(south)] => {
+                assert_eq!(WorldData::tile_count(&down.world_chunk), viewport * 3);
+                assert!(matches!(down.world_chunk[0], WorldData::Tile(_)));
+                assert_eq!(WorldData::tile_count(&east.world_chunk), VIEWPORT_HEIGHT * 5);
+                assert_eq!(WorldData::tile_count(&south.world_chunk), VIEWPORT_WIDTH * 5);
+            },
+            packets => panic!("unexpected packets {:?}", packets),
+        }
+
+        // On the surface nothing new comes into sight, deep down neither
+        match &describer.floor_change(Position { z: 6, ..surface }, surface)[..] {
+            [GameServerPacket::FloorChangeDown(down), _, _] => assert!(down.world_chunk.is_empty()),
+            packets => panic!("unexpected packets {:?}", packets),
+        }
+        match &describer.floor_change(Position { z: 14, ..surface }, Position { z: 15, ..surface })[..] {
+            [GameServerPacket::FloorChangeDown(down), _, _] => assert!(down.world_chunk.is_empty()),
+            packets => panic!("unexpected packets {:?}", packets),
+        }
+
+        assert!(matches!(&describer.floor_change(surface, Position { z: 5, ..surface })[..], [GameServerPacket::FullWorld(_)]));
+        assert!(describer.floor_change(surface, surface).is_empty());
+    }
+
+    #[test]
+    fn test_rows() {
+        let pos = Position { x: 100, y: 100, z: 7 };
+        let map = ground_map(&[
+            Position { x: 92, y: 94, z: 7 },
+            Position { x: 109, y: 107, z: 7 },
+        ]);
+        let item_types = item_types();
+        let describer = MapDescriber::new(&map, &item_types);
+
+        let row_tiles = |world_chunk: &[WorldData]| world_chunk.iter().filter(|data| matches!(data, WorldData::Tile(_))).count();
+
+        let north = describer.row_north(pos).world_chunk;
+        assert_eq!(WorldData::tile_count(&north), VIEWPORT_WIDTH * 8);
+        assert!(matches!(north[0], WorldData::Tile(_)));
+        let west = describer.row_west(pos).world_chunk;
+        assert_eq!(WorldData::tile_count(&west), VIEWPORT_HEIGHT * 8);
+        assert!(matches!(west[0], WorldData::Tile(_)));
+
+        // The bottom right corner is in the south and east rows
+        let south = describer.row_south(pos).world_chunk;
+        assert_eq!(WorldData::tile_count(&south), VIEWPORT_WIDTH * 8);
+        assert!(matches!(south[1], WorldData::Tile(_)) && row_tiles(&south) == 1);
+        let east = describer.row_east(pos).world_chunk;
+        assert_eq!(WorldData::tile_count(&east), VIEWPORT_HEIGHT * 8);
+        assert!(matches!(east[1], WorldData::Tile(_)) && row_tiles(&east) == 1);
+    }
+}
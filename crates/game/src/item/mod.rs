@@ -0,0 +1,91 @@
+use base::Position;
+
+mod otb;
+mod types;
+mod xml;
+
+pub use otb::{load_otb, read_otb};
+pub use types::{ItemFlags, ItemGroup, ItemType, ItemTypes};
+pub use xml::{load_xml, read_xml};
+
+/// An attribute of an item instance, as stored in OTBM
+///
+/// Attributes are kept in the order they were read, so they can be written back unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ItemAttribute {
+    Count(u8),
+    ActionId(u16),
+    UniqueId(u16),
+    Text(String),
+    Description(String),
+    TeleportDestination(Position),
+    DepotId(u16),
+    RuneCharges(u8),
+    HouseDoorId(u8),
+    Duration(i32),
+    DecayingState(u8),
+    WrittenDate(u32),
+    WrittenBy(String),
+    SleeperGuid(u32),
+    SleepStart(u32),
+    Charges(u16),
+    Name(String),
+    Article(String),
+    PluralName(String),
+    Weight(u32),
+    Attack(i32),
+    Defense(i32),
+    ExtraDefense(i32),
+    Armor(i32),
+    HitChance(i8),
+    ShootRange(u8),
+    DecayTo(i32),
+    WrapId(u16),
+    StoreItem(u8),
+    AttackSpeed(u32),
+    /// Custom attributes are kept in their serialized form
+    Custom(Vec<u8>),
+}
+
+/// An item instance
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Item {
+    /// Server id of the item type
+    pub id: u16,
+    pub attributes: Vec<ItemAttribute>,
+    /// Items inside this item if it's a container
+    pub contents: Vec<Item>,
+}
+
+impl Item {
+    /// Creates an item without attributes
+    pub fn new(id: u16) -> Self {
+        Self { id, ..Self::default() }
+    }
+
+    /// Returns the stack count or subtype of the item, 1 if it has none
+    pub fn count(&self) -> u8 {
+        self.attributes.iter()
+            .find_map(|attr| match attr {
+                ItemAttribute::Count(count) => Some(*count),
+                _ => None,
+            })
+            .unwrap_or(1)
+    }
+
+    /// Returns the action id of the item, if any
+    pub fn action_id(&self) -> Option<u16> {
+        self.attributes.iter().find_map(|attr| match attr {
+            ItemAttribute::ActionId(id) => Some(*id),
+            _ => None,
+        })
+    }
+
+    /// Returns the unique id of the item, if any
+    pub fn unique_id(&self) -> Option<u16> {
+        self.attributes.iter().find_map(|attr| match attr {
+            ItemAttribute::UniqueId(id) => Some(*id),
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use anyhow::Context;
+
+use crate::otbm::{NodeReader, PropReader};
+use super::{ItemFlags, ItemGroup, ItemType, ItemTypes};
+
+/// Attributes of the root node
+const ROOT_ATTR_VERSION: u8 = 0x01;
+
+/// Attributes of item nodes
+mod attr {
+    pub const SERVER_ID: u8 = 0x10;
+    pub const CLIENT_ID: u8 = 0x11;
+    pub const NAME: u8 = 0x12;
+    pub const SPEED: u8 = 0x14;
+    pub const MINIMAP_COLOR: u8 = 0x21;
+    pub const LIGHT2: u8 = 0x2A;
+    pub const TOP_ORDER: u8 = 0x2B;
+    pub const WARE_ID: u8 = 0x2D;
+}
+
+/// Loads the item types from an items.otb file
+pub fn load_otb<P: AsRef<Path>>(path: P) -> anyhow::Result<ItemTypes> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    read_otb(BufReader::new(file)).with_context(|| format!("failed to load {}", path.display()))
+}
+
+/// Reads the item types from a reader positioned at the start of an items.otb file
+///
+/// items.otb is a node tree like OTBM, with one node per item type whose type is the item group.
+pub fn read_otb<R: BufRead>(mut reader: R) -> anyhow::Result<ItemTypes> {
+    let mut identifier = [0; 4];
+    reader.read_exact(&mut identifier)?;
+
+    let mut nodes = NodeReader::new(reader);
+    let root = nodes.root()?;
+    let mut types = ItemTypes::new();
+
+    let mut props = PropReader::new(&root.props);
+    props.u32()?; // flags, unused
+    if !props.is_empty() && props.u8()? == ROOT_ATTR_VERSION {
+        let len = props.u16()? as usize;
+        let mut version = PropReader::new(props.bytes(len)?);
+        types.set_version(version.u32()?, version.u32()?);
+    }
+
+    while let Some(node) = nodes.next_child()? {
+        let item_type = read_item_type(node.kind, &node.props)
+            .with_context(|| format!("invalid item type at offset {}", node.offset))?;
+        types.insert(item_type);
+        nodes.skip_children()?;
+    }
+
+    Ok(types)
+}
+
+fn read_item_type(group: u8, data: &[u8]) -> anyhow::Result<ItemType> {
+    let mut item_type = ItemType {
+        group: ItemGroup::from_u8(group).with_context(|| format!("unknown item group {}", group))?,
+        ..ItemType::default()
+    };
+
+    let mut props = PropReader::new(data);
+    item_type.flags = ItemFlags(props.u32()?);
+
+    while !props.is_empty() {
+        let attr = props.u8()?;
+        let len = props.u16()? as usize;
+        let mut value = PropReader::new(props.bytes(len)?);
+        match attr {
+            attr::SERVER_ID => item_type.id = value.u16()?,
+            attr::CLIENT_ID => item_type.client_id = value.u16()?,
+            attr::NAME => item_type.name = value.remaining().iter().map(|&b| b as char).collect(),
+            attr::SPEED => item_type.speed = value.u16()?,
+            attr::MINIMAP_COLOR => item_type.minimap_color = value.u16()?,
+            attr::LIGHT2 => {
+                item_type.light_level = value.u16()?;
+                item_type.light_color = value.u16()?;
+            },
+            attr::TOP_ORDER => item_type.top_order = value.u8()?,
+            attr::WARE_ID => item_type.ware_id = value.u16()?,
+            // Sprite hashes and attributes of old versions are of no use to the server
+            _ => (),
+        }
+    }
+
+    Ok(item_type)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::otbm::NodeWriter;
+
+    fn item_node(nodes: &mut NodeWriter<Vec<u8>>, group: ItemGroup, flags: ItemFlags, id: u16, client_id: u16, extra: &[(u8, &[u8])]) {
+        nodes.start(group as u8).unwrap();
+        nodes.u32(flags.0).unwrap();
+        for (attr, value) in [(attr::SERVER_ID, &id.to_le_bytes()[..]), (attr::CLIENT_ID, &client_id.to_le_bytes()[..])].iter().chain(extra) {
+            nodes.u8(*attr).unwrap();
+            nodes.u16(value.len() as u16).unwrap();
+            nodes.bytes(value).unwrap();
+        }
+        nodes.end().unwrap();
+    }
+
+    /// A small items.otb with a ground, a wall, a border, a stackable and a container
+    pub(crate) fn test_otb() -> Vec<u8> {
+        let mut nodes = NodeWriter::new(vec![0; 4]);
+        nodes.start(0).unwrap();
+        nodes.u32(0).unwrap();
+        nodes.u8(ROOT_ATTR_VERSION).unwrap();
+        nodes.u16(140).unwrap();
+        nodes.u32(3).unwrap();
+        nodes.u32(57).unwrap();
+        nodes.u32(62).unwrap();
+        nodes.bytes(&[0; 128]).unwrap();
+
+        item_node(&mut nodes, ItemGroup::Ground, ItemFlags::default(), 102, 102, &[(attr::SPEED, &150u16.to_le_bytes())]);
+        item_node(&mut nodes, ItemGroup::None, ItemFlags(ItemFlags::BLOCK_SOLID.0 | ItemFlags::BLOCK_PROJECTILE.0), 1026, 1026, &[]);
+        item_node(&mut nodes, ItemGroup::None, ItemFlags::ALWAYS_ON_TOP, 4526, 4526, &[(attr::TOP_ORDER, &[1])]);
+        item_node(&mut nodes, ItemGroup::None, ItemFlags(ItemFlags::STACKABLE.0 | ItemFlags::PICKUPABLE.0 | ItemFlags::MOVEABLE.0), 2148, 3031, &[]);
+        item_node(&mut nodes, ItemGroup::Container, ItemFlags(ItemFlags::PICKUPABLE.0 | ItemFlags::MOVEABLE.0), 1988, 2854, &[(attr::LIGHT2, &[2, 0, 215, 0])]);
+        nodes.end().unwrap();
+        nodes.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_read_otb() {
+        let types = read_otb(&test_otb()[..]).unwrap();
+        assert_eq!(types.len(), 5);
+        assert_eq!(types.version(), (3, 57));
+
+        let ground = types.get(102).unwrap();
+        assert!(ground.is_ground());
+        assert_eq!(ground.speed, 150);
+
+        assert!(types.get(1026).unwrap().is_blocking());
+        assert!(types.get(4526).unwrap().is_always_on_top());
+        assert_eq!(types.get(4526).unwrap().top_order, 1);
+
+        let gold = types.by_client_id(3031).unwrap();
+        assert_eq!(gold.id, 2148);
+        assert!(gold.is_stackable() && gold.is_pickupable());
+
+        let backpack = types.get(1988).unwrap();
+        assert!(backpack.is_container() && !backpack.is_stackable());
+        assert_eq!((backpack.light_level, backpack.light_color), (2, 215));
+
+        assert!(types.get(100).is_none());
+    }
+}
@@ -0,0 +1,251 @@
+use std::path::Path;
+
+use ahash::AHashMap;
+
+use super::{otb, xml};
+
+/// Flags of an item type, as stored in items.otb
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ItemFlags(pub u32);
+
+impl ItemFlags {
+    pub const BLOCK_SOLID: ItemFlags = ItemFlags(1 << 0);
+    pub const BLOCK_PROJECTILE: ItemFlags = ItemFlags(1 << 1);
+    pub const BLOCK_PATHFIND: ItemFlags = ItemFlags(1 << 2);
+    pub const HAS_HEIGHT: ItemFlags = ItemFlags(1 << 3);
+    pub const USEABLE: ItemFlags = ItemFlags(1 << 4);
+    pub const PICKUPABLE: ItemFlags = ItemFlags(1 << 5);
+    pub const MOVEABLE: ItemFlags = ItemFlags(1 << 6);
+    pub const STACKABLE: ItemFlags = ItemFlags(1 << 7);
+    pub const FLOOR_CHANGE_DOWN: ItemFlags = ItemFlags(1 << 8);
+    pub const FLOOR_CHANGE_NORTH: ItemFlags = ItemFlags(1 << 9);
+    pub const FLOOR_CHANGE_EAST: ItemFlags = ItemFlags(1 << 10);
+    pub const FLOOR_CHANGE_SOUTH: ItemFlags = ItemFlags(1 << 11);
+    pub const FLOOR_CHANGE_WEST: ItemFlags = ItemFlags(1 << 12);
+    pub const ALWAYS_ON_TOP: ItemFlags = ItemFlags(1 << 13);
+    pub const READABLE: ItemFlags = ItemFlags(1 << 14);
+    pub const ROTATABLE: ItemFlags = ItemFlags(1 << 15);
+    pub const HANGABLE: ItemFlags = ItemFlags(1 << 16);
+    pub const VERTICAL: ItemFlags = ItemFlags(1 << 17);
+    pub const HORIZONTAL: ItemFlags = ItemFlags(1 << 18);
+    pub const CANNOT_DECAY: ItemFlags = ItemFlags(1 << 19);
+    pub const ALLOW_DIST_READ: ItemFlags = ItemFlags(1 << 20);
+    pub const CLIENT_CHARGES: ItemFlags = ItemFlags(1 << 22);
+    pub const LOOK_THROUGH: ItemFlags = ItemFlags(1 << 23);
+    pub const ANIMATION: ItemFlags = ItemFlags(1 << 24);
+    pub const FULL_TILE: ItemFlags = ItemFlags(1 << 25);
+    pub const FORCE_USE: ItemFlags = ItemFlags(1 << 26);
+
+    /// Returns true if all flags of other are set
+    pub fn contains(self, other: ItemFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// Group of an item type, the type of the node in items.otb
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ItemGroup {
+    #[default]
+    None,
+    Ground,
+    Container,
+    Weapon,
+    Ammunition,
+    Armor,
+    Charges,
+    Teleport,
+    MagicField,
+    Writeable,
+    Key,
+    Splash,
+    Fluid,
+    Door,
+    Deprecated,
+    Podium,
+}
+
+impl ItemGroup {
+    pub fn from_u8(group: u8) -> Option<Self> {
+        use ItemGroup::*;
+        Some(match group {
+            0 => None,
+            1 => Ground,
+            2 => Container,
+            3 => Weapon,
+            4 => Ammunition,
+            5 => Armor,
+            6 => Charges,
+            7 => Teleport,
+            8 => MagicField,
+            9 => Writeable,
+            10 => Key,
+            11 => Splash,
+            12 => Fluid,
+            13 => Door,
+            14 => Deprecated,
+            15 => Podium,
+            _ => return Option::None,
+        })
+    }
+}
+
+/// Properties shared by all items of a type
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ItemType {
+    /// Server id, used in maps and by the server
+    pub id: u16,
+    /// Client id, used in packets
+    pub client_id: u16,
+    pub group: ItemGroup,
+    pub flags: ItemFlags,
+    /// Order among the always on top items, lower is drawn on top
+    pub top_order: u8,
+    /// Walking speed of ground items
+    pub speed: u16,
+    pub light_level: u16,
+    pub light_color: u16,
+    pub minimap_color: u16,
+    pub ware_id: u16,
+
+    pub name: String,
+    pub article: String,
+    pub plural_name: String,
+    pub description: String,
+    /// Weight in hundredths of an oz
+    pub weight: u32,
+    pub decay_to: Option<u16>,
+    /// Seconds before the item decays
+    pub duration: Option<u32>,
+    pub container_size: Option<u8>,
+    /// Other attributes from items.xml, keys are lowercase
+    pub attributes: AHashMap<String, String>,
+}
+
+impl ItemType {
+    pub fn is_ground(&self) -> bool {
+        self.group == ItemGroup::Ground
+    }
+
+    pub fn is_container(&self) -> bool {
+        self.group == ItemGroup::Container
+    }
+
+    /// Returns true if the count of items of this type is their fluid type
+    pub fn is_fluid(&self) -> bool {
+        self.group == ItemGroup::Splash || self.group == ItemGroup::Fluid
+    }
+
+    pub fn is_blocking(&self) -> bool {
+        self.flags.contains(ItemFlags::BLOCK_SOLID)
+    }
+
+    /// Returns true if items of this type stop thrown items, distance attacks and the sight of spells
+    pub fn is_projectile_blocking(&self) -> bool {
+        self.flags.contains(ItemFlags::BLOCK_PROJECTILE)
+    }
+
+    /// Returns true if creatures don't walk over items of this type when finding paths, e.g fields
+    pub fn is_pathfind_blocking(&self) -> bool {
+        self.flags.contains(ItemFlags::BLOCK_PATHFIND)
+    }
+
+    pub fn is_stackable(&self) -> bool {
+        self.flags.contains(ItemFlags::STACKABLE)
+    }
+
+    pub fn is_always_on_top(&self) -> bool {
+        self.flags.contains(ItemFlags::ALWAYS_ON_TOP)
+    }
+
+    pub fn is_pickupable(&self) -> bool {
+        self.flags.contains(ItemFlags::PICKUPABLE)
+    }
+
+    pub fn is_moveable(&self) -> bool {
+        self.flags.contains(ItemFlags::MOVEABLE)
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.flags.contains(ItemFlags::ANIMATION)
+    }
+}
+
+/// Registry of all item types, by server id and client id
+#[derive(Debug, Default)]
+pub struct ItemTypes {
+    types: Vec<Option<ItemType>>,
+    client_ids: AHashMap<u16, u16>,
+    len: usize,
+    major_version: u32,
+    minor_version: u32,
+}
+
+impl ItemTypes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the item types from items.otb and their names and attributes from items.xml
+    pub fn load<P: AsRef<Path>, Q: AsRef<Path>>(otb_path: P, xml_path: Q) -> anyhow::Result<Self> {
+        let mut types = otb::load_otb(otb_path)?;
+        xml::load_xml(&mut types, xml_path)?;
+        Ok(types)
+    }
+
+    /// Adds an item type, replacing any type with the same server id
+    ///
+    /// When several types share a client id, the first one is found by client id.
+    pub fn insert(&mut self, item_type: ItemType) {
+        let id = item_type.id as usize;
+        if self.types.len() <= id {
+            self.types.resize(id + 1, None);
+        }
+
+        match &self.types[id] {
+            Some(old) if self.client_ids.get(&old.client_id) == Some(&item_type.id) => {
+                self.client_ids.remove(&old.client_id);
+            },
+            Some(_) => (),
+            None => self.len += 1,
+        }
+        self.client_ids.entry(item_type.client_id).or_insert(item_type.id);
+        self.types[id] = Some(item_type);
+    }
+
+    /// Returns the item type with the server id
+    pub fn get(&self, id: u16) -> Option<&ItemType> {
+        self.types.get(id as usize)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, id: u16) -> Option<&mut ItemType> {
+        self.types.get_mut(id as usize)?.as_mut()
+    }
+
+    /// Returns the item type with the client id
+    pub fn by_client_id(&self, client_id: u16) -> Option<&ItemType> {
+        self.get(*self.client_ids.get(&client_id)?)
+    }
+
+    /// Returns an iterator of all item types, ordered by server id
+    pub fn iter(&self) -> impl Iterator<Item = &ItemType> {
+        self.types.iter().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the major and minor version of the items.otb, maps are made for a specific version
+    pub fn version(&self) -> (u32, u32) {
+        (self.major_version, self.minor_version)
+    }
+
+    pub fn set_version(&mut self, major_version: u32, minor_version: u32) {
+        self.major_version = major_version;
+        self.minor_version = minor_version;
+    }
+}
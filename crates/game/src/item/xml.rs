@@ -0,0 +1,116 @@
+use std::{fs, path::Path, str::FromStr};
+
+use anyhow::Context;
+
+use super::{ItemType, ItemTypes};
+
+/// Loads names and attributes from an items.xml file into the item types loaded from items.otb
+pub fn load_xml<P: AsRef<Path>>(types: &mut ItemTypes, path: P) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    read_xml(types, &text).with_context(|| format!("failed to load {}", path.display()))
+}
+
+/// Reads names and attributes from the contents of an items.xml file
+///
+/// Items are either a single `id` or a `fromid`-`toid` range, with their attributes as
+/// `<attribute key="..." value="..."/>` children. Items missing in items.otb are skipped.
+pub fn read_xml(types: &mut ItemTypes, text: &str) -> anyhow::Result<()> {
+    let document = roxmltree::Document::parse(text)?;
+
+    for node in document.root_element().children().filter(|node| node.has_tag_name("item")) {
+        let line = document.text_pos_at(node.range().start).row;
+        let (from, to) = match (node.attribute("id"), node.attribute("fromid"), node.attribute("toid")) {
+            (Some(id), _, _) => (parse(id, line)?, parse(id, line)?),
+            (None, Some(from), Some(to)) => (parse(from, line)?, parse(to, line)?),
+            _ => anyhow::bail!("line {}: item without id", line),
+        };
+
+        for id in from..=to {
+            let item_type = match types.get_mut(id) {
+                Some(item_type) => item_type,
+                None => {
+                    log::warn!("items.xml line {}: unknown item {}", line, id);
+                    continue;
+                },
+            };
+
+            if let Some(name) = node.attribute("name") {
+                item_type.name = name.to_string();
+            }
+            if let Some(article) = node.attribute("article") {
+                item_type.article = article.to_string();
+            }
+            if let Some(plural) = node.attribute("plural") {
+                item_type.plural_name = plural.to_string();
+            }
+
+            for attribute in node.children().filter(|node| node.has_tag_name("attribute")) {
+                if let (Some(key), Some(value)) = (attribute.attribute("key"), attribute.attribute("value")) {
+                    let line = document.text_pos_at(attribute.range().start).row;
+                    set_attribute(item_type, &key.to_lowercase(), value, line)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn set_attribute(item_type: &mut ItemType, key: &str, value: &str, line: u32) -> anyhow::Result<()> {
+    match key {
+        "description" => item_type.description = value.to_string(),
+        "weight" => item_type.weight = parse(value, line)?,
+        "decayto" => item_type.decay_to = Some(parse(value, line)?),
+        "duration" => item_type.duration = Some(parse(value, line)?),
+        "containersize" => item_type.container_size = Some(parse(value, line)?),
+        _ => {
+            item_type.attributes.insert(key.to_string(), value.to_string());
+        },
+    }
+    Ok(())
+}
+
+fn parse<T: FromStr>(value: &str, line: u32) -> anyhow::Result<T> {
+    value.trim().parse().map_err(|_| anyhow::anyhow!("line {}: invalid number {:?}", line, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::item::otb::{read_otb, tests::test_otb};
+
+    #[test]
+    fn test_read_xml() {
+        let mut types = read_otb(&test_otb()[..]).unwrap();
+        read_xml(&mut types, r#"<?xml version="1.0"?>
+            <items>
+                <item id="102" article="a" name="grass" />
+                <item fromid="1026" toid="1027" name="stone wall" />
+                <item id="2148" article="a" name="gold coin" plural="gold coins">
+                    <attribute key="weight" value="10" />
+                </item>
+                <item id="1988" article="a" name="backpack">
+                    <attribute key="containerSize" value="20" />
+                    <attribute key="slotType" value="backpack" />
+                    <attribute key="decayTo" value="2148" />
+                    <attribute key="duration" value="60" />
+                </item>
+            </items>
+        "#).unwrap();
+
+        assert_eq!(types.get(102).unwrap().name, "grass");
+        assert_eq!(types.get(1026).unwrap().name, "stone wall");
+
+        let gold = types.get(2148).unwrap();
+        assert_eq!((gold.plural_name.as_str(), gold.weight), ("gold coins", 10));
+
+        let backpack = types.get(1988).unwrap();
+        assert_eq!(backpack.container_size, Some(20));
+        assert_eq!((backpack.decay_to, backpack.duration), (Some(2148), Some(60)));
+        assert_eq!(backpack.attributes.get("slottype").map(String::as_str), Some("backpack"));
+
+        assert!(read_xml(&mut types, r#"<items><item name="no id" /></items>"#).is_err());
+        assert!(read_xml(&mut types, r#"<items><item id="abc" /></items>"#).is_err());
+    }
+}
@@ -1 +1,10 @@
+pub mod creature;
+pub mod description;
+pub mod item;
 pub mod map;
+pub mod movement;
+pub mod otbm;
+pub mod pathfinding;
+pub mod thing;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
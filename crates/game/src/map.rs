@@ -1,69 +1,161 @@
-use std::{fmt::{self, Display, Formatter}, sync::Arc};
+use std::sync::Arc;
 
-use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use parking_lot::{MappedRwLockReadGuard, MappedRwLockWriteGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use ahash::AHashMap;
 use smallvec::{SmallVec};
 
-use base::Position;
+use base::{Area, CHUNK_BITS, CHUNK_MASK, CHUNK_SIZE, Direction, Line, Position, Rect};
+pub use base::ChunkPosition;
 
-const MAX_LAYERS: usize = 16;
+use crate::{item::{Item, ItemTypes}, movement::{CreatureMove, MoveError}, thing::{Thing, ThingId, Things}};
 
-const CHUNK_BITS: u16 = 3;
-const CHUNK_SIZE: u16 = 1 << CHUNK_BITS;
-const CHUNK_MASK: u16 = CHUNK_SIZE - 1;
+pub(crate) const MAX_LAYERS: usize = 16;
 
-pub type ThingId = usize; // change
+/// Largest diagonal offset between floors seen from each other, from the highest floor to sea level
+const MAX_FLOOR_OFFSET: u16 = 7;
+
+
+/// Number of things of a tile that are known by the client, things above are not sent
+pub const MAX_CLIENT_STACK: usize = 10;
+
+/// Group of a thing in the stack of a tile, the stack is ordered by it
+///
+/// The client expects the ground first, then always on top items by their top order, then creatures,
+/// then other items. Other items are stacked newest first, the rest in the order they were added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StackPriority {
+    Ground,
+    /// Always on top items, by top order
+    OnTop(u8),
+    Creature,
+    Item,
+}
+
+impl StackPriority {
+    /// Returns the priority of the thing, items of unknown types are ordered as other items
+    pub fn of(thing: &Thing, item_types: &ItemTypes) -> Self {
+        let item = match thing {
+            Thing::Item(item) => item,
+            Thing::Creature(_) => return StackPriority::Creature,
+        };
+
+        match item_types.get(item.id) {
+            Some(item_type) if item_type.is_ground() => StackPriority::Ground,
+            Some(item_type) if item_type.is_always_on_top() => StackPriority::OnTop(item_type.top_order),
+            _ => StackPriority::Item,
+        }
+    }
+}
+
+/// Flags of a tile, as stored in OTBM
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileFlags(pub u32);
+
+impl TileFlags {
+    pub const PROTECTION_ZONE: TileFlags = TileFlags(1 << 0);
+    pub const NO_PVP_ZONE: TileFlags = TileFlags(1 << 2);
+    pub const NO_LOGOUT: TileFlags = TileFlags(1 << 3);
+    pub const PVP_ZONE: TileFlags = TileFlags(1 << 4);
+
+    /// Returns true if all flags of other are set
+    pub fn contains(self, other: TileFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns true if no flags are set
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
 
 #[derive(Debug, Default, Clone)]
 pub struct Tile {
     things: SmallVec<[ThingId; 10]>,
+    flags: TileFlags,
+    house_id: Option<u32>,
 }
 
 impl Tile {
-    // Returns the stack index of the thing in the tile
+    /// Returns the flags of the tile
+    pub fn flags(&self) -> TileFlags {
+        self.flags
+    }
+
+    pub fn set_flags(&mut self, flags: TileFlags) {
+        self.flags = flags;
+    }
+
+    /// Returns the id of the house the tile belongs to, if any
+    pub fn house_id(&self) -> Option<u32> {
+        self.house_id
+    }
+
+    pub fn set_house_id(&mut self, house_id: Option<u32>) {
+        self.house_id = house_id;
+    }
+
+    /// Returns the stack index of the thing in the tile
     pub fn thing_index(&self, thing: ThingId) -> Option<usize> {
         self.things.iter().position(|t| *t == thing)
     }
 
-    /// Returns an iterator of the things in the tile, bottom -> top
+    /// Returns the stack index of the thing as known by the client, None if it's not visible
+    pub fn client_stack_index(&self, thing: ThingId) -> Option<u8> {
+        self.thing_index(thing)
+            .filter(|&index| index < MAX_CLIENT_STACK)
+            .map(|index| index as u8)
+    }
+
+    /// Returns an iterator of the things in the tile, in stack order starting with the ground
     pub fn things_iter(&self) -> impl Iterator<Item = &ThingId> {
         self.things.iter()
     }
 
-    /// Adds a thing to the top of the tile
+    /// Returns true if there are no things in the tile
+    pub fn is_empty(&self) -> bool {
+        self.things.is_empty()
+    }
+
+    /// Adds a thing to the end of the stack
+    ///
+    /// Does not keep the stack order, only for things added in stack order, e.g when loading a map.
     pub fn push(&mut self, thing: ThingId) {
         self.things.push(thing);
     }
 
-    /// Remove and return thing at index
-    pub fn remove(&mut self, thing_index: usize) -> ThingId {
-        self.things.remove(thing_index)
+    /// Inserts a thing at its position in the stack, returning its stack index
+    ///
+    /// Other items go first in their group, everything else last in its group.
+    pub fn insert(&mut self, thing: ThingId, priority: StackPriority, priority_of: impl Fn(ThingId) -> StackPriority) -> usize {
+        let index = self.things.iter()
+            .position(|&other| match priority {
+                StackPriority::Item => priority_of(other) >= priority,
+                _ => priority_of(other) > priority,
+            })
+            .unwrap_or_else(|| self.things.len());
+        self.things.insert(index, thing);
+        index
     }
 
-    /// Swap two things in the stack
-    pub fn swap(&mut self, a: usize, b: usize) {
-        self.things.swap(a, b)
+    /// Inserts a thing at its position in the stack by its item type, returning its stack index
+    ///
+    /// Stale things in the tile are ordered as other items.
+    pub fn add(&mut self, thing: ThingId, things: &Things, item_types: &ItemTypes) -> usize {
+        let priority_of = |id| things.get(id)
+            .map_or(StackPriority::Item, |thing| StackPriority::of(thing, item_types));
+        self.insert(thing, priority_of(thing), priority_of)
     }
-}
-
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
-pub struct ChunkPosition {
-    pub x: u16,
-    pub y: u16,
-}
 
-impl ChunkPosition {
-    pub const fn new(x: u16, y: u16) -> Self {
-        Self { x, y }
+    /// Remove and return thing at index
+    pub fn remove(&mut self, thing_index: usize) -> ThingId {
+        self.things.remove(thing_index)
     }
-}
 
-impl From<Position> for ChunkPosition {
-    fn from(pos: Position) -> Self {
-        Self {
-            x: pos.x >> CHUNK_BITS,
-            y: pos.y >> CHUNK_BITS,
-        }
+    /// Removes the thing, returning its stack index
+    pub fn remove_thing(&mut self, thing: ThingId) -> Option<usize> {
+        let index = self.thing_index(thing)?;
+        self.things.remove(index);
+        Some(index)
     }
 }
 
@@ -84,15 +176,11 @@ impl From<Position> for TilePosition {
     }
 }
 
-impl Display for ChunkPosition {
-    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
-        write!(f, "({}, {})", self.x, self.y)
-    }
-}
-
 pub struct Chunk {
     layers: [Option<Vec<Tile>>; MAX_LAYERS],
     position: ChunkPosition,
+    /// Creatures on the tiles of the chunk with their positions, for spectator queries
+    creatures: Vec<(ThingId, Position)>,
 }
 
 impl Chunk {
@@ -103,7 +191,8 @@ impl Chunk {
                 None, None, None, None, None, None, None, None, None,
                 None, None, None, None, None, None, None, 
             ],
-            position
+            position,
+            creatures: Vec::new(),
         }
     }
 
@@ -112,6 +201,11 @@ impl Chunk {
         self.position
     }
 
+    /// Returns the creatures on the tiles of the chunk with their positions
+    pub fn creatures(&self) -> &[(ThingId, Position)] {
+        &self.creatures
+    }
+
     /// Get a reference to the tile at pos
     pub fn tile_at(&self, pos: TilePosition) -> Option<&Tile> {
         match &self.layers[pos.z] {
@@ -128,9 +222,38 @@ impl Chunk {
         }
     }
 
+    /// Returns an iterator of the tiles in the chunk with their positions, floor by floor
+    pub fn tiles(&self) -> impl Iterator<Item = (Position, &Tile)> {
+        let position = self.position;
+        self.layers.iter().enumerate().flat_map(move |(z, layer)| {
+            layer.iter().flat_map(|tiles| tiles.iter()).enumerate().map(move |(i, tile)| {
+                let pos = Position {
+                    x: (position.x << CHUNK_BITS) + (i as u16 & CHUNK_MASK),
+                    y: (position.y << CHUNK_BITS) + (i as u16 >> CHUNK_BITS),
+                    z: z as u8,
+                };
+                (pos, tile)
+            })
+        })
+    }
+
+    /// Adds a creature to the tile at pos in stack order, returning its stack index
+    fn add_creature(&mut self, id: ThingId, pos: Position, things: &Things, item_types: &ItemTypes) -> Option<usize> {
+        let index = self.tile_at_mut(pos.into())?.add(id, things, item_types);
+        self.creatures.push((id, pos));
+        Some(index)
+    }
+
+    /// Removes a creature from the tile at pos, returning the stack index it had
+    fn remove_creature(&mut self, id: ThingId, pos: Position) -> Option<usize> {
+        let index = self.tile_at_mut(pos.into())?.remove_thing(id)?;
+        self.creatures.retain(|(creature, _)| *creature != id);
+        Some(index)
+    }
+
     pub fn set_tile(&mut self, pos: TilePosition, tile: Tile) {
         if self.layers[pos.z].is_none() {
-            self.layers[pos.z] = Some(Vec::with_capacity(CHUNK_SIZE as usize ^ 2));
+            self.layers[pos.z] = Some(vec![Tile::default(); (CHUNK_SIZE * CHUNK_SIZE) as usize]);
         }
 
         if let Some(layer) = &mut self.layers[pos.z] {
@@ -139,11 +262,31 @@ impl Chunk {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Town {
+    pub id: u32,
+    pub name: String,
+    pub temple: Position,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Waypoint {
+    pub name: String,
+    pub position: Position,
+}
+
+/// Chunks of tiles, and the things placed on them
+///
+/// The lock order is the things first, then the chunks in order of their positions. Use view to read
+/// things and tiles together.
 #[derive(Default)]
 pub struct Map {
     width: u16,
     height: u16,
     chunks: AHashMap<ChunkPosition, Arc<RwLock<Chunk>>>,
+    things: RwLock<Things>,
+    towns: Vec<Town>,
+    waypoints: Vec<Waypoint>,
 }
 
 impl Map {
@@ -155,6 +298,53 @@ impl Map {
         }
     }
 
+    /// Returns the width of the map in tiles
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    /// Returns the height of the map in tiles
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Adds an item to the things of the map, returning the id used to place it on tiles
+    pub fn add_item(&mut self, item: Item) -> ThingId {
+        self.things.get_mut().insert(item)
+    }
+
+    /// Returns the things placed on the tiles of the map
+    pub fn things(&self) -> RwLockReadGuard<'_, Things> {
+        self.things.read()
+    }
+
+    pub fn things_mut(&self) -> RwLockWriteGuard<'_, Things> {
+        self.things.write()
+    }
+
+    /// Locks the things for reading, the tiles are then locked one at a time through the view
+    pub fn view(&self) -> MapView<'_> {
+        MapView { map: self, things: self.things() }
+    }
+
+    /// Returns the towns of the map
+    pub fn towns(&self) -> &[Town] {
+        &self.towns
+    }
+
+    pub fn add_town(&mut self, town: Town) {
+        self.towns.push(town);
+    }
+
+    /// Returns the waypoints of the map
+    pub fn waypoints(&self) -> &[Waypoint] {
+        &self.waypoints
+    }
+
+    pub fn add_waypoint(&mut self, waypoint: Waypoint) {
+        self.waypoints.push(waypoint);
+    }
+
     pub fn insert_chunk(&mut self, chunk: Chunk) {
         self.chunks.insert(chunk.position(), Arc::new(RwLock::new(chunk)));
     }
@@ -165,11 +355,409 @@ impl Map {
         }
     }
 
-    pub fn chunk_at(&self, pos: ChunkPosition) -> Option<RwLockReadGuard<Chunk>> {
+    /// Returns the positions of all chunks in the map, in no particular order
+    pub fn chunk_positions(&self) -> impl Iterator<Item = ChunkPosition> + '_ {
+        self.chunks.keys().copied()
+    }
+
+    pub fn chunk_at(&self, pos: ChunkPosition) -> Option<RwLockReadGuard<'_, Chunk>> {
         self.chunks.get(&pos).map(|lock| lock.read())
     }
 
-    pub fn chunk_at_mut(&self, pos: ChunkPosition) -> Option<RwLockWriteGuard<Chunk>> {
+    pub fn chunk_at_mut(&self, pos: ChunkPosition) -> Option<RwLockWriteGuard<'_, Chunk>> {
         self.chunks.get(&pos).map(|lock| lock.write())
     }
+
+    /// Returns the tile at pos, holding a read lock on its chunk
+    pub fn tile_at(&self, pos: Position) -> Option<MappedRwLockReadGuard<'_, Tile>> {
+        let chunk = self.chunk_at(pos.into())?;
+        RwLockReadGuard::try_map(chunk, |chunk| chunk.tile_at(pos.into())).ok()
+    }
+
+    /// Returns the tile at pos to modify, holding a write lock on its chunk
+    pub fn tile_at_mut(&self, pos: Position) -> Option<MappedRwLockWriteGuard<'_, Tile>> {
+        let chunk = self.chunk_at_mut(pos.into())?;
+        RwLockWriteGuard::try_map(chunk, |chunk| chunk.tile_at_mut(pos.into())).ok()
+    }
+
+    /// Sets the tile at pos, creating the chunk if needed
+    pub fn set_tile(&mut self, pos: Position, tile: Tile) {
+        let chunk_pos = pos.into();
+        self.ensure_chunk(chunk_pos);
+        if let Some(mut chunk) = self.chunk_at_mut(chunk_pos) {
+            chunk.set_tile(pos.into(), tile);
+        }
+    }
+
+    /// Places a creature on the tile at pos in stack order, returning its stack index
+    ///
+    /// Creatures have to be placed, moved and removed through the map to be found as spectators,
+    /// the position of the creature is kept up to date. Returns None if there is no tile at pos.
+    pub fn place_creature(&self, id: ThingId, pos: Position, item_types: &ItemTypes) -> Option<usize> {
+        let mut things = self.things_mut();
+        let index = self.chunk_at_mut(pos.into())?.add_creature(id, pos, &things, item_types)?;
+        if let Some(creature) = things.creature_mut(id) {
+            creature.position = pos;
+        }
+        Some(index)
+    }
+
+    /// Removes a creature from its tile, returning the position and the stack index it had
+    pub fn remove_creature(&self, id: ThingId) -> Option<(Position, usize)> {
+        let things = self.things();
+        let pos = things.creature(id)?.position;
+        let index = self.chunk_at_mut(pos.into())?.remove_creature(id, pos)?;
+        Some((pos, index))
+    }
+
+    /// Turns a creature, returning its position and its stack index there
+    pub fn turn_creature(&self, id: ThingId, direction: Direction) -> Option<(Position, usize)> {
+        let mut things = self.things_mut();
+        let creature = things.creature_mut(id)?;
+        creature.direction = direction;
+        let pos = creature.position;
+        let index = self.tile_at(pos)?.thing_index(id)?;
+        Some((pos, index))
+    }
+
+    /// Moves a creature from its tile to the tile at to, if it can stand there
+    ///
+    /// The destination needs a ground without blocking items or other creatures.
+    pub fn move_creature(&self, id: ThingId, to: Position, item_types: &ItemTypes) -> Result<CreatureMove, MoveError> {
+        let mut things = self.things_mut();
+        let from = things.creature(id).ok_or(MoveError::NotFound)?.position;
+        let from_lock = self.chunks.get(&from.into()).ok_or(MoveError::NotFound)?;
+        let to_lock = self.chunks.get(&to.into()).ok_or(MoveError::NoTile)?;
+
+        let (from_stack_index, to_stack_index) = if Arc::ptr_eq(from_lock, to_lock) {
+            let mut chunk = to_lock.write();
+            check_source(chunk.tile_at(from.into()), id)?;
+            check_destination(chunk.tile_at(to.into()), id, &things, item_types)?;
+            let from_index = chunk.remove_creature(id, from).ok_or(MoveError::NotFound)?;
+            (from_index, chunk.add_creature(id, to, &things, item_types).ok_or(MoveError::NoTile)?)
+        } else {
+            // Chunks are locked in order of their positions, so creatures moving the other way can't deadlock
+            let (mut from_chunk, mut to_chunk) = if ChunkPosition::from(from) < ChunkPosition::from(to) {
+                let from_chunk = from_lock.write();
+                (from_chunk, to_lock.write())
+            } else {
+                let to_chunk = to_lock.write();
+                (from_lock.write(), to_chunk)
+            };
+            check_source(from_chunk.tile_at(from.into()), id)?;
+            check_destination(to_chunk.tile_at(to.into()), id, &things, item_types)?;
+            let from_index = from_chunk.remove_creature(id, from).ok_or(MoveError::NotFound)?;
+            (from_index, to_chunk.add_creature(id, to, &things, item_types).ok_or(MoveError::NoTile)?)
+        };
+
+        if let Some(creature) = things.creature_mut(id) {
+            creature.position = to;
+        }
+
+        Ok(CreatureMove { creature: id, from, from_stack_index, to, to_stack_index })
+    }
+
+    /// Returns the creatures that can see pos within range, see Position::can_see
+    ///
+    /// Without multifloor only creatures on the floor of pos are included.
+    /// The viewport reaches 9 tiles east and 7 south of the player, use that range for broadcasts.
+    pub fn spectators(&self, pos: Position, range_x: u16, range_y: u16, multifloor: bool) -> Vec<ThingId> {
+        // Creatures on other floors see pos offset diagonally
+        let offset = if multifloor { MAX_FLOOR_OFFSET } else { 0 };
+        let rect = Rect::around(pos, range_x.saturating_add(offset), range_y.saturating_add(offset));
+
+        let mut spectators = Vec::new();
+        for chunk_pos in rect.chunks() {
+            if let Some(chunk) = self.chunk_at(chunk_pos) {
+                spectators.extend(chunk.creatures.iter()
+                    .filter(|(_, creature_pos)| multifloor || creature_pos.z == pos.z)
+                    .filter(|(_, creature_pos)| creature_pos.can_see(&pos, range_x, range_y))
+                    .map(|(id, _)| *id));
+            }
+        }
+        spectators
+    }
+
+    /// Returns true if there is a clear line from from to to, e.g to throw an item or shoot at a creature
+    ///
+    /// Lines are blocked by items blocking projectiles, tiles next to from are always in sight. Unless same_floor
+    /// is set, a line blocked on the floor can go over the obstacle, and to a floor above or below, like in TFS.
+    /// It then has to pass the tiles between the floors without a ground. Lines never cross the surface.
+    pub fn is_sight_clear(&self, from: Position, to: Position, same_floor: bool, item_types: &ItemTypes) -> bool {
+        let view = self.view();
+        let is_clear = |pos: Position, block_ground: bool| match view.tile_at(pos) {
+            Some(tile) => tile.things_iter()
+                .filter_map(|&id| view.things().item(id))
+                .filter_map(|item| item_types.get(item.id))
+                .all(|item_type| !(item_type.is_projectile_blocking() || (block_ground && item_type.is_ground()))),
+            None => true,
+        };
+        let is_line_clear = |from: Position, to: Position| Line::new(from, to).skip(1).all(|pos| is_clear(pos, false));
+        let above = |pos: Position| Position { z: pos.z - 1, ..pos };
+
+        if from.z == to.z {
+            if from.chebyshev_distance(&to) < 2 {
+                return true;
+            }
+            if is_line_clear(from, to) {
+                return true;
+            }
+
+            // Over the obstacle, nothing blocks above the highest floor
+            return !same_floor && (from.z == 0 || (is_clear(above(from), true) && is_clear(above(to), true)
+                && is_line_clear(above(from), above(to))));
+        }
+
+        if same_floor || (from.z <= 7) != (to.z <= 7) {
+            return false;
+        }
+
+        if from.z > to.z {
+            // Up through the tile above, to the floor right above
+            from.z == to.z + 1 && is_clear(above(from), true) && is_line_clear(above(from), to)
+        } else {
+            // Down through the tiles above the target
+            (from.z..to.z).all(|z| is_clear(Position { z, ..to }, true)) && is_line_clear(from, Position { z: from.z, ..to })
+        }
+    }
+}
+
+/// The things of a map locked for reading, with the tiles, see Map::view
+pub struct MapView<'a> {
+    map: &'a Map,
+    things: RwLockReadGuard<'a, Things>,
+}
+
+impl<'a> MapView<'a> {
+    /// Returns the things placed on the tiles of the map
+    pub fn things(&self) -> &Things {
+        &self.things
+    }
+
+    /// Returns the tile at pos, locking its chunk until the tile is dropped
+    pub fn tile_at(&self, pos: Position) -> Option<MappedRwLockReadGuard<'a, Tile>> {
+        self.map.tile_at(pos)
+    }
+}
+
+/// Checks that the creature is on the tile it moves from
+fn check_source(tile: Option<&Tile>, creature: ThingId) -> Result<(), MoveError> {
+    match tile.and_then(|tile| tile.thing_index(creature)) {
+        Some(_) => Ok(()),
+        None => Err(MoveError::NotFound),
+    }
+}
+
+/// Checks that a creature can stand on the tile, other than itself
+fn check_destination(tile: Option<&Tile>, creature: ThingId, things: &Things, item_types: &ItemTypes) -> Result<(), MoveError> {
+    let tile = tile.ok_or(MoveError::NoTile)?;
+    let mut has_ground = false;
+    for &id in tile.things_iter() {
+        match things.get(id) {
+            Some(Thing::Creature(_)) if id != creature => return Err(MoveError::Occupied),
+            Some(Thing::Item(item)) => match item_types.get(item.id) {
+                Some(item_type) if item_type.is_blocking() => return Err(MoveError::Blocked),
+                Some(item_type) if item_type.is_ground() => has_ground = true,
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+
+    if has_ground {
+        Ok(())
+    } else {
+        Err(MoveError::NoTile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{creature::Creature, test_util::{self, GRASS, WALL, item_types}};
+
+    /// Sets tiles with grass, and a wall on top if wall is set
+    fn set_tiles(map: &mut Map, positions: &[Position], wall: bool) {
+        let items: &[u16] = if wall { &[GRASS, WALL] } else { &[GRASS] };
+        for &pos in positions {
+            test_util::set_tile(map, pos, items);
+        }
+    }
+
+    #[test]
+    fn test_stack_priority() {
+        let types = item_types();
+        let priority = |thing: Thing| StackPriority::of(&thing, &types);
+        assert_eq!(priority(Item::new(102).into()), StackPriority::Ground);
+        assert_eq!(priority(Item::new(4526).into()), StackPriority::OnTop(1));
+        assert_eq!(priority(Creature::new(1, "Rustia".to_string()).into()), StackPriority::Creature);
+        assert_eq!(priority(Item::new(2148).into()), StackPriority::Item);
+        assert_eq!(priority(Item::new(9999).into()), StackPriority::Item);
+
+        assert!(StackPriority::Ground < StackPriority::OnTop(1));
+        assert!(StackPriority::OnTop(1) < StackPriority::OnTop(2));
+        assert!(StackPriority::OnTop(3) < StackPriority::Creature);
+        assert!(StackPriority::Creature < StackPriority::Item);
+    }
+
+    #[test]
+    fn test_tile_stack_order() {
+        let types = item_types();
+        let mut things = Things::new();
+        let mut tile = Tile::default();
+
+        let coin = things.insert(Item::new(2148));
+        let player = things.insert(Creature::new(1, "Rustia".to_string()));
+        let ladder = things.insert(Item::new(1948));
+        let ground = things.insert(Item::new(102));
+        let border = things.insert(Item::new(4526));
+        let monster = things.insert(Creature::new(2, "Rat".to_string()));
+        let second_coin = things.insert(Item::new(2148));
+
+        assert_eq!(tile.add(coin, &things, &types), 0);
+        assert_eq!(tile.add(player, &things, &types), 0);
+        assert_eq!(tile.add(ladder, &things, &types), 0);
+        assert_eq!(tile.add(ground, &things, &types), 0);
+        assert_eq!(tile.add(border, &things, &types), 1);
+        // Newer creatures come last in their group, newer items first
+        assert_eq!(tile.add(monster, &things, &types), 4);
+        assert_eq!(tile.add(second_coin, &things, &types), 5);
+
+        let stack: Vec<_> = tile.things_iter().copied().collect();
+        assert_eq!(stack, vec![ground, border, ladder, player, monster, second_coin, coin]);
+
+        assert_eq!(tile.remove_thing(player), Some(3));
+        assert_eq!(tile.thing_index(monster), Some(3));
+        assert_eq!(tile.remove_thing(player), None);
+    }
+
+    #[test]
+    fn test_spectators() {
+        let types = item_types();
+        let mut map = Map::new(200, 200);
+        let center = Position::new(100, 100, 7);
+        let positions = [
+            center,
+            Position::new(91, 100, 7),
+            Position::new(109, 107, 7),
+            Position::new(110, 100, 7),
+            Position::new(100, 100, 8),
+            Position::new(106, 106, 6),
+            Position::new(100, 92, 5),
+            Position::new(100, 100, 10),
+        ];
+        for pos in positions.iter() {
+            map.set_tile(*pos, Tile::default());
+        }
+
+        let creatures: Vec<_> = positions.iter().enumerate().map(|(i, pos)| {
+            let id = map.things_mut().insert(Creature::new(i as u32, "Rat".to_string()));
+            assert_eq!(map.place_creature(id, *pos, &types), Some(0));
+            id
+        }).collect();
+
+        let mut spectators = map.spectators(center, 9, 7, false);
+        spectators.sort_by_key(|id| map.things().creature(*id).unwrap().id);
+        assert_eq!(spectators, vec![creatures[0], creatures[1], creatures[2]]);
+
+        // Creatures on the floors above see the center offset, the one underground sees 2 floors up
+        let mut spectators = map.spectators(center, 9, 7, true);
+        spectators.sort_by_key(|id| map.things().creature(*id).unwrap().id);
+        assert_eq!(spectators, vec![creatures[0], creatures[1], creatures[2], creatures[4], creatures[5]]);
+
+        assert_eq!(map.things().creature(creatures[1]).unwrap().position, positions[1]);
+        assert_eq!(map.remove_creature(creatures[1]), Some((positions[1], 0)));
+        assert_eq!(map.remove_creature(creatures[1]), None);
+        assert_eq!(map.spectators(center, 9, 7, false).len(), 2);
+        assert!(map.tile_at(positions[1]).unwrap().is_empty());
+
+        assert_eq!(map.place_creature(creatures[1], Position::new(0, 0, 7), &types), None);
+    }
+
+    #[test]
+    fn test_client_stack_index() {
+        let types = item_types();
+        let mut things = Things::new();
+        let mut tile = Tile::default();
+
+        tile.add(things.insert(Item::new(102)), &things, &types);
+        let coins: Vec<_> = (0..10).map(|_| {
+            let coin = things.insert(Item::new(2148));
+            tile.add(coin, &things, &types);
+            coin
+        }).collect();
+
+        // The first coin is pushed down to the 11th position, out of sight
+        assert_eq!(tile.client_stack_index(coins[9]), Some(1));
+        assert_eq!(tile.client_stack_index(coins[1]), Some(9));
+        assert_eq!(tile.client_stack_index(coins[0]), None);
+
+        let player = things.insert(Creature::new(1, "Rustia".to_string()));
+        assert_eq!(tile.add(player, &things, &types), 1);
+        assert_eq!(tile.client_stack_index(player), Some(1));
+        assert_eq!(tile.client_stack_index(coins[1]), None);
+    }
+
+    #[test]
+    fn test_sight_clear() {
+        let types = item_types();
+        let mut map = Map::new(200, 200);
+        let floor: Vec<_> = Rect::new(100, 100, 110, 110).positions(7).collect();
+        set_tiles(&mut map, &floor, false);
+        set_tiles(&mut map, &[Position::new(103, 100, 7), Position::new(102, 102, 7)], true);
+
+        let from = Position::new(100, 100, 7);
+        let sight = |map: &Map, to: (u16, u16), same_floor| map.is_sight_clear(from, Position::new(to.0, to.1, 7), same_floor, &types);
+        assert!(sight(&map, (102, 100), true));
+        // Across chunks
+        assert!(sight(&map, (100, 108), true));
+        // Behind the wall, and onto it
+        assert!(!sight(&map, (105, 100), true));
+        assert!(!sight(&map, (103, 100), true));
+        // Diagonally through the wall, and past it on the tiles next to the diagonal
+        assert!(!sight(&map, (104, 104), true));
+        assert!(sight(&map, (104, 106), true));
+        assert!(sight(&map, (106, 104), true));
+        // Next to from is always in sight
+        assert!(map.is_sight_clear(Position::new(102, 101, 7), Position::new(103, 100, 7), true, &types));
+
+        // Over the wall when there is nothing above, but not under a roof
+        assert!(sight(&map, (105, 100), false));
+        set_tiles(&mut map, &[Position::new(100, 100, 6)], false);
+        assert!(!sight(&map, (105, 100), false));
+        assert!(map.is_sight_clear(Position::new(101, 100, 7), Position::new(105, 100, 7), false, &types));
+        set_tiles(&mut map, &[Position::new(104, 100, 6)], true);
+        assert!(!map.is_sight_clear(Position::new(101, 100, 7), Position::new(105, 100, 7), false, &types));
+    }
+
+    #[test]
+    fn test_sight_clear_floors() {
+        let types = item_types();
+        let mut map = Map::new(200, 200);
+        set_tiles(&mut map, &[Position::new(105, 100, 6), Position::new(100, 100, 6)], false);
+        set_tiles(&mut map, &[Position::new(102, 102, 5)], false);
+        let ground = Position::new(100, 102, 7);
+        let balcony = Position::new(105, 100, 6);
+
+        // Up to the floor above, through the hole above
+        assert!(map.is_sight_clear(ground, balcony, false, &types));
+        assert!(!map.is_sight_clear(ground, balcony, true, &types));
+        assert!(!map.is_sight_clear(Position::new(100, 101, 7), Position::new(100, 100, 5), false, &types));
+        assert!(!map.is_sight_clear(Position::new(100, 101, 8), Position::new(105, 100, 7), false, &types));
+
+        // Down from the balcony, the tile above the target has no ground
+        assert!(map.is_sight_clear(balcony, ground, false, &types));
+        assert!(!map.is_sight_clear(balcony, Position::new(100, 100, 7), false, &types));
+        assert!(map.is_sight_clear(Position::new(102, 103, 5), ground, false, &types));
+        assert!(!map.is_sight_clear(Position::new(102, 103, 5), Position::new(102, 102, 7), false, &types));
+
+        // Blocked by a wall on the floor the line passes
+        set_tiles(&mut map, &[Position::new(102, 101, 6)], true);
+        assert!(!map.is_sight_clear(ground, balcony, false, &types));
+        assert!(!map.is_sight_clear(balcony, ground, false, &types));
+
+        // A roof above blocks the way up
+        set_tiles(&mut map, &[Position::new(100, 102, 6)], false);
+        assert!(!map.is_sight_clear(ground, Position::new(101, 102, 6), false, &types));
+    }
 }
@@ -0,0 +1,447 @@
+use std::{collections::VecDeque, fmt, time::{Duration, Instant}};
+
+use base::{Direction, Position};
+use protocol::packet::GameServerPacket;
+use protocol::packet::game::{AddTileThing, DeleteTileThing, MoveCreature};
+
+use crate::{
+    description::{MapDescriber, in_viewport},
+    item::ItemTypes,
+    map::{MAX_CLIENT_STACK, Map},
+    thing::ThingId,
+};
+
+/// Speed of tiles without ground, and of grounds without a speed
+pub const DEFAULT_GROUND_SPEED: u16 = 150;
+
+/// Constants of the step duration formula, sent to the client in LoginSuccess
+///
+/// The client predicts the duration of its own steps with the same formula, so they have to match.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeedFormula {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    /// Step durations are rounded down to whole beats, in milliseconds
+    pub beat_duration: u16,
+}
+
+impl Default for SpeedFormula {
+    /// The constants of TFS
+    fn default() -> Self {
+        Self {
+            a: 857.36,
+            b: 261.29,
+            c: -4795.01,
+            beat_duration: 50,
+        }
+    }
+}
+
+impl SpeedFormula {
+    /// Returns the duration of a step of a creature with speed, onto a ground with ground_speed
+    ///
+    /// Diagonal steps take three times as long.
+    pub fn step_duration(&self, speed: u16, ground_speed: u16, diagonal: bool) -> Duration {
+        let step_speed = (self.a * ((speed / 2) as f64 + self.b).ln() + self.c + 0.5).floor().max(1.0);
+        let duration = (1000.0 * ground_speed as f64 / step_speed).floor() as u64;
+        let beat = self.beat_duration.max(1) as u64;
+        let duration = duration / beat * beat;
+        Duration::from_millis(if diagonal { duration * 3 } else { duration })
+    }
+}
+
+/// Reason a creature could not move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveError {
+    /// The creature is not on the map
+    NotFound,
+    /// There is no tile with a ground at the destination
+    NoTile,
+    /// An item blocks the destination
+    Blocked,
+    /// Another creature stands on the destination
+    Occupied,
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MoveError::NotFound => write!(f, "creature is not on the map"),
+            MoveError::NoTile => write!(f, "there is no way"),
+            MoveError::Blocked => write!(f, "there is not enough room"),
+            MoveError::Occupied => write!(f, "the tile is occupied by a creature"),
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+/// A creature that moved between two tiles, with its stack indices before and after
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CreatureMove {
+    pub creature: ThingId,
+    pub from: Position,
+    pub from_stack_index: usize,
+    pub to: Position,
+    pub to_stack_index: usize,
+}
+
+impl CreatureMove {
+    /// Returns the packets telling the player that moved about it, with the rows coming into sight
+    ///
+    /// Moving further than one step or out of the client stack is like a teleport, the whole viewport is sent.
+    pub fn own_packets(&self, describer: &MapDescriber) -> Vec<GameServerPacket> {
+        let (from, to) = (self.from, self.to);
+        let mut packets = Vec::new();
+
+        if from.chebyshev_distance(&to) > 1 || from.distance_z(&to) > 1 || self.from_stack_index >= MAX_CLIENT_STACK {
+            if self.from_stack_index < MAX_CLIENT_STACK {
+                packets.push(self.delete_packet());
+            }
+            packets.push(describer.full_world(to).into());
+            return packets;
+        }
+
+        // The surface doesn't see underground, so the creature disappears from it
+        if from.z == 7 && to.z >= 8 {
+            packets.push(self.delete_packet());
+        } else {
+            packets.push(self.move_packet());
+        }
+        packets.extend(describer.floor_change(from, to));
+
+        if from.y > to.y {
+            packets.push(describer.row_north(to).into());
+        } else if from.y < to.y {
+            packets.push(describer.row_south(to).into());
+        }
+        if from.x < to.x {
+            packets.push(describer.row_east(to).into());
+        } else if from.x > to.x {
+            packets.push(describer.row_west(to).into());
+        }
+        packets
+    }
+
+    /// Returns the packets telling another player at viewer about the move
+    ///
+    /// The creature moves if the player sees both tiles, otherwise it disappears from or appears on the one it sees.
+    pub fn spectator_packets(&self, describer: &MapDescriber, viewer: Position) -> Vec<GameServerPacket> {
+        let sees_from = self.from_stack_index < MAX_CLIENT_STACK && in_viewport(viewer, self.from);
+        let sees_to = self.to_stack_index < MAX_CLIENT_STACK && in_viewport(viewer, self.to);
+
+        match (sees_from, sees_to) {
+            (true, true) => vec![self.move_packet()],
+            (true, false) => vec![self.delete_packet()],
+            (false, true) => describer.thing(self.creature)
+                .map(|thing| AddTileThing { position: self.to, stack_index: self.to_stack_index as u8, thing }.into())
+                .into_iter()
+                .collect(),
+            (false, false) => Vec::new(),
+        }
+    }
+
+    fn move_packet(&self) -> GameServerPacket {
+        MoveCreature {
+            old_position: self.from,
+            old_stack_index: self.from_stack_index as u8,
+            new_position: self.to,
+        }.into()
+    }
+
+    fn delete_packet(&self) -> GameServerPacket {
+        DeleteTileThing { position: self.from, stack_index: self.from_stack_index as u8 }.into()
+    }
+}
+
+/// Result of a step of a Walker
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepResult {
+    /// The creature moved, the next step can be taken after the duration
+    Moved(CreatureMove, Duration),
+    /// The previous step is not done yet, try again after the duration
+    Wait(Duration),
+    /// The step is not possible, the client has to cancel its walk
+    Cancel(MoveError),
+}
+
+/// Walks a creature step by step, no faster than its speed allows
+///
+/// Steps are taken one at a time with step, or along a path with walk and step_path.
+#[derive(Debug, Clone, Default)]
+pub struct Walker {
+    formula: SpeedFormula,
+    next_step: Option<Instant>,
+    path: VecDeque<Direction>,
+}
+
+impl Walker {
+    pub fn new(formula: SpeedFormula) -> Self {
+        Self { formula, next_step: None, path: VecDeque::new() }
+    }
+
+    /// Replaces the path to walk
+    pub fn walk(&mut self, directions: impl IntoIterator<Item = Direction>) {
+        self.path = directions.into_iter().collect();
+    }
+
+    /// Clears the path, the step in progress is still finished
+    pub fn stop(&mut self) {
+        self.path.clear();
+    }
+
+    /// Returns true if there are steps left on the path
+    pub fn is_walking(&self) -> bool {
+        !self.path.is_empty()
+    }
+
+    /// Takes the next step of the path, None if there is none
+    ///
+    /// A cancelled step clears the rest of the path.
+    pub fn step_path(&mut self, map: &Map, item_types: &ItemTypes, creature: ThingId, now: Instant) -> Option<StepResult> {
+        let direction = *self.path.front()?;
+        let result = self.step(map, item_types, creature, direction, now);
+        match result {
+            StepResult::Moved(..) => { self.path.pop_front(); },
+            StepResult::Cancel(_) => self.path.clear(),
+            StepResult::Wait(_) => {},
+        }
+        Some(result)
+    }
+
+    /// Returns the time when the next step can be taken, None if the creature can step now
+    pub fn next_step(&self) -> Option<Instant> {
+        self.next_step
+    }
+
+    /// Takes a step of the creature in direction, at the time now
+    ///
+    /// The creature turns towards the direction, east or west when walking diagonally.
+    pub fn step(&mut self, map: &Map, item_types: &ItemTypes, creature: ThingId, direction: Direction, now: Instant) -> StepResult {
+        if let Some(next_step) = self.next_step.filter(|&next_step| next_step > now) {
+            return StepResult::Wait(next_step - now);
+        }
+
+        let (from, speed) = match map.things().creature(creature) {
+            Some(creature) => (creature.position, creature.speed),
+            None => return StepResult::Cancel(MoveError::NotFound),
+        };
+        let to = match from.checked_add(direction.into()) {
+            Some(to) if direction != Direction::None => to,
+            _ => return StepResult::Cancel(MoveError::NoTile),
+        };
+
+        let creature_move = match map.move_creature(creature, to, item_types) {
+            Ok(creature_move) => creature_move,
+            Err(err) => return StepResult::Cancel(err),
+        };
+
+        // The move already happened, even if the creature is removed before it is turned
+        if let Some(creature) = map.things_mut().creature_mut(creature) {
+            creature.direction = match direction {
+                Direction::NorthWest | Direction::SouthWest => Direction::West,
+                Direction::NorthEast | Direction::SouthEast => Direction::East,
+                direction => direction,
+            };
+        }
+
+        let ground_speed = ground_speed(map, to, item_types);
+
+        let duration = self.formula.step_duration(speed, ground_speed, direction.is_diagonal());
+        self.next_step = Some(now + duration);
+        StepResult::Moved(creature_move, duration)
+    }
+}
+
+/// Returns the speed of the ground at pos, DEFAULT_GROUND_SPEED if there is none
+pub fn ground_speed(map: &Map, pos: Position, item_types: &ItemTypes) -> u16 {
+    let view = map.view();
+    let tile = match view.tile_at(pos) {
+        Some(tile) => tile,
+        None => return DEFAULT_GROUND_SPEED,
+    };
+
+    let speed = tile.things_iter()
+        .filter_map(|&id| view.things().item(id))
+        .filter_map(|item| item_types.get(item.id))
+        .find(|item_type| item_type.is_ground())
+        .map(|item_type| item_type.speed);
+    speed.filter(|&speed| speed > 0).unwrap_or(DEFAULT_GROUND_SPEED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{creature::Creature, test_util::{self, item_types}};
+
+    /// A map with grass on the tiles, a wall at (101, 99) and a road at (99, 100)
+    fn walk_map() -> Map {
+        test_util::map(Position::new(96, 96, 7), &[
+            ".........",
+            ".........",
+            ".........",
+            ".....#...",
+            "...r.....",
+            ".........",
+            ".........",
+            ".........",
+            ".........",
+        ])
+    }
+
+    fn place(map: &Map, item_types: &ItemTypes, id: u32, pos: Position) -> ThingId {
+        let creature = map.things_mut().insert(Creature::new(id, "Rat".to_string()));
+        assert_eq!(map.place_creature(creature, pos, item_types), Some(1));
+        creature
+    }
+
+    #[test]
+    fn test_step_duration() {
+        let formula = SpeedFormula::default();
+        assert_eq!(formula.step_duration(220, 150, false), Duration::from_millis(500));
+        assert_eq!(formula.step_duration(220, 150, true), Duration::from_millis(1500));
+        assert_eq!(formula.step_duration(220, 100, false), Duration::from_millis(350));
+        // Speed is halved as an integer, like the client does
+        assert_eq!(formula.step_duration(221, 150, false), formula.step_duration(220, 150, false));
+        // Faster creatures take shorter steps, rounded down to a beat
+        assert!(formula.step_duration(1000, 150, false) < formula.step_duration(220, 150, false));
+        assert_eq!(formula.step_duration(1000, 150, false).as_millis() % 50, 0);
+        // Too slow for the formula
+        assert_eq!(formula.step_duration(0, 150, false), Duration::from_millis(150_000));
+    }
+
+    #[test]
+    fn test_move_creature() {
+        let item_types = item_types();
+        let map = walk_map();
+        let start = Position::new(100, 100, 7);
+        let rat = place(&map, &item_types, 1, start);
+        let other = place(&map, &item_types, 2, Position::new(100, 101, 7));
+
+        assert_eq!(map.move_creature(rat, Position::new(101, 99, 7), &item_types), Err(MoveError::Blocked));
+        assert_eq!(map.move_creature(rat, Position::new(100, 101, 7), &item_types), Err(MoveError::Occupied));
+        assert_eq!(map.move_creature(rat, Position::new(100, 95, 7), &item_types), Err(MoveError::NoTile));
+        assert_eq!(map.move_creature(rat, Position::new(100, 100, 6), &item_types), Err(MoveError::NoTile));
+        assert_eq!(map.things().creature(rat).unwrap().position, start);
+
+        // Across the chunk border at x 104
+        let moved = map.move_creature(rat, Position::new(103, 100, 7), &item_types).unwrap();
+        assert_eq!(moved, CreatureMove {
+            creature: rat,
+            from: start,
+            from_stack_index: 1,
+            to: Position::new(103, 100, 7),
+            to_stack_index: 1,
+        });
+        let moved = map.move_creature(rat, Position::new(104, 100, 7), &item_types).unwrap();
+        assert_eq!(moved.from, Position::new(103, 100, 7));
+        assert!(map.tile_at(start).unwrap().thing_index(rat).is_none());
+        assert_eq!(map.tile_at(moved.to).unwrap().thing_index(rat), Some(1));
+        assert_eq!(map.spectators(moved.to, 0, 0, false), vec![rat]);
+
+        // The other creature can take the place of the first
+        map.move_creature(other, start, &item_types).unwrap();
+        assert_eq!(map.spectators(start, 0, 0, false), vec![other]);
+        assert_eq!(map.remove_creature(rat), Some((moved.to, 1)));
+        assert_eq!(map.move_creature(rat, Position::new(102, 100, 7), &item_types), Err(MoveError::NotFound));
+    }
+
+    #[test]
+    fn test_walker() {
+        let item_types = item_types();
+        let map = walk_map();
+        let rat = place(&map, &item_types, 1, Position::new(100, 100, 7));
+        let mut walker = Walker::default();
+        let now = Instant::now();
+
+        let step = walker.step(&map, &item_types, rat, Direction::West, now);
+        let creature_move = match step {
+            StepResult::Moved(creature_move, duration) => {
+                // Onto the road
+                assert_eq!(duration, Duration::from_millis(350));
+                creature_move
+            },
+            step => panic!("expected Moved, got {:?}", step),
+        };
+        assert_eq!(creature_move.to, Position::new(99, 100, 7));
+        assert_eq!(map.things().creature(rat).unwrap().direction, Direction::West);
+
+        assert_eq!(walker.step(&map, &item_types, rat, Direction::West, now + Duration::from_millis(100)),
+            StepResult::Wait(Duration::from_millis(250)));
+
+        let later = now + Duration::from_millis(350);
+        match walker.step(&map, &item_types, rat, Direction::NorthEast, later) {
+            StepResult::Moved(_, duration) => assert_eq!(duration, Duration::from_millis(1500)),
+            step => panic!("expected Moved, got {:?}", step),
+        }
+        assert_eq!(map.things().creature(rat).unwrap().direction, Direction::East);
+
+        let later = later + Duration::from_millis(1500);
+        assert_eq!(walker.step(&map, &item_types, rat, Direction::East, later), StepResult::Cancel(MoveError::Blocked));
+        // Cancelled steps don't have to be waited for
+        assert_eq!(walker.next_step(), Some(later));
+    }
+
+    #[test]
+    fn test_walk_path() {
+        let item_types = item_types();
+        let map = walk_map();
+        let rat = place(&map, &item_types, 1, Position::new(100, 100, 7));
+        let mut walker = Walker::default();
+        let now = Instant::now();
+        assert_eq!(walker.step_path(&map, &item_types, rat, now), None);
+
+        walker.walk(vec![Direction::North, Direction::East, Direction::South]);
+        assert!(matches!(walker.step_path(&map, &item_types, rat, now), Some(StepResult::Moved(..))));
+        assert!(matches!(walker.step_path(&map, &item_types, rat, now), Some(StepResult::Wait(..))));
+        // The wall east of the first step ends the walk
+        let later = walker.next_step().unwrap();
+        assert_eq!(walker.step_path(&map, &item_types, rat, later), Some(StepResult::Cancel(MoveError::Blocked)));
+        assert!(!walker.is_walking());
+        assert_eq!(map.things().creature(rat).unwrap().position, Position::new(100, 99, 7));
+
+        walker.walk(vec![Direction::West, Direction::West]);
+        walker.stop();
+        assert_eq!(walker.step_path(&map, &item_types, rat, later), None);
+    }
+
+    #[test]
+    fn test_move_packets() {
+        let item_types = item_types();
+        let map = walk_map();
+        let rat = place(&map, &item_types, 1, Position::new(100, 100, 7));
+        let creature_move = map.move_creature(rat, Position::new(101, 101, 7), &item_types).unwrap();
+        let describer = MapDescriber::new(&map, &item_types);
+
+        let packets = describer_kinds(&creature_move.own_packets(&describer));
+        assert_eq!(packets, vec!["MoveCreature", "WorldRowSouth", "WorldRowEast"]);
+
+        let spectator_packets = |viewer| describer_kinds(&creature_move.spectator_packets(&describer, viewer));
+        assert_eq!(spectator_packets(Position::new(100, 100, 7)), vec!["MoveCreature"]);
+        assert_eq!(spectator_packets(Position::new(92, 100, 7)), vec!["MoveCreature"]);
+        // The creature walks into the viewport from the west, and out of it to the east
+        assert_eq!(spectator_packets(Position::new(109, 100, 7)), vec!["AddTileThing"]);
+        assert_eq!(spectator_packets(Position::new(91, 100, 7)), vec!["DeleteTileThing"]);
+        assert!(spectator_packets(Position::new(120, 100, 7)).is_empty());
+        // Just below the surface sees 2 floors up
+        assert_eq!(spectator_packets(Position::new(100, 100, 8)), vec!["MoveCreature"]);
+
+        let teleport = map.move_creature(rat, Position::new(96, 96, 7), &item_types).unwrap();
+        assert_eq!(describer_kinds(&teleport.own_packets(&describer)), vec!["DeleteTileThing", "FullWorld"]);
+    }
+
+    fn describer_kinds(packets: &[GameServerPacket]) -> Vec<&'static str> {
+        packets.iter().map(|packet| match packet {
+            GameServerPacket::MoveCreature(_) => "MoveCreature",
+            GameServerPacket::AddTileThing(_) => "AddTileThing",
+            GameServerPacket::DeleteTileThing(_) => "DeleteTileThing",
+            GameServerPacket::FullWorld(_) => "FullWorld",
+            GameServerPacket::WorldRowNorth(_) => "WorldRowNorth",
+            GameServerPacket::WorldRowEast(_) => "WorldRowEast",
+            GameServerPacket::WorldRowSouth(_) => "WorldRowSouth",
+            GameServerPacket::WorldRowWest(_) => "WorldRowWest",
+            packet => panic!("unexpected packet {:?}", packet),
+        }).collect()
+    }
+}
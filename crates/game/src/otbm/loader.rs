@@ -0,0 +1,584 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use anyhow::Context;
+use base::Position;
+
+use crate::{
+    item::{Item, ItemAttribute},
+    map::{Map, Tile, TileFlags, Town, Waypoint},
+};
+use super::{
+    MapInfo,
+    node::{Node, NodeReader, PropReader},
+    attr, node_kind,
+};
+
+/// Progress is reported at most once per this many bytes
+const PROGRESS_INTERVAL: u64 = 1 << 20;
+
+/// Progress of a map being loaded
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub bytes_read: u64,
+    /// Size of the file, if known
+    pub total_bytes: Option<u64>,
+    pub tiles: usize,
+}
+
+/// An error in a node that was skipped while loading, the rest of the map is still loaded
+#[derive(Debug)]
+pub struct NodeError {
+    /// Offset of the node in the file
+    pub offset: u64,
+    /// Position of the tile the node belongs to, if any
+    pub position: Option<Position>,
+    pub error: anyhow::Error,
+}
+
+/// A loaded map together with the header information and the errors of skipped nodes
+pub struct LoadedMap {
+    pub map: Map,
+    pub info: MapInfo,
+    pub errors: Vec<NodeError>,
+}
+
+/// Loads OTBM maps, streaming the nodes straight into the chunks of a Map
+pub struct OtbmLoader<'a> {
+    progress: Option<Box<dyn FnMut(Progress) + 'a>>,
+    total_bytes: Option<u64>,
+}
+
+impl<'a> OtbmLoader<'a> {
+    pub fn new() -> Self {
+        Self {
+            progress: None,
+            total_bytes: None,
+        }
+    }
+
+    /// Calls the callback as the map is loaded, and once when it is done
+    pub fn with_progress<F: FnMut(Progress) + 'a>(mut self, progress: F) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Loads the map from an OTBM file
+    pub fn load_file<P: AsRef<Path>>(mut self, path: P) -> anyhow::Result<LoadedMap> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        self.total_bytes = Some(file.metadata()?.len());
+        self.load(BufReader::new(file))
+    }
+
+    /// Loads the map from a reader positioned at the start of an OTBM file
+    pub fn load<R: BufRead>(mut self, mut reader: R) -> anyhow::Result<LoadedMap> {
+        let mut identifier = [0; 4];
+        reader.read_exact(&mut identifier)?;
+        if identifier != [0; 4] && &identifier != b"OTBM" {
+            anyhow::bail!("not an OTBM file, identifier {:?}", identifier);
+        }
+
+        let mut nodes = NodeReader::new(reader);
+        let root = nodes.root()?;
+        let mut props = PropReader::new(&root.props);
+        let mut info = MapInfo {
+            version: props.u32()?,
+            width: props.u16()?,
+            height: props.u16()?,
+            items_major_version: props.u32()?,
+            items_minor_version: props.u32()?,
+            ..MapInfo::default()
+        };
+
+        let mut state = LoadState {
+            map: Map::new(info.width, info.height),
+            errors: Vec::new(),
+            tiles: 0,
+            last_progress: 0,
+        };
+
+        while let Some(node) = nodes.next_child()? {
+            if node.kind != node_kind::MAP_DATA {
+                state.error(&node, None, anyhow::anyhow!("unexpected node {} in root", node.kind));
+                nodes.skip_children()?;
+                continue;
+            }
+
+            read_map_data(&mut info, &node).context("invalid map data")?;
+            while let Some(node) = nodes.next_child()? {
+                match node.kind {
+                    node_kind::TILE_AREA => self.read_tile_area(&mut nodes, &mut state, node)?,
+                    node_kind::TOWNS => read_towns(&mut nodes, &mut state)?,
+                    node_kind::WAYPOINTS => read_waypoints(&mut nodes, &mut state)?,
+                    kind => {
+                        state.error(&node, None, anyhow::anyhow!("unexpected node {} in map data", kind));
+                        nodes.skip_children()?;
+                    },
+                }
+            }
+        }
+
+        self.report(&nodes, &mut state, true);
+
+        Ok(LoadedMap {
+            map: state.map,
+            info,
+            errors: state.errors,
+        })
+    }
+
+    fn read_tile_area<R: BufRead>(&mut self, nodes: &mut NodeReader<R>, state: &mut LoadState, area: Node) -> anyhow::Result<()> {
+        let base = match PropReader::new(&area.props).position() {
+            Ok(pos) => pos,
+            Err(err) => {
+                state.error(&area, None, err);
+                return nodes.skip_children();
+            },
+        };
+
+        while let Some(node) = nodes.next_child()? {
+            if node.kind != node_kind::TILE && node.kind != node_kind::HOUSE_TILE {
+                state.error(&node, None, anyhow::anyhow!("unexpected node {} in tile area", node.kind));
+                nodes.skip_children()?;
+                continue;
+            }
+
+            let mut props = PropReader::new(&node.props);
+            let pos = match (props.u8(), props.u8()) {
+                (Ok(x), Ok(y)) => Position { x: base.x.wrapping_add(x as u16), y: base.y.wrapping_add(y as u16), z: base.z },
+                _ => {
+                    state.error(&node, None, anyhow::anyhow!("missing tile position"));
+                    nodes.skip_children()?;
+                    continue;
+                },
+            };
+
+            if let Some(tile) = read_tile(nodes, state, &node, pos, &mut props)? {
+                state.map.set_tile(pos, tile);
+                state.tiles += 1;
+            }
+            self.report(nodes, state, false);
+        }
+        Ok(())
+    }
+
+    fn report<R: BufRead>(&mut self, nodes: &NodeReader<R>, state: &mut LoadState, done: bool) {
+        let bytes_read = nodes.offset();
+        if let Some(progress) = &mut self.progress {
+            if done || bytes_read - state.last_progress >= PROGRESS_INTERVAL {
+                state.last_progress = bytes_read;
+                progress(Progress {
+                    bytes_read,
+                    total_bytes: self.total_bytes,
+                    tiles: state.tiles,
+                });
+            }
+        }
+    }
+}
+
+impl<'a> Default for OtbmLoader<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Loads the map from an OTBM file without reporting progress
+pub fn load_otbm<P: AsRef<Path>>(path: P) -> anyhow::Result<LoadedMap> {
+    OtbmLoader::new().load_file(path)
+}
+
+struct LoadState {
+    map: Map,
+    errors: Vec<NodeError>,
+    tiles: usize,
+    last_progress: u64,
+}
+
+impl LoadState {
+    fn error(&mut self, node: &Node, position: Option<Position>, error: anyhow::Error) {
+        self.errors.push(NodeError {
+            offset: node.offset,
+            position,
+            error,
+        });
+    }
+}
+
+fn read_map_data(info: &mut MapInfo, node: &Node) -> anyhow::Result<()> {
+    let mut props = PropReader::new(&node.props);
+    while !props.is_empty() {
+        match props.u8()? {
+            attr::DESCRIPTION => info.descriptions.push(props.string()?),
+            attr::EXT_SPAWN_FILE => info.spawn_file = Some(props.string()?),
+            attr::EXT_HOUSE_FILE => info.house_file = Some(props.string()?),
+            attr => anyhow::bail!("unknown map attribute {}", attr),
+        }
+    }
+    Ok(())
+}
+
+/// Reads the tile and all its items, None if the tile was skipped because of an error
+///
+/// Items with errors are skipped without skipping the rest of the tile.
+fn read_tile<R: BufRead>(
+    nodes: &mut NodeReader<R>,
+    state: &mut LoadState,
+    node: &Node,
+    pos: Position,
+    props: &mut PropReader,
+) -> anyhow::Result<Option<Tile>> {
+    let mut tile = Tile::default();
+    let mut items = Vec::new();
+    if let Err(err) = read_tile_props(node, props, &mut tile, &mut items) {
+        state.error(node, Some(pos), err);
+        nodes.skip_children()?;
+        return Ok(None);
+    }
+
+    while let Some(child) = nodes.next_child()? {
+        if child.kind != node_kind::ITEM {
+            state.error(&child, Some(pos), anyhow::anyhow!("unexpected node {} in tile", child.kind));
+            nodes.skip_children()?;
+            continue;
+        }
+
+        match read_item(nodes, &child)? {
+            Ok(item) => items.push(item),
+            Err(err) => state.error(&child, Some(pos), err.context("invalid item")),
+        }
+    }
+
+    for item in items {
+        tile.push(state.map.add_item(item));
+    }
+    Ok(Some(tile))
+}
+
+fn read_tile_props(node: &Node, props: &mut PropReader, tile: &mut Tile, items: &mut Vec<Item>) -> anyhow::Result<()> {
+    if node.kind == node_kind::HOUSE_TILE {
+        tile.set_house_id(Some(props.u32()?));
+    }
+
+    while !props.is_empty() {
+        match props.u8()? {
+            attr::TILE_FLAGS => tile.set_flags(TileFlags(props.u32()?)),
+            // Items without attributes can be stored inline in the tile
+            attr::ITEM => items.push(Item::new(props.u16()?)),
+            attr => anyhow::bail!("unknown tile attribute {}", attr),
+        }
+    }
+    Ok(())
+}
+
+/// Reads an item node and its contents
+///
+/// The outer result is an error in the node stream, the inner result an error in the item.
+/// The whole item node is consumed in both cases.
+fn read_item<R: BufRead>(nodes: &mut NodeReader<R>, node: &Node) -> anyhow::Result<anyhow::Result<Item>> {
+    let mut item = match read_item_props(node) {
+        Ok(item) => item,
+        Err(err) => {
+            nodes.skip_children()?;
+            return Ok(Err(err));
+        },
+    };
+
+    while let Some(child) = nodes.next_child()? {
+        if child.kind != node_kind::ITEM {
+            nodes.skip_children()?;
+            nodes.skip_children()?;
+            return Ok(Err(anyhow::anyhow!("unexpected node {} in item", child.kind)));
+        }
+
+        match read_item(nodes, &child)? {
+            Ok(content) => item.contents.push(content),
+            Err(err) => {
+                nodes.skip_children()?;
+                return Ok(Err(err.context(format!("invalid content at offset {}", child.offset))));
+            },
+        }
+    }
+
+    Ok(Ok(item))
+}
+
+fn read_item_props(node: &Node) -> anyhow::Result<Item> {
+    let mut props = PropReader::new(&node.props);
+    let mut item = Item::new(props.u16()?);
+    while !props.is_empty() {
+        if let Some(attribute) = read_item_attribute(&mut props)? {
+            item.attributes.push(attribute);
+        }
+    }
+    Ok(item)
+}
+
+/// Reads an attribute of an item, None if the attribute is not kept
+pub(crate) fn read_item_attribute(props: &mut PropReader) -> anyhow::Result<Option<ItemAttribute>> {
+    use ItemAttribute::*;
+
+    Ok(Some(match props.u8()? {
+        attr::COUNT => Count(props.u8()?),
+        attr::ACTION_ID => ActionId(props.u16()?),
+        attr::UNIQUE_ID => UniqueId(props.u16()?),
+        attr::TEXT => Text(props.string()?),
+        attr::DESC => Description(props.string()?),
+        attr::TELE_DEST => TeleportDestination(props.position()?),
+        attr::DEPOT_ID => DepotId(props.u16()?),
+        attr::RUNE_CHARGES => RuneCharges(props.u8()?),
+        attr::HOUSE_DOOR_ID => HouseDoorId(props.u8()?),
+        attr::DURATION => Duration(props.u32()? as i32),
+        attr::DECAYING_STATE => DecayingState(props.u8()?),
+        attr::WRITTEN_DATE => WrittenDate(props.u32()?),
+        attr::WRITTEN_BY => WrittenBy(props.string()?),
+        attr::SLEEPER_GUID => SleeperGuid(props.u32()?),
+        attr::SLEEP_START => SleepStart(props.u32()?),
+        attr::CHARGES => Charges(props.u16()?),
+        attr::CONTAINER_ITEMS => {
+            // Only a hint of the number of contents, the contents are child nodes
+            props.u32()?;
+            return Ok(None);
+        },
+        attr::NAME => Name(props.string()?),
+        attr::ARTICLE => Article(props.string()?),
+        attr::PLURAL_NAME => PluralName(props.string()?),
+        attr::WEIGHT => Weight(props.u32()?),
+        attr::ATTACK => Attack(props.u32()? as i32),
+        attr::DEFENSE => Defense(props.u32()? as i32),
+        attr::EXTRA_DEFENSE => ExtraDefense(props.u32()? as i32),
+        attr::ARMOR => Armor(props.u32()? as i32),
+        attr::HIT_CHANCE => HitChance(props.u8()? as i8),
+        attr::SHOOT_RANGE => ShootRange(props.u8()?),
+        attr::CUSTOM_ATTRIBUTES => Custom(read_custom_attributes(props)?),
+        attr::DECAY_TO => DecayTo(props.u32()? as i32),
+        attr::WRAP_ID => WrapId(props.u16()?),
+        attr::STORE_ITEM => StoreItem(props.u8()?),
+        attr::ATTACK_SPEED => AttackSpeed(props.u32()?),
+        attr => anyhow::bail!("unknown item attribute {}", attr),
+    }))
+}
+
+/// Returns the serialized custom attributes, a u64 count of key, type, value entries
+fn read_custom_attributes(props: &mut PropReader) -> anyhow::Result<Vec<u8>> {
+    let start = props.remaining();
+    let count = props.u64()?;
+    for _ in 0..count {
+        props.string()?;
+        match props.u8()? {
+            1 => { props.string()?; },
+            2 | 3 => { props.bytes(8)?; },
+            4 => { props.u8()?; },
+            kind => anyhow::bail!("unknown custom attribute type {}", kind),
+        }
+    }
+    Ok(start[..start.len() - props.remaining().len()].to_vec())
+}
+
+fn read_towns<R: BufRead>(nodes: &mut NodeReader<R>, state: &mut LoadState) -> anyhow::Result<()> {
+    while let Some(node) = nodes.next_child()? {
+        if node.kind != node_kind::TOWN {
+            state.error(&node, None, anyhow::anyhow!("unexpected node {} in towns", node.kind));
+        } else {
+            match read_town(&node) {
+                Ok(town) => state.map.add_town(town),
+                Err(err) => state.error(&node, None, err.context("invalid town")),
+            }
+        }
+        nodes.skip_children()?;
+    }
+    Ok(())
+}
+
+fn read_town(node: &Node) -> anyhow::Result<Town> {
+    let mut props = PropReader::new(&node.props);
+    Ok(Town {
+        id: props.u32()?,
+        name: props.string()?,
+        temple: props.position()?,
+    })
+}
+
+fn read_waypoints<R: BufRead>(nodes: &mut NodeReader<R>, state: &mut LoadState) -> anyhow::Result<()> {
+    while let Some(node) = nodes.next_child()? {
+        if node.kind != node_kind::WAYPOINT {
+            state.error(&node, None, anyhow::anyhow!("unexpected node {} in waypoints", node.kind));
+        } else {
+            match read_waypoint(&node) {
+                Ok(waypoint) => state.map.add_waypoint(waypoint),
+                Err(err) => state.error(&node, None, err.context("invalid waypoint")),
+            }
+        }
+        nodes.skip_children()?;
+    }
+    Ok(())
+}
+
+fn read_waypoint(node: &Node) -> anyhow::Result<Waypoint> {
+    let mut props = PropReader::new(&node.props);
+    Ok(Waypoint {
+        name: props.string()?,
+        position: props.position()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::otbm::node::{ESCAPE, NODE_END, NODE_START};
+
+    /// Builds OTBM node trees for tests
+    #[derive(Default)]
+    struct Builder(Vec<u8>);
+
+    impl Builder {
+        fn start(&mut self, kind: u8, props: &[u8]) -> &mut Self {
+            self.0.push(NODE_START);
+            self.0.push(kind);
+            for &b in props {
+                if b == NODE_START || b == NODE_END || b == ESCAPE {
+                    self.0.push(ESCAPE);
+                }
+                self.0.push(b);
+            }
+            self
+        }
+
+        fn end(&mut self) -> &mut Self {
+            self.0.push(NODE_END);
+            self
+        }
+    }
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut bytes = (s.len() as u16).to_le_bytes().to_vec();
+        bytes.extend_from_slice(s.as_bytes());
+        bytes
+    }
+
+    fn test_map() -> Vec<u8> {
+        let mut root = vec![2, 0, 0, 0];
+        root.extend_from_slice(&[0, 1, 0, 1]); // 256x256
+        root.extend_from_slice(&[3, 0, 0, 0, 57, 0, 0, 0]);
+
+        let mut map_data = vec![attr::DESCRIPTION];
+        map_data.extend(string("Test map"));
+        map_data.push(attr::EXT_SPAWN_FILE);
+        map_data.extend(string("test-spawn.xml"));
+
+        let mut town = 1u32.to_le_bytes().to_vec();
+        town.extend(string("Rustia"));
+        town.extend_from_slice(&[100, 0, 100, 0, 7]);
+
+        let mut waypoint = string("Temple");
+        waypoint.extend_from_slice(&[100, 0, 101, 0, 7]);
+
+        let mut sign = vec![0xFE, 0x0F, attr::TEXT];
+        sign.extend(string("Welcome"));
+        sign.extend_from_slice(&[attr::ACTION_ID, 0xE8, 0x03]);
+
+        let mut b = Builder(b"OTBM".to_vec());
+        b.start(node_kind::ROOT, &root)
+            .start(node_kind::MAP_DATA, &map_data)
+                .start(node_kind::TILE_AREA, &[0, 1, 0, 1, 7])
+                    // Ground inline, then a sign and a backpack with a stack of coins
+                    .start(node_kind::TILE, &[1, 2, attr::TILE_FLAGS, 1, 0, 0, 0, attr::ITEM, 102, 0])
+                        .start(node_kind::ITEM, &sign).end()
+                        .start(node_kind::ITEM, &[0xDA, 0x07])
+                            .start(node_kind::ITEM, &[0xD7, 0x0B, attr::COUNT, 100]).end()
+                        .end()
+                    .end()
+                    .start(node_kind::HOUSE_TILE, &[2, 2, 5, 0, 0, 0, attr::ITEM, 102, 0]).end()
+                    // The broken item is skipped, the rest of the tile is kept
+                    .start(node_kind::TILE, &[3, 2, attr::ITEM, 102, 0])
+                        .start(node_kind::ITEM, &[0xDA, 0x07, 200]).end()
+                        .start(node_kind::ITEM, &[0xD7, 0x0B]).end()
+                    .end()
+                    // The broken tile is skipped with its children
+                    .start(node_kind::TILE, &[4, 2, 200])
+                        .start(node_kind::ITEM, &[0xD7, 0x0B]).end()
+                    .end()
+                .end()
+                .start(node_kind::TOWNS, &[])
+                    .start(node_kind::TOWN, &town).end()
+                .end()
+                .start(node_kind::WAYPOINTS, &[])
+                    .start(node_kind::WAYPOINT, &waypoint).end()
+                .end()
+            .end()
+        .end();
+        b.0
+    }
+
+    fn items(map: &Map, pos: Position) -> Vec<Item> {
+        let things = map.things();
+        map.tile_at(pos).unwrap().things_iter().map(|id| things.item(*id).unwrap().clone()).collect()
+    }
+
+    #[test]
+    fn test_load() {
+        let mut reports = Vec::new();
+        let loaded = OtbmLoader::new()
+            .with_progress(|progress| reports.push(progress))
+            .load(&test_map()[..])
+            .unwrap();
+
+        assert_eq!(loaded.info, MapInfo {
+            version: 2,
+            width: 256,
+            height: 256,
+            items_major_version: 3,
+            items_minor_version: 57,
+            descriptions: vec!["Test map".to_string()],
+            spawn_file: Some("test-spawn.xml".to_string()),
+            house_file: None,
+        });
+
+        let map = &loaded.map;
+        let pos = Position { x: 257, y: 258, z: 7 };
+        assert_eq!(map.tile_at(pos).unwrap().flags(), TileFlags::PROTECTION_ZONE);
+        assert_eq!(items(map, pos), vec![
+            Item::new(102),
+            Item {
+                id: 0x0FFE,
+                attributes: vec![ItemAttribute::Text("Welcome".to_string()), ItemAttribute::ActionId(1000)],
+                contents: vec![],
+            },
+            Item {
+                id: 2010,
+                attributes: vec![],
+                contents: vec![Item { id: 3031, attributes: vec![ItemAttribute::Count(100)], contents: vec![] }],
+            },
+        ]);
+
+        let house_pos = Position { x: 258, y: 258, z: 7 };
+        assert_eq!(map.tile_at(house_pos).unwrap().house_id(), Some(5));
+        assert_eq!(items(map, house_pos), vec![Item::new(102)]);
+
+        assert_eq!(items(map, Position { x: 259, y: 258, z: 7 }), vec![Item::new(102), Item::new(3031)]);
+        assert!(map.tile_at(Position { x: 260, y: 258, z: 7 }).unwrap().is_empty());
+
+        assert_eq!(loaded.errors.len(), 2);
+        assert_eq!(loaded.errors[0].position, Some(Position { x: 259, y: 258, z: 7 }));
+        assert_eq!(loaded.errors[1].position, Some(Position { x: 260, y: 258, z: 7 }));
+
+        assert_eq!(map.towns(), &[Town { id: 1, name: "Rustia".to_string(), temple: Position { x: 100, y: 100, z: 7 } }]);
+        assert_eq!(map.waypoints(), &[Waypoint { name: "Temple".to_string(), position: Position { x: 100, y: 101, z: 7 } }]);
+
+        let last = reports.last().unwrap();
+        assert_eq!((last.bytes_read, last.tiles), (test_map().len() as u64 - 4, 3));
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        assert!(OtbmLoader::new().load(&b"OTBX"[..]).is_err());
+
+        // Truncated in the middle of the tile area
+        let map = test_map();
+        assert!(OtbmLoader::new().load(&map[..map.len() / 2]).is_err());
+    }
+}
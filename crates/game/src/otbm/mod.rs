@@ -0,0 +1,81 @@
+//! OTBM (OpenTibia binary map) support
+//!
+//! An OTBM file is a tree of nodes. Each node starts with 0xFE and its type, followed by
+//! its properties and children, and ends with 0xFF. 0xFD escapes those bytes in properties.
+
+mod loader;
+mod node;
+mod writer;
+
+pub use loader::{LoadedMap, NodeError, OtbmLoader, Progress, load_otbm};
+pub use node::{Node, NodeReader, PropReader};
+pub use writer::{NodeWriter, save_otbm, write_otbm};
+
+/// Node types
+pub mod node_kind {
+    pub const ROOT: u8 = 0;
+    pub const MAP_DATA: u8 = 2;
+    pub const TILE_AREA: u8 = 4;
+    pub const TILE: u8 = 5;
+    pub const ITEM: u8 = 6;
+    pub const TOWNS: u8 = 12;
+    pub const TOWN: u8 = 13;
+    pub const HOUSE_TILE: u8 = 14;
+    pub const WAYPOINTS: u8 = 15;
+    pub const WAYPOINT: u8 = 16;
+}
+
+/// Attribute types of map data, tile and item nodes
+pub mod attr {
+    pub const DESCRIPTION: u8 = 1;
+    pub const TILE_FLAGS: u8 = 3;
+    pub const ACTION_ID: u8 = 4;
+    pub const UNIQUE_ID: u8 = 5;
+    pub const TEXT: u8 = 6;
+    pub const DESC: u8 = 7;
+    pub const TELE_DEST: u8 = 8;
+    pub const ITEM: u8 = 9;
+    pub const DEPOT_ID: u8 = 10;
+    pub const EXT_SPAWN_FILE: u8 = 11;
+    pub const RUNE_CHARGES: u8 = 12;
+    pub const EXT_HOUSE_FILE: u8 = 13;
+    pub const HOUSE_DOOR_ID: u8 = 14;
+    pub const COUNT: u8 = 15;
+    pub const DURATION: u8 = 16;
+    pub const DECAYING_STATE: u8 = 17;
+    pub const WRITTEN_DATE: u8 = 18;
+    pub const WRITTEN_BY: u8 = 19;
+    pub const SLEEPER_GUID: u8 = 20;
+    pub const SLEEP_START: u8 = 21;
+    pub const CHARGES: u8 = 22;
+    pub const CONTAINER_ITEMS: u8 = 23;
+    pub const NAME: u8 = 30;
+    pub const ARTICLE: u8 = 31;
+    pub const PLURAL_NAME: u8 = 32;
+    pub const WEIGHT: u8 = 33;
+    pub const ATTACK: u8 = 34;
+    pub const DEFENSE: u8 = 35;
+    pub const EXTRA_DEFENSE: u8 = 36;
+    pub const ARMOR: u8 = 37;
+    pub const HIT_CHANCE: u8 = 38;
+    pub const SHOOT_RANGE: u8 = 39;
+    pub const CUSTOM_ATTRIBUTES: u8 = 40;
+    pub const DECAY_TO: u8 = 41;
+    pub const WRAP_ID: u8 = 42;
+    pub const STORE_ITEM: u8 = 43;
+    pub const ATTACK_SPEED: u8 = 44;
+}
+
+/// Header information of an OTBM file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MapInfo {
+    pub version: u32,
+    pub width: u16,
+    pub height: u16,
+    /// Version of the items.otb the map was made with
+    pub items_major_version: u32,
+    pub items_minor_version: u32,
+    pub descriptions: Vec<String>,
+    pub spawn_file: Option<String>,
+    pub house_file: Option<String>,
+}
@@ -0,0 +1,246 @@
+use std::io::BufRead;
+
+use anyhow::Context;
+use base::Position;
+
+pub(crate) const NODE_START: u8 = 0xFE;
+pub(crate) const NODE_END: u8 = 0xFF;
+pub(crate) const ESCAPE: u8 = 0xFD;
+
+/// A node read from an OTBM file, its children are read separately from the NodeReader
+#[derive(Debug)]
+pub struct Node {
+    pub kind: u8,
+    /// Unescaped properties of the node
+    pub props: Vec<u8>,
+    /// Offset of the node in the file
+    pub offset: u64,
+}
+
+/// Streams the node tree of a binary OpenTibia file one node at a time
+///
+/// Only the properties of the current node are kept in memory. Every node returned by
+/// `next_child` must have its children consumed (or skipped) before reading its siblings.
+pub struct NodeReader<R> {
+    reader: R,
+    offset: u64,
+    /// The marker that ended the properties of the last node, or the node before it
+    pending: Option<u8>,
+}
+
+impl<R: BufRead> NodeReader<R> {
+    /// Creates a node reader from a reader positioned after the file identifier
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            offset: 0,
+            pending: None,
+        }
+    }
+
+    /// Returns the number of bytes read so far
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Reads the root node
+    pub fn root(&mut self) -> anyhow::Result<Node> {
+        match self.read_marker()? {
+            Some(NODE_START) => self.read_node(),
+            Some(marker) => anyhow::bail!("expected root node at offset {}, found {:#x}", self.offset, marker),
+            None => anyhow::bail!("missing root node"),
+        }
+    }
+
+    /// Reads the next child of the current node, None when the current node has ended
+    pub fn next_child(&mut self) -> anyhow::Result<Option<Node>> {
+        match self.pending.take() {
+            Some(NODE_START) => Ok(Some(self.read_node()?)),
+            Some(NODE_END) => {
+                // The current node ended, peek what comes after it for the parent
+                self.pending = self.read_marker()?;
+                Ok(None)
+            },
+            _ => anyhow::bail!("unexpected end of node tree at offset {}", self.offset),
+        }
+    }
+
+    /// Skips all remaining children of the current node
+    pub fn skip_children(&mut self) -> anyhow::Result<()> {
+        while self.next_child()?.is_some() {
+            self.skip_children()?;
+        }
+        Ok(())
+    }
+
+    fn read_marker(&mut self) -> anyhow::Result<Option<u8>> {
+        let buf = self.reader.fill_buf()?;
+        let marker = match buf.first() {
+            Some(marker) => *marker,
+            None => return Ok(None),
+        };
+        self.reader.consume(1);
+        self.offset += 1;
+
+        if marker != NODE_START && marker != NODE_END {
+            anyhow::bail!("expected node marker at offset {}, found {:#x}", self.offset - 1, marker);
+        }
+        Ok(Some(marker))
+    }
+
+    /// Reads the type and properties of a node, the start marker has already been read
+    fn read_node(&mut self) -> anyhow::Result<Node> {
+        let offset = self.offset - 1;
+        let mut props = Vec::new();
+        let mut escaped = false;
+        let mut kind = None;
+
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                anyhow::bail!("unexpected end of file in node at offset {}", offset);
+            }
+
+            let mut consumed = 0;
+            let mut marker = None;
+            for &byte in buf {
+                consumed += 1;
+                if escaped {
+                    escaped = false;
+                } else if byte == ESCAPE {
+                    escaped = true;
+                    continue;
+                } else if byte == NODE_START || byte == NODE_END {
+                    marker = Some(byte);
+                    break;
+                }
+
+                match kind {
+                    None => kind = Some(byte),
+                    Some(_) => props.push(byte),
+                }
+            }
+
+            self.reader.consume(consumed);
+            self.offset += consumed as u64;
+
+            if let Some(marker) = marker {
+                self.pending = Some(marker);
+                break;
+            }
+        }
+
+        Ok(Node {
+            kind: kind.with_context(|| format!("node at offset {} has no type", offset))?,
+            props,
+            offset,
+        })
+    }
+}
+
+/// Reads little endian values from the properties of a node
+pub struct PropReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> PropReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns the unread properties
+    pub fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn bytes(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        if self.data.len() < n {
+            anyhow::bail!("unexpected end of properties, needed {} bytes, {} left", n, self.data.len());
+        }
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> anyhow::Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> anyhow::Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(self.u32()? as u64 | (self.u32()? as u64) << 32)
+    }
+
+    /// Reads a string prefixed with its u16 length
+    pub fn string(&mut self) -> anyhow::Result<String> {
+        let len = self.u16()? as usize;
+        // Strings in maps are latin-1 more often than not
+        Ok(self.bytes(len)?.iter().map(|&b| b as char).collect())
+    }
+
+    pub fn position(&mut self) -> anyhow::Result<Position> {
+        Ok(Position {
+            x: self.u16()?,
+            y: self.u16()?,
+            z: self.u8()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_reader() {
+        // root(0, [1]) { child(2, [0xFE escaped]) { grandchild(3) }, child(4) }
+        let data = [
+            0xFE, 0, 1,
+                0xFE, 2, ESCAPE, 0xFE,
+                    0xFE, 3, 0xFF,
+                0xFF,
+                0xFE, 4, 0xFF,
+            0xFF,
+        ];
+        let mut reader = NodeReader::new(&data[..]);
+
+        let root = reader.root().unwrap();
+        assert_eq!((root.kind, root.props.as_slice()), (0, &[1][..]));
+
+        let child = reader.next_child().unwrap().unwrap();
+        assert_eq!((child.kind, child.props.as_slice(), child.offset), (2, &[0xFE][..], 3));
+        assert_eq!(reader.next_child().unwrap().unwrap().kind, 3);
+        assert!(reader.next_child().unwrap().is_none());
+        assert!(reader.next_child().unwrap().is_none());
+
+        assert_eq!(reader.next_child().unwrap().unwrap().kind, 4);
+        reader.skip_children().unwrap();
+
+        assert!(reader.next_child().unwrap().is_none());
+        assert_eq!(reader.offset(), data.len() as u64);
+    }
+
+    #[test]
+    fn test_prop_reader() {
+        let mut props = PropReader::new(&[1, 2, 0, 2, 0, b'h', b'i', 100, 0, 200, 0, 7]);
+        assert_eq!(props.u8().unwrap(), 1);
+        assert_eq!(props.u16().unwrap(), 2);
+        assert_eq!(props.string().unwrap(), "hi");
+        assert_eq!(props.position().unwrap(), Position { x: 100, y: 200, z: 7 });
+        assert!(props.is_empty());
+        assert!(props.u8().is_err());
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use base::{CHUNK_BITS, Position};
+
+use crate::{
+    item::{Item, ItemAttribute},
+    map::{ChunkPosition, MAX_LAYERS, Map, Tile},
+    thing::{Thing, Things},
+};
+use super::{
+    MapInfo,
+    node::{ESCAPE, NODE_END, NODE_START},
+    attr, node_kind,
+};
+
+/// Tile areas cover 256x256 tiles of a floor, tiles store their position relative to the area
+const AREA_BITS: u16 = 8;
+
+/// Writes the nodes of a binary OpenTibia file, escaping the properties
+pub struct NodeWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> NodeWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Starts a node, its properties and children are written until the matching end
+    pub fn start(&mut self, kind: u8) -> anyhow::Result<()> {
+        self.writer.write_all(&[NODE_START])?;
+        self.u8(kind)
+    }
+
+    pub fn end(&mut self) -> anyhow::Result<()> {
+        self.writer.write_all(&[NODE_END])?;
+        Ok(())
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        for &b in bytes {
+            if b == NODE_START || b == NODE_END || b == ESCAPE {
+                self.writer.write_all(&[ESCAPE, b])?;
+            } else {
+                self.writer.write_all(&[b])?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn u8(&mut self, value: u8) -> anyhow::Result<()> {
+        self.bytes(&[value])
+    }
+
+    pub fn u16(&mut self, value: u16) -> anyhow::Result<()> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub fn u32(&mut self, value: u32) -> anyhow::Result<()> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    /// Writes a string prefixed with its u16 length, as latin-1
+    pub fn string(&mut self, value: &str) -> anyhow::Result<()> {
+        let bytes: Vec<u8> = value.chars()
+            .map(|c| if (c as u32) < 256 { c as u8 } else { b'?' })
+            .collect();
+        if bytes.len() > u16::MAX as usize {
+            anyhow::bail!("string of {} characters is too long", bytes.len());
+        }
+        self.u16(bytes.len() as u16)?;
+        self.bytes(&bytes)
+    }
+
+    pub fn position(&mut self, pos: Position) -> anyhow::Result<()> {
+        self.u16(pos.x)?;
+        self.u16(pos.y)?;
+        self.u8(pos.z)
+    }
+
+    /// Flushes and returns the underlying writer
+    pub fn into_inner(mut self) -> anyhow::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Saves the map to an OTBM file
+pub fn save_otbm<P: AsRef<Path>>(path: P, map: &Map, info: &MapInfo) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let file = File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    write_otbm(BufWriter::new(file), map, info)?;
+    Ok(())
+}
+
+/// Writes the map as OTBM, the width and height of the map are used instead of the ones in info
+pub fn write_otbm<W: Write>(mut writer: W, map: &Map, info: &MapInfo) -> anyhow::Result<W> {
+    writer.write_all(&[0; 4])?;
+    let mut nodes = NodeWriter::new(writer);
+
+    nodes.start(node_kind::ROOT)?;
+    nodes.u32(info.version)?;
+    nodes.u16(map.width())?;
+    nodes.u16(map.height())?;
+    nodes.u32(info.items_major_version)?;
+    nodes.u32(info.items_minor_version)?;
+
+    nodes.start(node_kind::MAP_DATA)?;
+    for description in info.descriptions.iter() {
+        nodes.u8(attr::DESCRIPTION)?;
+        nodes.string(description)?;
+    }
+    if let Some(spawn_file) = &info.spawn_file {
+        nodes.u8(attr::EXT_SPAWN_FILE)?;
+        nodes.string(spawn_file)?;
+    }
+    if let Some(house_file) = &info.house_file {
+        nodes.u8(attr::EXT_HOUSE_FILE)?;
+        nodes.string(house_file)?;
+    }
+
+    write_tile_areas(&mut nodes, map)?;
+
+    nodes.start(node_kind::TOWNS)?;
+    for town in map.towns() {
+        nodes.start(node_kind::TOWN)?;
+        nodes.u32(town.id)?;
+        nodes.string(&town.name)?;
+        nodes.position(town.temple)?;
+        nodes.end()?;
+    }
+    nodes.end()?;
+
+    nodes.start(node_kind::WAYPOINTS)?;
+    for waypoint in map.waypoints() {
+        nodes.start(node_kind::WAYPOINT)?;
+        nodes.string(&waypoint.name)?;
+        nodes.position(waypoint.position)?;
+        nodes.end()?;
+    }
+    nodes.end()?;
+
+    nodes.end()?; // map data
+    nodes.end()?; // root
+    nodes.into_inner()
+}
+
+/// Writes the tiles grouped in tile areas, sorted by area and floor so the output is deterministic
+fn write_tile_areas<W: Write>(nodes: &mut NodeWriter<W>, map: &Map) -> anyhow::Result<()> {
+    let mut areas: BTreeMap<(u16, u16), Vec<ChunkPosition>> = BTreeMap::new();
+    for chunk_pos in map.chunk_positions() {
+        let tile_pos = Position { x: chunk_pos.x << CHUNK_BITS, y: chunk_pos.y << CHUNK_BITS, z: 0 };
+        areas.entry((tile_pos.y >> AREA_BITS, tile_pos.x >> AREA_BITS)).or_default().push(chunk_pos);
+    }
+
+    let things = map.things();
+    for ((area_y, area_x), mut chunks) in areas {
+        chunks.sort_by_key(|pos| (pos.y, pos.x));
+        for z in 0..MAX_LAYERS as u8 {
+            let mut started = false;
+            for chunk_pos in chunks.iter() {
+                let chunk = match map.chunk_at(*chunk_pos) {
+                    Some(chunk) => chunk,
+                    None => continue,
+                };
+
+                for (pos, tile) in chunk.tiles().filter(|(pos, _)| pos.z == z) {
+                    let items = tile_items(&things, pos, tile)?;
+                    if items.is_empty() && tile.flags().is_empty() && tile.house_id().is_none() {
+                        continue;
+                    }
+
+                    if !started {
+                        nodes.start(node_kind::TILE_AREA)?;
+                        nodes.position(Position { x: area_x << AREA_BITS, y: area_y << AREA_BITS, z })?;
+                        started = true;
+                    }
+                    write_tile(nodes, pos, tile, &items)?;
+                }
+            }
+
+            if started {
+                nodes.end()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the items of the tile, creatures are not saved
+fn tile_items<'a>(things: &'a Things, pos: Position, tile: &Tile) -> anyhow::Result<Vec<&'a Item>> {
+    let mut items = Vec::new();
+    for id in tile.things_iter() {
+        match things.get(*id) {
+            Some(Thing::Item(item)) => items.push(item),
+            Some(Thing::Creature(_)) => (),
+            None => anyhow::bail!("tile at {:?} has a removed thing {:?}", pos, id),
+        }
+    }
+    Ok(items)
+}
+
+fn write_tile<W: Write>(nodes: &mut NodeWriter<W>, pos: Position, tile: &Tile, items: &[&Item]) -> anyhow::Result<()> {
+    match tile.house_id() {
+        Some(house_id) => {
+            nodes.start(node_kind::HOUSE_TILE)?;
+            nodes.u8(pos.x as u8)?;
+            nodes.u8(pos.y as u8)?;
+            nodes.u32(house_id)?;
+        },
+        None => {
+            nodes.start(node_kind::TILE)?;
+            nodes.u8(pos.x as u8)?;
+            nodes.u8(pos.y as u8)?;
+        },
+    }
+
+    if !tile.flags().is_empty() {
+        nodes.u8(attr::TILE_FLAGS)?;
+        nodes.u32(tile.flags().0)?;
+    }
+
+    let mut items = items.iter().peekable();
+
+    // The ground is usually a plain item, which is stored inline like other map editors do
+    if let Some(item) = items.peek() {
+        if item.attributes.is_empty() && item.contents.is_empty() {
+            nodes.u8(attr::ITEM)?;
+            nodes.u16(item.id)?;
+            items.next();
+        }
+    }
+
+    for item in items {
+        write_item(nodes, item)?;
+    }
+    nodes.end()
+}
+
+fn write_item<W: Write>(nodes: &mut NodeWriter<W>, item: &Item) -> anyhow::Result<()> {
+    nodes.start(node_kind::ITEM)?;
+    nodes.u16(item.id)?;
+    for attribute in item.attributes.iter() {
+        write_item_attribute(nodes, attribute)?;
+    }
+    for content in item.contents.iter() {
+        write_item(nodes, content)?;
+    }
+    nodes.end()
+}
+
+fn write_item_attribute<W: Write>(nodes: &mut NodeWriter<W>, attribute: &ItemAttribute) -> anyhow::Result<()> {
+    use ItemAttribute::*;
+
+    match attribute {
+        Count(count) => { nodes.u8(attr::COUNT)?; nodes.u8(*count) },
+        ActionId(id) => { nodes.u8(attr::ACTION_ID)?; nodes.u16(*id) },
+        UniqueId(id) => { nodes.u8(attr::UNIQUE_ID)?; nodes.u16(*id) },
+        Text(text) => { nodes.u8(attr::TEXT)?; nodes.string(text) },
+        Description(text) => { nodes.u8(attr::DESC)?; nodes.string(text) },
+        TeleportDestination(pos) => { nodes.u8(attr::TELE_DEST)?; nodes.position(*pos) },
+        DepotId(id) => { nodes.u8(attr::DEPOT_ID)?; nodes.u16(*id) },
+        RuneCharges(charges) => { nodes.u8(attr::RUNE_CHARGES)?; nodes.u8(*charges) },
+        HouseDoorId(id) => { nodes.u8(attr::HOUSE_DOOR_ID)?; nodes.u8(*id) },
+        Duration(duration) => { nodes.u8(attr::DURATION)?; nodes.u32(*duration as u32) },
+        DecayingState(state) => { nodes.u8(attr::DECAYING_STATE)?; nodes.u8(*state) },
+        WrittenDate(date) => { nodes.u8(attr::WRITTEN_DATE)?; nodes.u32(*date) },
+        WrittenBy(name) => { nodes.u8(attr::WRITTEN_BY)?; nodes.string(name) },
+        SleeperGuid(guid) => { nodes.u8(attr::SLEEPER_GUID)?; nodes.u32(*guid) },
+        SleepStart(start) => { nodes.u8(attr::SLEEP_START)?; nodes.u32(*start) },
+        Charges(charges) => { nodes.u8(attr::CHARGES)?; nodes.u16(*charges) },
+        Name(name) => { nodes.u8(attr::NAME)?; nodes.string(name) },
+        Article(article) => { nodes.u8(attr::ARTICLE)?; nodes.string(article) },
+        PluralName(name) => { nodes.u8(attr::PLURAL_NAME)?; nodes.string(name) },
+        Weight(weight) => { nodes.u8(attr::WEIGHT)?; nodes.u32(*weight) },
+        Attack(attack) => { nodes.u8(attr::ATTACK)?; nodes.u32(*attack as u32) },
+        Defense(defense) => { nodes.u8(attr::DEFENSE)?; nodes.u32(*defense as u32) },
+        ExtraDefense(defense) => { nodes.u8(attr::EXTRA_DEFENSE)?; nodes.u32(*defense as u32) },
+        Armor(armor) => { nodes.u8(attr::ARMOR)?; nodes.u32(*armor as u32) },
+        HitChance(chance) => { nodes.u8(attr::HIT_CHANCE)?; nodes.u8(*chance as u8) },
+        ShootRange(range) => { nodes.u8(attr::SHOOT_RANGE)?; nodes.u8(*range) },
+        DecayTo(id) => { nodes.u8(attr::DECAY_TO)?; nodes.u32(*id as u32) },
+        WrapId(id) => { nodes.u8(attr::WRAP_ID)?; nodes.u16(*id) },
+        StoreItem(store) => { nodes.u8(attr::STORE_ITEM)?; nodes.u8(*store) },
+        AttackSpeed(speed) => { nodes.u8(attr::ATTACK_SPEED)?; nodes.u32(*speed) },
+        Custom(bytes) => { nodes.u8(attr::CUSTOM_ATTRIBUTES)?; nodes.bytes(bytes) },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        creature::Creature,
+        map::{TileFlags, Town, Waypoint},
+        otbm::OtbmLoader,
+    };
+
+    /// Every tile that would be saved, with its position and items
+    fn tiles(map: &Map) -> Vec<(Position, TileFlags, Option<u32>, Vec<Item>)> {
+        let mut tiles = Vec::new();
+        let things = map.things();
+        for chunk_pos in map.chunk_positions() {
+            let chunk = map.chunk_at(chunk_pos).unwrap();
+            for (pos, tile) in chunk.tiles() {
+                let items: Vec<Item> = tile_items(&things, pos, tile).unwrap().into_iter().cloned().collect();
+                if !items.is_empty() || !tile.flags().is_empty() || tile.house_id().is_some() {
+                    tiles.push((pos, tile.flags(), tile.house_id(), items));
+                }
+            }
+        }
+        tiles.sort_by_key(|(pos, ..)| (pos.z, pos.y, pos.x));
+        tiles
+    }
+
+    fn test_map() -> Map {
+        let mut map = Map::new(1024, 1024);
+
+        let attributes = vec![
+            ItemAttribute::Count(3),
+            ItemAttribute::ActionId(1000),
+            ItemAttribute::UniqueId(0xFEFF),
+            ItemAttribute::Text("Hello\nworld".to_string()),
+            ItemAttribute::Description("A test item".to_string()),
+            ItemAttribute::TeleportDestination(Position { x: 0xFD, y: 0xFE, z: 7 }),
+            ItemAttribute::DepotId(1),
+            ItemAttribute::RuneCharges(5),
+            ItemAttribute::HouseDoorId(2),
+            ItemAttribute::Duration(-1),
+            ItemAttribute::DecayingState(1),
+            ItemAttribute::WrittenDate(1_600_000_000),
+            ItemAttribute::WrittenBy("Rustia".to_string()),
+            ItemAttribute::SleeperGuid(12),
+            ItemAttribute::SleepStart(1_600_000_000),
+            ItemAttribute::Charges(100),
+            ItemAttribute::Name("sword".to_string()),
+            ItemAttribute::Article("a".to_string()),
+            ItemAttribute::PluralName("swords".to_string()),
+            ItemAttribute::Weight(3500),
+            ItemAttribute::Attack(14),
+            ItemAttribute::Defense(12),
+            ItemAttribute::ExtraDefense(-1),
+            ItemAttribute::Armor(2),
+            ItemAttribute::HitChance(-5),
+            ItemAttribute::ShootRange(6),
+            ItemAttribute::DecayTo(2148),
+            ItemAttribute::WrapId(26054),
+            ItemAttribute::StoreItem(1),
+            ItemAttribute::AttackSpeed(1500),
+            // One string entry "key" = "value"
+            ItemAttribute::Custom(vec![
+                1, 0, 0, 0, 0, 0, 0, 0,
+                3, 0, b'k', b'e', b'y', 1, 5, 0, b'v', b'a', b'l', b'u', b'e',
+            ]),
+        ];
+
+        let positions = [
+            Position { x: 100, y: 100, z: 7 },
+            Position { x: 255, y: 255, z: 7 },
+            Position { x: 256, y: 100, z: 7 },
+            Position { x: 100, y: 100, z: 6 },
+            Position { x: 1000, y: 1000, z: 15 },
+        ];
+        for (i, pos) in positions.iter().enumerate() {
+            let mut tile = Tile::default();
+            tile.push(map.add_item(Item::new(102 + i as u16)));
+            map.set_tile(*pos, tile);
+        }
+
+        let mut tile = Tile::default();
+        tile.set_flags(TileFlags(TileFlags::PROTECTION_ZONE.0 | TileFlags::NO_LOGOUT.0));
+        tile.push(map.add_item(Item { id: 0xFDFE, attributes: attributes.clone(), contents: vec![] }));
+        tile.push(map.add_item(Item {
+            id: 2000,
+            attributes: vec![],
+            contents: vec![Item { id: 3031, attributes: vec![ItemAttribute::Count(100)], contents: vec![] }, Item::new(2000)],
+        }));
+        map.set_tile(Position { x: 101, y: 100, z: 7 }, tile);
+
+        // Creatures are not saved
+        let player = map.things_mut().insert(Creature::new(0x1000_0000, "Rustia".to_string()));
+        map.tile_at_mut(positions[0]).unwrap().push(player);
+
+        // House tiles and flagged tiles are kept even without items
+        let mut tile = Tile::default();
+        tile.set_house_id(Some(7));
+        map.set_tile(Position { x: 102, y: 100, z: 7 }, tile);
+
+        map.add_town(Town { id: 1, name: "Rustia".to_string(), temple: Position { x: 100, y: 100, z: 7 } });
+        map.add_waypoint(Waypoint { name: "Temple".to_string(), position: Position { x: 100, y: 100, z: 7 } });
+        map
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let info = MapInfo {
+            version: 2,
+            width: 1024,
+            height: 1024,
+            items_major_version: 3,
+            items_minor_version: 57,
+            descriptions: vec!["Saved by Rustia".to_string()],
+            spawn_file: Some("map-spawn.xml".to_string()),
+            house_file: Some("map-house.xml".to_string()),
+        };
+
+        let map = test_map();
+        let saved = write_otbm(Vec::new(), &map, &info).unwrap();
+
+        let loaded = OtbmLoader::new().load(&saved[..]).unwrap();
+        assert!(loaded.errors.is_empty(), "{:?}", loaded.errors);
+        assert_eq!(loaded.info, info);
+        assert_eq!(tiles(&loaded.map), tiles(&map));
+        assert_eq!(tiles(&map).len(), 7);
+        assert_eq!(loaded.map.towns(), map.towns());
+        assert_eq!(loaded.map.waypoints(), map.waypoints());
+
+        // Saving the loaded map again gives the same file
+        let resaved = write_otbm(Vec::new(), &loaded.map, &loaded.info).unwrap();
+        assert_eq!(resaved, saved);
+    }
+}
@@ -0,0 +1,351 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use ahash::AHashMap;
+use base::{Direction, Position};
+
+use crate::{
+    item::ItemTypes,
+    map::{Map, Tile},
+    thing::{Thing, Things},
+};
+
+/// Nodes expanded before a search gives up, like in TFS
+pub const DEFAULT_MAX_NODES: usize = 512;
+
+const STEP_DIRECTIONS: [Direction; 8] = [
+    Direction::North,
+    Direction::East,
+    Direction::South,
+    Direction::West,
+    Direction::NorthEast,
+    Direction::SouthEast,
+    Direction::SouthWest,
+    Direction::NorthWest,
+];
+
+/// Where a path ends relative to the target position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathTarget {
+    /// On the target
+    Exact,
+    /// Next to the target, diagonally too, e.g to attack in melee
+    Adjacent,
+    /// Between min and max tiles away from the target, diagonally too, e.g to keep distance when attacking from range
+    Distance { min: u16, max: u16 },
+}
+
+impl PathTarget {
+    /// Returns the range of distances to the target where the path ends
+    fn range(self) -> (u16, u16) {
+        match self {
+            PathTarget::Exact => (0, 0),
+            PathTarget::Adjacent => (1, 1),
+            PathTarget::Distance { min, max } => (min, max),
+        }
+    }
+
+    /// Returns true if a path ending at pos reaches the target at target_pos
+    pub fn is_reached(self, pos: Position, target_pos: Position) -> bool {
+        let (min, max) = self.range();
+        pos.z == target_pos.z && (min..=max).contains(&pos.chebyshev_distance(&target_pos))
+    }
+}
+
+struct Node {
+    pos: Position,
+    cost: u32,
+    parent: Option<usize>,
+    /// Expanded, or not walkable
+    closed: bool,
+}
+
+/// Finds paths between tiles of a floor with A*
+///
+/// Straight steps cost 10 and diagonal steps 25 by default, like in TFS.
+#[derive(Debug, Clone)]
+pub struct Pathfinder {
+    straight_cost: u32,
+    diagonal_cost: Option<u32>,
+    max_nodes: usize,
+}
+
+impl Default for Pathfinder {
+    fn default() -> Self {
+        Self {
+            straight_cost: 10,
+            diagonal_cost: Some(25),
+            max_nodes: DEFAULT_MAX_NODES,
+        }
+    }
+}
+
+impl Pathfinder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the cost of straight steps, relative to the diagonal cost
+    pub fn with_straight_cost(mut self, cost: u32) -> Self {
+        self.straight_cost = cost;
+        self
+    }
+
+    /// Sets the cost of diagonal steps, None to only walk straight
+    pub fn with_diagonal_cost(mut self, cost: Option<u32>) -> Self {
+        self.diagonal_cost = cost;
+        self
+    }
+
+    /// Sets the number of nodes expanded before giving up, limiting the time spent on unreachable targets
+    pub fn with_max_nodes(mut self, max_nodes: usize) -> Self {
+        self.max_nodes = max_nodes;
+        self
+    }
+
+    /// Finds the cheapest path from from to the target at to, on tiles where walkable returns true
+    ///
+    /// The start is never checked, so the creature walking doesn't block itself. Returns the steps of the path,
+    /// empty if from already reaches the target, or None if there is no path within the max nodes or on the floor.
+    pub fn find<W>(&self, map: &Map, from: Position, to: Position, target: PathTarget, mut walkable: W) -> Option<Vec<Direction>>
+    where W: FnMut(Position, &Tile, &Things) -> bool
+    {
+        if from.z != to.z {
+            return None;
+        }
+
+        let view = map.view();
+        let mut nodes = vec![Node { pos: from, cost: 0, parent: None, closed: false }];
+        let mut indices = AHashMap::new();
+        indices.insert(from, 0);
+        let mut open = BinaryHeap::new();
+        open.push(Reverse((self.estimate(from, to, target), 0, 0)));
+        let mut expanded = 0;
+
+        while let Some(Reverse((_, cost, index))) = open.pop() {
+            let node = &mut nodes[index];
+            if node.closed || cost > node.cost {
+                continue;
+            }
+            node.closed = true;
+            let pos = node.pos;
+
+            if target.is_reached(pos, to) {
+                return Some(path(&nodes, index));
+            }
+            expanded += 1;
+            if expanded > self.max_nodes {
+                return None;
+            }
+
+            for &direction in STEP_DIRECTIONS.iter() {
+                let step_cost = match (direction.is_diagonal(), self.diagonal_cost) {
+                    (false, _) => self.straight_cost,
+                    (true, Some(cost)) => cost,
+                    (true, None) => continue,
+                };
+                let next = match pos.checked_add(direction.into()) {
+                    Some(next) => next,
+                    None => continue,
+                };
+                let next_cost = cost + step_cost;
+
+                let next_index = match indices.get(&next) {
+                    Some(&next_index) => {
+                        let node = &mut nodes[next_index];
+                        if node.closed || node.cost <= next_cost {
+                            continue;
+                        }
+                        node.cost = next_cost;
+                        node.parent = Some(index);
+                        next_index
+                    },
+                    None => {
+                        let is_walkable = view.tile_at(next).is_some_and(|tile| walkable(next, &tile, view.things()));
+                        indices.insert(next, nodes.len());
+                        nodes.push(Node { pos: next, cost: next_cost, parent: Some(index), closed: !is_walkable });
+                        if !is_walkable {
+                            continue;
+                        }
+                        nodes.len() - 1
+                    },
+                };
+                open.push(Reverse((next_cost + self.estimate(next, to, target), next_cost, next_index)));
+            }
+        }
+
+        None
+    }
+
+    /// Returns the lowest possible cost from pos to reach the target, the heuristic of the search
+    fn estimate(&self, pos: Position, to: Position, target: PathTarget) -> u32 {
+        let (_, max) = target.range();
+        let dx = pos.distance_x(&to).saturating_sub(max) as u32;
+        let dy = pos.distance_y(&to).saturating_sub(max) as u32;
+        let (short, long) = (dx.min(dy), dx.max(dy));
+
+        match self.diagonal_cost {
+            Some(diagonal) if diagonal < 2 * self.straight_cost => diagonal * short + self.straight_cost * (long - short),
+            _ => self.straight_cost * (dx + dy),
+        }
+    }
+}
+
+/// Returns the steps from the start to the node at index
+fn path(nodes: &[Node], mut index: usize) -> Vec<Direction> {
+    let mut directions = Vec::new();
+    while let Some(parent) = nodes[index].parent {
+        directions.push(nodes[parent].pos.direction_to(&nodes[index].pos));
+        index = parent;
+    }
+    directions.reverse();
+    directions
+}
+
+/// Returns true if creatures walk on the tile when finding paths
+///
+/// The tile needs a ground, without items blocking or avoided by pathfinding and without creatures.
+pub fn is_walkable(tile: &Tile, things: &Things, item_types: &ItemTypes) -> bool {
+    let mut has_ground = false;
+    for &id in tile.things_iter() {
+        match things.get(id) {
+            Some(Thing::Creature(_)) => return false,
+            Some(Thing::Item(item)) => match item_types.get(item.id) {
+                Some(item_type) if item_type.is_blocking() || item_type.is_pathfind_blocking() => return false,
+                Some(item_type) if item_type.is_ground() => has_ground = true,
+                _ => {},
+            },
+            None => {},
+        }
+    }
+    has_ground
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{creature::Creature, test_util::{self, item_types}};
+
+    /// A map from rows of tiles starting at (100, 100), see test_util::map
+    fn map(rows: &[&str]) -> Map {
+        test_util::map(Position::new(100, 100, 7), rows)
+    }
+
+    fn find(pathfinder: &Pathfinder, map: &Map, from: (u16, u16), to: (u16, u16), target: PathTarget) -> Option<Vec<Direction>> {
+        let item_types = item_types();
+        let (from, to) = (Position::new(100 + from.0, 100 + from.1, 7), Position::new(100 + to.0, 100 + to.1, 7));
+        pathfinder.find(map, from, to, target, |_, tile, things| is_walkable(tile, things, &item_types))
+    }
+
+    /// Returns where the path ends, to check it without depending on the order of equally cheap steps
+    fn walk(from: (u16, u16), path: &[Direction]) -> (u16, u16) {
+        let pos = path.iter().fold(Position::new(from.0, from.1, 7), |pos, &direction| pos + direction);
+        (pos.x, pos.y)
+    }
+
+    #[test]
+    fn test_straight() {
+        let map = map(&["....."]);
+        let pathfinder = Pathfinder::new();
+        assert_eq!(find(&pathfinder, &map, (0, 0), (4, 0), PathTarget::Exact), Some(vec![Direction::East; 4]));
+        assert_eq!(find(&pathfinder, &map, (4, 0), (0, 0), PathTarget::Adjacent), Some(vec![Direction::West; 3]));
+        assert_eq!(find(&pathfinder, &map, (2, 0), (2, 0), PathTarget::Exact), Some(vec![]));
+        // Off the map and on another floor
+        assert_eq!(find(&pathfinder, &map, (0, 0), (5, 0), PathTarget::Exact), None);
+        assert_eq!(pathfinder.find(&map, Position::new(100, 100, 7), Position::new(101, 100, 6), PathTarget::Exact, |_, _, _| true), None);
+    }
+
+    #[test]
+    fn test_around_walls() {
+        let map = map(&[
+            ".....",
+            ".###.",
+            "...#.",
+            "##.#f",
+            ".....",
+        ]);
+        let pathfinder = Pathfinder::new();
+
+        // Diagonal steps cost more than two straight steps by default
+        let path = find(&pathfinder, &map, (0, 0), (2, 2), PathTarget::Exact).unwrap();
+        assert_eq!(path, vec![Direction::South, Direction::South, Direction::East, Direction::East]);
+        let diagonal = pathfinder.clone().with_diagonal_cost(Some(14));
+        let path = find(&diagonal, &map, (0, 0), (2, 2), PathTarget::Exact).unwrap();
+        assert_eq!(path, vec![Direction::South, Direction::SouthEast, Direction::East]);
+
+        // Around the walls, the field is avoided unless walkable says otherwise
+        let path = find(&pathfinder, &map, (2, 2), (4, 2), PathTarget::Exact).unwrap();
+        assert_eq!(path.len(), 10);
+        assert_eq!(walk((2, 2), &path), (4, 2));
+
+        let item_types = item_types();
+        let field = Position::new(104, 103, 7);
+        let path = pathfinder.find(&map, Position::new(102, 102, 7), Position::new(104, 102, 7), PathTarget::Exact,
+            |pos, tile, things| pos == field || is_walkable(tile, things, &item_types)).unwrap();
+        assert_eq!(path.len(), 6);
+
+        let straight = pathfinder.with_diagonal_cost(None);
+        let path = find(&straight, &map, (0, 4), (4, 2), PathTarget::Exact).unwrap();
+        assert_eq!(path.len(), 14);
+        assert!(path.iter().all(|direction| !direction.is_diagonal()));
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let map = map(&[
+            "..#..",
+            "..#..",
+            "###..",
+        ]);
+        let pathfinder = Pathfinder::new();
+        assert_eq!(find(&pathfinder, &map, (0, 0), (4, 0), PathTarget::Exact), None);
+        assert_eq!(find(&pathfinder, &map, (0, 0), (2, 0), PathTarget::Exact), None);
+        assert!(find(&pathfinder, &map, (1, 0), (2, 0), PathTarget::Adjacent).unwrap().is_empty());
+
+        // Too far for the max nodes
+        let map = self::map(&["...................."]);
+        assert!(find(&pathfinder, &map, (0, 0), (19, 0), PathTarget::Exact).is_some());
+        assert_eq!(find(&pathfinder.with_max_nodes(10), &map, (0, 0), (19, 0), PathTarget::Exact), None);
+    }
+
+    #[test]
+    fn test_creatures() {
+        let item_types = item_types();
+        let map = map(&[
+            "...",
+            "...",
+        ]);
+        let rat = map.things_mut().insert(Creature::new(1, "Rat".to_string()));
+        map.place_creature(rat, Position::new(101, 100, 7), &item_types).unwrap();
+        let player = map.things_mut().insert(Creature::new(2, "Rustia".to_string()));
+        map.place_creature(player, Position::new(100, 100, 7), &item_types).unwrap();
+
+        // The player doesn't block itself, but walks around the rat
+        let pathfinder = Pathfinder::new().with_diagonal_cost(None);
+        let path = find(&pathfinder, &map, (0, 0), (2, 0), PathTarget::Exact).unwrap();
+        assert_eq!(path, vec![Direction::South, Direction::East, Direction::East, Direction::North]);
+        assert_eq!(find(&pathfinder, &map, (0, 0), (1, 0), PathTarget::Exact), None);
+        assert!(find(&pathfinder, &map, (0, 0), (1, 0), PathTarget::Adjacent).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_keep_distance() {
+        let map = map(&[
+            "......",
+            "......",
+            "......",
+        ]);
+        let pathfinder = Pathfinder::new();
+        let target = PathTarget::Distance { min: 3, max: 4 };
+
+        // Closer than min, the path leads away from the target
+        let path = find(&pathfinder, &map, (1, 1), (0, 1), target).unwrap();
+        assert_eq!(path.len(), 2);
+        assert_eq!(walk((1, 1), &path).0, 3);
+        assert!(find(&pathfinder, &map, (4, 0), (0, 1), target).unwrap().is_empty());
+        assert_eq!(find(&pathfinder, &map, (0, 1), (5, 1), PathTarget::Distance { min: 6, max: 8 }), None);
+
+        assert!(target.is_reached(Position::new(3, 3, 7), Position::new(0, 0, 7)));
+        assert!(!target.is_reached(Position::new(5, 0, 7), Position::new(0, 0, 7)));
+        assert!(!target.is_reached(Position::new(3, 3, 6), Position::new(0, 0, 7)));
+    }
+}
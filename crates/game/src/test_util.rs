@@ -0,0 +1,65 @@
+//! Item types and maps shared by the tests and benchmarks, enabled outside of the crate by the test-util feature
+
+use base::Position;
+
+use crate::{
+    item::{Item, ItemFlags, ItemGroup, ItemType, ItemTypes},
+    map::{Map, Tile},
+};
+
+/// Grass, a ground with speed 150
+pub const GRASS: u16 = 102;
+/// Road, a ground with speed 100
+pub const ROAD: u16 = 103;
+/// Wall, blocking creatures and projectiles
+pub const WALL: u16 = 1026;
+/// Field, only avoided by pathfinding
+pub const FIELD: u16 = 1492;
+/// Border, always on top with top order 1
+pub const BORDER: u16 = 4526;
+/// Ladder, always on top with top order 2
+pub const LADDER: u16 = 1948;
+/// Gold coin, stackable
+pub const COIN: u16 = 2148;
+
+/// Returns the item types of the items above
+pub fn item_types() -> ItemTypes {
+    let mut item_types = ItemTypes::new();
+    item_types.insert(ItemType { id: GRASS, group: ItemGroup::Ground, speed: 150, ..ItemType::default() });
+    item_types.insert(ItemType { id: ROAD, group: ItemGroup::Ground, speed: 100, ..ItemType::default() });
+    item_types.insert(ItemType { id: WALL, flags: ItemFlags(ItemFlags::BLOCK_SOLID.0 | ItemFlags::BLOCK_PROJECTILE.0), ..ItemType::default() });
+    item_types.insert(ItemType { id: FIELD, flags: ItemFlags::BLOCK_PATHFIND, ..ItemType::default() });
+    item_types.insert(ItemType { id: BORDER, flags: ItemFlags::ALWAYS_ON_TOP, top_order: 1, ..ItemType::default() });
+    item_types.insert(ItemType { id: LADDER, flags: ItemFlags::ALWAYS_ON_TOP, top_order: 2, ..ItemType::default() });
+    item_types.insert(ItemType { id: COIN, flags: ItemFlags::STACKABLE, ..ItemType::default() });
+    item_types
+}
+
+/// Sets the tile at pos with the items, in stack order
+pub fn set_tile(map: &mut Map, pos: Position, items: &[u16]) {
+    let mut tile = Tile::default();
+    for &id in items {
+        tile.push(map.add_item(Item::new(id)));
+    }
+    map.set_tile(pos, tile);
+}
+
+/// A 200 x 200 map from rows of tiles starting at origin
+///
+/// '.' is grass, 'r' road, '#' a wall on grass and 'f' a field on grass, there is no tile at ' '.
+pub fn map(origin: Position, rows: &[&str]) -> Map {
+    let mut map = Map::new(200, 200);
+    for (y, row) in rows.iter().enumerate() {
+        for (x, c) in row.chars().enumerate() {
+            let items: &[u16] = match c {
+                '.' => &[GRASS],
+                'r' => &[ROAD],
+                '#' => &[GRASS, WALL],
+                'f' => &[GRASS, FIELD],
+                _ => continue,
+            };
+            set_tile(&mut map, Position { x: origin.x + x as u16, y: origin.y + y as u16, ..origin }, items);
+        }
+    }
+    map
+}
@@ -0,0 +1,169 @@
+use ahash::AHashMap;
+use generational_arena::{Arena, Index};
+
+use crate::{creature::Creature, item::Item};
+
+/// Handle to a thing in Things
+///
+/// Handles stay valid while the thing exists. After it's removed the handle no longer resolves,
+/// even if its slot is reused by another thing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ThingId(Index);
+
+/// Anything that can be placed on a tile
+#[derive(Debug, Clone, PartialEq)]
+pub enum Thing {
+    Item(Item),
+    Creature(Creature),
+}
+
+impl Thing {
+    pub fn as_item(&self) -> Option<&Item> {
+        match self {
+            Thing::Item(item) => Some(item),
+            _ => None,
+        }
+    }
+
+    pub fn as_item_mut(&mut self) -> Option<&mut Item> {
+        match self {
+            Thing::Item(item) => Some(item),
+            _ => None,
+        }
+    }
+
+    pub fn as_creature(&self) -> Option<&Creature> {
+        match self {
+            Thing::Creature(creature) => Some(creature),
+            _ => None,
+        }
+    }
+
+    pub fn as_creature_mut(&mut self) -> Option<&mut Creature> {
+        match self {
+            Thing::Creature(creature) => Some(creature),
+            _ => None,
+        }
+    }
+}
+
+impl From<Item> for Thing {
+    fn from(item: Item) -> Self {
+        Thing::Item(item)
+    }
+}
+
+impl From<Creature> for Thing {
+    fn from(creature: Creature) -> Self {
+        Thing::Creature(creature)
+    }
+}
+
+/// Storage of all things, items and creatures, in a generational arena
+#[derive(Debug, Default)]
+pub struct Things {
+    arena: Arena<Thing>,
+    /// Things of creatures by creature id
+    creatures: AHashMap<u32, ThingId>,
+}
+
+impl Things {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a thing, returning its handle
+    pub fn insert<T: Into<Thing>>(&mut self, thing: T) -> ThingId {
+        let thing = thing.into();
+        let creature_id = thing.as_creature().map(|creature| creature.id);
+        let id = ThingId(self.arena.insert(thing));
+        if let Some(creature_id) = creature_id {
+            self.creatures.insert(creature_id, id);
+        }
+        id
+    }
+
+    /// Removes and returns the thing, None if the handle is stale
+    pub fn remove(&mut self, id: ThingId) -> Option<Thing> {
+        let thing = self.arena.remove(id.0)?;
+        if let Thing::Creature(creature) = &thing {
+            self.creatures.remove(&creature.id);
+        }
+        Some(thing)
+    }
+
+    pub fn contains(&self, id: ThingId) -> bool {
+        self.arena.contains(id.0)
+    }
+
+    pub fn get(&self, id: ThingId) -> Option<&Thing> {
+        self.arena.get(id.0)
+    }
+
+    /// Returns the thing to modify, the creature id of creatures must not be changed
+    pub fn get_mut(&mut self, id: ThingId) -> Option<&mut Thing> {
+        self.arena.get_mut(id.0)
+    }
+
+    /// Returns the item, None if the handle is stale or not an item
+    pub fn item(&self, id: ThingId) -> Option<&Item> {
+        self.get(id)?.as_item()
+    }
+
+    pub fn item_mut(&mut self, id: ThingId) -> Option<&mut Item> {
+        self.get_mut(id)?.as_item_mut()
+    }
+
+    /// Returns the creature, None if the handle is stale or not a creature
+    pub fn creature(&self, id: ThingId) -> Option<&Creature> {
+        self.get(id)?.as_creature()
+    }
+
+    pub fn creature_mut(&mut self, id: ThingId) -> Option<&mut Creature> {
+        self.get_mut(id)?.as_creature_mut()
+    }
+
+    /// Returns the handle of the creature with the creature id
+    pub fn creature_by_id(&self, creature_id: u32) -> Option<ThingId> {
+        self.creatures.get(&creature_id).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.arena.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_things() {
+        let mut things = Things::new();
+        let ground = things.insert(Item::new(102));
+        let player = things.insert(Creature::new(0x1000_0000, "Rustia".to_string()));
+        assert_eq!(things.len(), 2);
+
+        assert_eq!(things.item(ground).map(|item| item.id), Some(102));
+        assert!(things.creature(ground).is_none());
+        assert_eq!(things.creature_by_id(0x1000_0000), Some(player));
+
+        things.item_mut(ground).unwrap().attributes.push(crate::item::ItemAttribute::ActionId(1000));
+        assert_eq!(things.item(ground).unwrap().action_id(), Some(1000));
+
+        assert!(matches!(things.remove(player), Some(Thing::Creature(_))));
+        assert!(things.creature_by_id(0x1000_0000).is_none());
+        assert!(things.remove(player).is_none());
+
+        // The slot is reused, but the old handle is stale
+        let coin = things.insert(Item::new(2148));
+        assert!(!things.contains(player));
+        assert!(things.get(player).is_none());
+        assert_ne!(coin, player);
+        assert_eq!(things.len(), 2);
+    }
+}
@@ -0,0 +1,341 @@
+//! Derive macros for the PacketRead and PacketWrite traits in rustia-protocol
+//!
+//! Fields are read and written in declaration order. By default a field uses the PacketRead/PacketWrite
+//! impl of its type, which can be changed with field attributes:
+//!
+//! - `#[packet(bool)]` bool encoded as a u8
+//! - `#[packet(string)]` u16 length prefixed string, for any type that is `From<String>` and `AsRef<str>`
+//! - `#[packet(double(precision = 3))]` f64 encoded as precision byte + u32
+//! - `#[packet(len = "u8")]` Vec with a u8/u16/u32 length prefix (Vec fields default to u16)
+//! - `#[packet(if = "expr")]` Option that is only read when expr is true. The expression can use
+//!   previously read fields by name and the DecodeContext as `ctx`. It's evaluated again when writing, with
+//!   the EncodeContext as `ctx`, and the field must then be Some exactly when it's true.
+//! - `#[packet(since = 1098)]` / `#[packet(until = 1098)]` field that only exists from a protocol version,
+//!   or only before it. The field is left as Default when reading other versions, and skipped when writing them.
+//!
+//! The attributes can be combined, e.g `#[packet(if = "has_speed", double(precision = 3))]` on an `Option<f64>`.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{TokenStream as TokenStream2, TokenTree};
+use quote::{format_ident, quote, ToTokens};
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Expr, Fields, GenericArgument, Lit, Meta,
+    NestedMeta, PathArguments, Type,
+};
+
+#[proc_macro_derive(PacketRead, attributes(packet))]
+pub fn derive_packet_read(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_read(&input).unwrap_or_else(|err| err.to_compile_error()).into()
+}
+
+#[proc_macro_derive(PacketWrite, attributes(packet))]
+pub fn derive_packet_write(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_write(&input).unwrap_or_else(|err| err.to_compile_error()).into()
+}
+
+#[derive(Clone, Copy)]
+enum LenPrefix {
+    U8,
+    U16,
+    U32,
+}
+
+/// How a single value is encoded
+#[derive(Clone, Copy)]
+enum Encoding {
+    Default,
+    Bool,
+    String,
+    Double(u8),
+    Vec(LenPrefix),
+}
+
+struct FieldSpec {
+    encoding: Encoding,
+    condition: Option<Expr>,
+    since: Option<u16>,
+    until: Option<u16>,
+}
+
+impl FieldSpec {
+    /// Version check for fields that don't exist in all protocol versions
+    fn version_condition(&self) -> Option<TokenStream2> {
+        let version = quote!(::rustia_protocol::packet::ProtocolVersion);
+        match (self.since, self.until) {
+            (Some(since), Some(until)) => Some(quote!(ctx.version() >= #version::new(#since) && ctx.version() < #version::new(#until))),
+            (Some(since), None) => Some(quote!(ctx.version() >= #version::new(#since))),
+            (None, Some(until)) => Some(quote!(ctx.version() < #version::new(#until))),
+            (None, None) => None,
+        }
+    }
+}
+
+fn parse_field(field: &syn::Field) -> syn::Result<FieldSpec> {
+    let mut encoding = None;
+    let mut len = None;
+    let mut condition = None;
+    let mut since = None;
+    let mut until = None;
+
+    for attr in field.attrs.iter().filter(|attr| attr.path.is_ident("packet")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(syn::Error::new(meta.span(), "expected #[packet(...)]")),
+        };
+
+        for nested in list.nested.iter() {
+            match nested {
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("bool") => encoding = Some(Encoding::Bool),
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("string") => encoding = Some(Encoding::String),
+                NestedMeta::Meta(Meta::List(list)) if list.path.is_ident("double") => {
+                    encoding = Some(Encoding::Double(parse_precision(list)?));
+                },
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("len") => {
+                    len = Some(match &nv.lit {
+                        Lit::Str(s) if s.value() == "u8" => LenPrefix::U8,
+                        Lit::Str(s) if s.value() == "u16" => LenPrefix::U16,
+                        Lit::Str(s) if s.value() == "u32" => LenPrefix::U32,
+                        lit => return Err(syn::Error::new(lit.span(), "expected \"u8\", \"u16\" or \"u32\"")),
+                    });
+                },
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("if") => {
+                    condition = Some(match &nv.lit {
+                        Lit::Str(s) => s.parse::<Expr>()?,
+                        lit => return Err(syn::Error::new(lit.span(), "expected expression string")),
+                    });
+                },
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("since") => since = Some(parse_version(&nv.lit)?),
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("until") => until = Some(parse_version(&nv.lit)?),
+                nested => return Err(syn::Error::new(nested.span(), "unknown packet attribute")),
+            }
+        }
+    }
+
+    // Vec fields are length prefixed, unwrap Option when conditional
+    let value_type = if condition.is_some() {
+        inner_type(&field.ty, "Option")
+            .ok_or_else(|| syn::Error::new(field.ty.span(), "conditional fields must be Option"))?
+    } else {
+        &field.ty
+    };
+
+    let encoding = match (encoding, len) {
+        (Some(_), Some(_)) => return Err(syn::Error::new(field.span(), "len can only be used on Vec fields")),
+        (Some(encoding), None) => encoding,
+        (None, len) if inner_type(value_type, "Vec").is_some() => Encoding::Vec(len.unwrap_or(LenPrefix::U16)),
+        (None, Some(_)) => return Err(syn::Error::new(field.span(), "len can only be used on Vec fields")),
+        (None, None) => Encoding::Default,
+    };
+
+    Ok(FieldSpec { encoding, condition, since, until })
+}
+
+fn parse_version(lit: &Lit) -> syn::Result<u16> {
+    match lit {
+        Lit::Int(int) => int.base10_parse(),
+        lit => Err(syn::Error::new(lit.span(), "expected protocol version, e.g 1098")),
+    }
+}
+
+fn parse_precision(list: &syn::MetaList) -> syn::Result<u8> {
+    for nested in list.nested.iter() {
+        if let NestedMeta::Meta(Meta::NameValue(nv)) = nested {
+            if nv.path.is_ident("precision") {
+                if let Lit::Int(int) = &nv.lit {
+                    return int.base10_parse();
+                }
+            }
+        }
+    }
+    Err(syn::Error::new(list.span(), "expected double(precision = N)"))
+}
+
+/// Returns T if ty is wrapper<T>
+fn inner_type<'a>(ty: &'a Type, wrapper: &str) -> Option<&'a Type> {
+    let segment = match ty {
+        Type::Path(path) => path.path.segments.last()?,
+        _ => return None,
+    };
+
+    if segment.ident != wrapper {
+        return None;
+    }
+
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first()? {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn read_value(encoding: Encoding) -> TokenStream2 {
+    let ext = quote!(::rustia_protocol::packet::BytesMutExt);
+    match encoding {
+        Encoding::Default => quote!(::rustia_protocol::packet::PacketRead::read_from(data, ctx)?),
+        Encoding::Bool => quote!(#ext::read_u8(data)? != 0),
+        Encoding::String => quote!(::std::convert::From::from(#ext::get_string(data)?)),
+        Encoding::Double(_) => quote!(#ext::get_double(data)?),
+        Encoding::Vec(prefix) => {
+            let len = match prefix {
+                LenPrefix::U8 => quote!(#ext::read_u8(data)? as usize),
+                LenPrefix::U16 => quote!(#ext::read_u16_le(data)? as usize),
+                LenPrefix::U32 => quote!(#ext::read_u32_le(data)? as usize),
+            };
+            quote!({
+                let len = #len;
+                // Every value takes at least a byte, so a bogus length can't reserve more than the packet size
+                let mut values = ::std::vec::Vec::with_capacity(len.min(data.len()));
+                for _ in 0..len {
+                    values.push(::rustia_protocol::packet::PacketRead::read_from(data, ctx)?);
+                }
+                values
+            })
+        },
+    }
+}
+
+fn write_value(encoding: Encoding, value: TokenStream2) -> TokenStream2 {
+    let ext = quote!(::rustia_protocol::packet::BytesMutExt);
+    let buf = quote!(::rustia_protocol::bytes::BufMut);
+    match encoding {
+        Encoding::Default => quote!(::rustia_protocol::packet::PacketWrite::write_to(#value, out, ctx)?;),
+        Encoding::Bool => quote!(#buf::put_u8(out, if *#value { 1 } else { 0 });),
+        Encoding::String => quote!(#ext::put_string(out, ::std::convert::AsRef::<str>::as_ref(#value));),
+        Encoding::Double(precision) => quote!(#ext::put_double(out, *#value, #precision);),
+        Encoding::Vec(prefix) => {
+            let (ty, put) = match prefix {
+                LenPrefix::U8 => (quote!(u8), quote!(put_u8)),
+                LenPrefix::U16 => (quote!(u16), quote!(put_u16_le)),
+                LenPrefix::U32 => (quote!(u32), quote!(put_u32_le)),
+            };
+            quote!({
+                let len = #value.len();
+                let prefix = <#ty as ::std::convert::TryFrom<usize>>::try_from(len)
+                    .map_err(|_| ::rustia_protocol::packet::PacketError::ListTooLong { len, max: #ty::MAX as usize })?;
+                #buf::#put(out, prefix);
+                for value in #value.iter() {
+                    ::rustia_protocol::packet::PacketWrite::write_to(value, out, ctx)?;
+                }
+            })
+        },
+    }
+}
+
+/// Returns true if the tokens contain the identifier, e.g a field used by a condition
+fn uses_ident(tokens: TokenStream2, ident: &syn::Ident) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(token) => token == *ident,
+        TokenTree::Group(group) => uses_ident(group.stream(), ident),
+        _ => false,
+    })
+}
+
+/// Field identifiers for the struct and local variables used while reading
+fn field_names(fields: &Fields) -> Vec<(TokenStream2, syn::Ident)> {
+    fields.iter().enumerate().map(|(i, field)| match &field.ident {
+        Some(ident) => (quote!(#ident), ident.clone()),
+        None => {
+            let index = syn::Index::from(i);
+            (quote!(#index), format_ident!("field_{}", i))
+        },
+    }).collect()
+}
+
+fn struct_fields(input: &DeriveInput) -> syn::Result<&Fields> {
+    match &input.data {
+        Data::Struct(data) => Ok(&data.fields),
+        _ => Err(syn::Error::new(input.span(), "packets can only be derived for structs")),
+    }
+}
+
+fn expand_read(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let fields = struct_fields(input)?;
+    let names = field_names(fields);
+
+    let mut reads = Vec::new();
+    for (field, (_, local)) in fields.iter().zip(names.iter()) {
+        let spec = parse_field(field)?;
+        let mut value = read_value(spec.encoding);
+        if let Some(condition) = &spec.condition {
+            value = quote!(if #condition { Some(#value) } else { None });
+        }
+        if let Some(version) = spec.version_condition() {
+            value = quote!(if #version { #value } else { ::std::default::Default::default() });
+        }
+        reads.push(quote!(let #local = #value;));
+    }
+
+    let locals = names.iter().map(|(_, local)| local);
+    let construct = match fields {
+        Fields::Named(_) => {
+            let members = names.iter().map(|(member, _)| member);
+            quote!(#name { #(#members: #locals),* })
+        },
+        Fields::Unnamed(_) => quote!(#name ( #(#locals),* )),
+        Fields::Unit => quote!(#name),
+    };
+
+    Ok(quote! {
+        impl ::rustia_protocol::packet::PacketRead for #name {
+            #[allow(unused_variables)]
+            fn read_from(data: &mut ::rustia_protocol::bytes::BytesMut, ctx: &::rustia_protocol::packet::DecodeContext)
+                -> ::std::result::Result<Self, ::rustia_protocol::packet::PacketError>
+            where Self: ::std::marker::Sized {
+                #(#reads)*
+                Ok(#construct)
+            }
+        }
+    })
+}
+
+fn expand_write(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let fields = struct_fields(input)?;
+    let names = field_names(fields);
+
+    let mut writes = Vec::new();
+    for (i, (field, (member, _))) in fields.iter().zip(names.iter()).enumerate() {
+        let spec = parse_field(field)?;
+        let mut write = match &spec.condition {
+            Some(condition) => {
+                // The previous fields the condition uses are bound by name, like when reading
+                let bindings = names[..i].iter()
+                    .filter(|(_, local)| uses_ident(condition.to_token_stream(), local))
+                    .map(|(member, local)| quote!(let #local = ::std::clone::Clone::clone(&self.#member);));
+                let write = write_value(spec.encoding, quote!(value));
+                let field_name = member.to_string();
+                quote!({
+                    #(#bindings)*
+                    match (&self.#member, #condition) {
+                        (Some(value), true) => { #write },
+                        (None, false) => {},
+                        _ => return Err(::rustia_protocol::packet::PacketError::ConditionMismatch(#field_name)),
+                    }
+                })
+            },
+            None => write_value(spec.encoding, quote!((&self.#member))),
+        };
+        if let Some(version) = spec.version_condition() {
+            write = quote!(if #version { #write });
+        }
+        writes.push(write);
+    }
+
+    Ok(quote! {
+        impl ::rustia_protocol::packet::PacketWrite for #name {
+            #[allow(unused_variables)]
+            fn write_to(&self, out: &mut ::rustia_protocol::bytes::BytesMut, ctx: &::rustia_protocol::packet::EncodeContext)
+                -> ::std::result::Result<(), ::rustia_protocol::packet::PacketError> {
+                #(#writes)*
+                Ok(())
+            }
+        }
+    })
+}
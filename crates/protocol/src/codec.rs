@@ -1,6 +1,8 @@
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use std::io;
+use std::io::{self, Read, Write};
+use std::num::Wrapping;
 use adler32::adler32;
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
 use tokio_util::codec::{Encoder, Decoder};
 
 use super::util::xtea;
@@ -10,6 +12,13 @@ const CHECKSUM_SIZE: usize = 4;
 const MAX_FRAME_SIZE: usize = 24590;
 const MAX_DATA_SIZE: usize = MAX_FRAME_SIZE - HEADER_SIZE;
 
+/// Set in the sequence number when the payload is deflate compressed
+const COMPRESSED_FLAG: u32 = 1 << 31;
+/// Smaller payloads are not worth compressing
+const COMPRESSION_THRESHOLD: usize = 128;
+/// Limit for inflated payloads, so a small frame can't expand into an unbounded buffer
+const MAX_INFLATED_SIZE: usize = u16::MAX as usize;
+
 #[derive(Debug)]
 enum DecodeState {
     Head,
@@ -20,13 +29,23 @@ enum DecodeState {
 pub enum FrameType {
     Raw,
     LengthPrefixed, // Nonce
-    XTEA([std::num::Wrapping<u32>; 4]),
+    XTEA([Wrapping<u32>; 4]),
+    /// XTEA frames used by newer clients after the handshake
+    ///
+    /// The checksum is replaced by a sequence number, where the highest bit flags a deflate compressed payload.
+    /// The encrypted data starts with the number of padding bytes instead of the payload length.
+    /// Payloads are compressed when encoding if `compress` is set, compressed frames are always decoded.
+    Sequenced {
+        xtea_key: [Wrapping<u32>; 4],
+        compress: bool,
+    },
 }
 
 #[derive(Debug)]
 pub struct TibiaCodec {
     state: DecodeState,
     frame_type: FrameType,
+    sequence: u32,
 }
 
 impl TibiaCodec {
@@ -36,6 +55,7 @@ impl TibiaCodec {
         Self {
             state: DecodeState::Head,
             frame_type: FrameType::Raw,
+            sequence: 0,
         }
     }
 
@@ -43,10 +63,21 @@ impl TibiaCodec {
         self.frame_type
     }
 
+    /// Changes the frame type, the sequence starts over when switching to sequenced frames
     pub fn set_frame_type(&mut self, frame_type: FrameType) {
+        if let FrameType::Sequenced { .. } = frame_type {
+            if !matches!(self.frame_type, FrameType::Sequenced { .. }) {
+                self.sequence = 0;
+            }
+        }
         self.frame_type = frame_type;
     }
 
+    /// Returns the sequence number of the next encoded frame
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
     fn decode_head(&mut self, src: &mut BytesMut) -> io::Result<Option<usize>> {
         if src.len() < HEADER_SIZE {
             return Ok(None);
@@ -73,7 +104,19 @@ impl TibiaCodec {
 
         let mut data = src.split_to(n);
 
+        if data.len() < CHECKSUM_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame too short for checksum"
+            ));
+        }
+
         let recv_checksum = data.split_to(CHECKSUM_SIZE).get_u32_le();
+
+        if let FrameType::Sequenced { xtea_key, .. } = self.frame_type {
+            return Self::decode_sequenced(recv_checksum, &xtea_key, data).map(Some);
+        }
+
         let checksum = match data.remaining() {
             0 => 0,
             _ => adler32(data.as_ref())?,
@@ -87,10 +130,22 @@ impl TibiaCodec {
         }
 
         if let FrameType::XTEA(key) = self.frame_type {
+            if !data.len().is_multiple_of(8) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "XTEA data not multiple of 8"
+                ));
+            }
             xtea::decrypt(&mut data[..], &key);
         }
 
         if let FrameType::LengthPrefixed | FrameType::XTEA(_) = self.frame_type {
+            if data.len() < HEADER_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "frame too short for length header"
+                ));
+            }
             let length = data.split_to(HEADER_SIZE).get_u16_le() as usize;
             if data.len() < length {
                 return Err(io::Error::new(
@@ -104,6 +159,41 @@ impl TibiaCodec {
         Ok(Some(data))
     }
 
+    fn decode_sequenced(sequence: u32, key: &[Wrapping<u32>; 4], mut data: BytesMut) -> io::Result<BytesMut> {
+        if data.is_empty() || !data.len().is_multiple_of(8) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "XTEA data not multiple of 8"
+            ));
+        }
+        xtea::decrypt(&mut data[..], key);
+
+        let padding = data.get_u8() as usize;
+        if padding > data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "padding longer than frame"
+            ));
+        }
+        data.truncate(data.len() - padding);
+
+        if sequence & COMPRESSED_FLAG == 0 {
+            return Ok(data);
+        }
+
+        let mut inflated = Vec::new();
+        DeflateDecoder::new(&data[..])
+            .take(MAX_INFLATED_SIZE as u64 + 1)
+            .read_to_end(&mut inflated)?;
+        if inflated.len() > MAX_INFLATED_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "inflated frame size over limit"
+            ));
+        }
+        Ok(BytesMut::from(&inflated[..]))
+    }
+
     pub fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<BytesMut>> {
         let n = match self.state {
             DecodeState::Head => match self.decode_head(src)? {
@@ -127,19 +217,23 @@ impl TibiaCodec {
     }
 
     pub fn encode(&mut self, packet_data: &[u8], dst: &mut BytesMut) -> Result<(), io::Error> {
+        if let FrameType::Sequenced { xtea_key, compress } = self.frame_type {
+            return self.encode_sequenced(packet_data, &xtea_key, compress, dst);
+        }
+
         // Calculate data size and padding
         let (n, padding) = match self.frame_type {
             FrameType::Raw => (CHECKSUM_SIZE + packet_data.len(), 0),
             FrameType::LengthPrefixed => (CHECKSUM_SIZE + HEADER_SIZE + packet_data.len(), 0),
             FrameType::XTEA(_) => {
                 let n = HEADER_SIZE + packet_data.len();
-                let mut padding = 0;
-                while (n + padding) % 8 != 0 { padding += 1; } // ugly, use math
+                let padding = (8 - n % 8) % 8;
                 (CHECKSUM_SIZE + n + padding, padding)
             }
+            FrameType::Sequenced { .. } => unreachable!("sequenced frames are encoded separately"),
         };
         
-        if n > MAX_DATA_SIZE as usize {
+        if n > MAX_DATA_SIZE {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
                 "input size over limit",
@@ -179,6 +273,49 @@ impl TibiaCodec {
 
         Ok(())
     }
+
+    fn encode_sequenced(&mut self, packet_data: &[u8], key: &[Wrapping<u32>; 4], compress: bool, dst: &mut BytesMut) -> io::Result<()> {
+        let mut sequence = self.sequence;
+        let mut payload = packet_data;
+        let compressed;
+
+        if compress && packet_data.len() >= COMPRESSION_THRESHOLD {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(packet_data)?;
+            compressed = encoder.finish()?;
+            if compressed.len() < packet_data.len() {
+                payload = &compressed;
+                sequence |= COMPRESSED_FLAG;
+            }
+        }
+
+        let n = 1 + payload.len();
+        let padding = (8 - n % 8) % 8;
+        let n = n + padding;
+
+        if CHECKSUM_SIZE + n > MAX_DATA_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "input size over limit",
+            ));
+        }
+
+        dst.reserve(HEADER_SIZE + CHECKSUM_SIZE + n);
+        dst.put_u16_le((CHECKSUM_SIZE + n) as u16);
+        dst.put_u32_le(sequence);
+
+        let mut data = BytesMut::with_capacity(n);
+        data.put_u8(padding as u8);
+        data.extend_from_slice(payload);
+        for _ in 0..padding {
+            data.put_u8(0x33);
+        }
+        xtea::encrypt(&mut data[..], key);
+        dst.extend_from_slice(&data);
+
+        self.sequence = (self.sequence + 1) & !COMPRESSED_FLAG;
+        Ok(())
+    }
 }
 
 impl Default for TibiaCodec {
@@ -211,3 +348,84 @@ impl Encoder<Bytes> for TibiaCodec {
         self.encode(item.as_ref(), dst)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(data: &[u8]) -> BytesMut {
+        let mut src = BytesMut::new();
+        src.put_u16_le(data.len() as u16);
+        src.extend_from_slice(data);
+        src
+    }
+
+    #[test]
+    fn test_short_frames() {
+        let mut codec = TibiaCodec::new();
+        assert!(codec.decode(&mut frame(&[1, 2])).is_err());
+
+        let mut codec = TibiaCodec::new();
+        codec.set_frame_type(FrameType::LengthPrefixed);
+        assert!(codec.decode(&mut frame(&[0, 0, 0, 0])).is_err());
+
+        let mut codec = TibiaCodec::new();
+        codec.set_frame_type(FrameType::XTEA([Wrapping(1); 4]));
+        let data = [9u8, 9, 9];
+        let mut checksummed = BytesMut::new();
+        checksummed.put_u32_le(adler32(&data[..]).unwrap());
+        checksummed.extend_from_slice(&data);
+        assert!(codec.decode(&mut frame(&checksummed)).is_err());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let frame_types = [
+            FrameType::Raw,
+            FrameType::LengthPrefixed,
+            FrameType::XTEA([Wrapping(7); 4]),
+            FrameType::Sequenced { xtea_key: [Wrapping(7); 4], compress: true },
+        ];
+        for frame_type in frame_types.iter() {
+            let mut codec = TibiaCodec::new();
+            codec.set_frame_type(*frame_type);
+
+            let mut dst = BytesMut::new();
+            codec.encode(b"Hello World!", &mut dst).unwrap();
+            let result = codec.decode(&mut dst).unwrap().expect("frame should be complete");
+            assert_eq!(&result[..], b"Hello World!");
+        }
+    }
+
+    #[test]
+    fn test_sequenced() {
+        let frame_type = FrameType::Sequenced { xtea_key: [Wrapping(3); 4], compress: true };
+        let mut codec = TibiaCodec::new();
+        codec.set_frame_type(frame_type);
+
+        let small = b"Hello World!".to_vec();
+        let large = vec![0x42u8; 1000];
+
+        let mut dst = BytesMut::new();
+        codec.encode(&small, &mut dst).unwrap();
+        codec.encode(&large, &mut dst).unwrap();
+        assert_eq!(codec.sequence(), 2);
+
+        // Small payloads are sent uncompressed
+        assert_eq!(u32::from_le_bytes([dst[2], dst[3], dst[4], dst[5]]), 0);
+        let second = HEADER_SIZE + u16::from_le_bytes([dst[0], dst[1]]) as usize;
+        let sequence = u32::from_le_bytes([dst[second + 2], dst[second + 3], dst[second + 4], dst[second + 5]]);
+        assert_eq!(sequence, 1 | COMPRESSED_FLAG);
+        assert!(dst.len() - second < large.len());
+
+        let mut decoder = TibiaCodec::new();
+        decoder.set_frame_type(frame_type);
+        assert_eq!(&decoder.decode(&mut dst).unwrap().unwrap()[..], &small[..]);
+        assert_eq!(&decoder.decode(&mut dst).unwrap().unwrap()[..], &large[..]);
+
+        // Switching back and forth restarts the sequence
+        codec.set_frame_type(FrameType::Raw);
+        codec.set_frame_type(frame_type);
+        assert_eq!(codec.sequence(), 0);
+    }
+}
@@ -1,5 +1,10 @@
+// Lets the derive macros refer to ::rustia_protocol from inside this crate
+extern crate self as rustia_protocol;
+
 pub mod packet;
 pub mod util;
 
 mod codec;
 pub use codec::*;
+
+pub use bytes;
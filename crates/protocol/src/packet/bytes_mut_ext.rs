@@ -1,32 +1,76 @@
 use bytes::{Buf, BufMut, BytesMut};
 use std::num::Wrapping;
-use super::{PacketError, PacketRead, PacketWrite};
-//use std::convert::TryInto;
+use super::{DecodeContext, EncodeContext, PacketError, PacketRead, PacketWrite};
+use std::convert::TryInto;
 
+/// Bounds checked reads and Tibia specific types
+///
+/// All reads return PacketError::UnexpectedEof instead of panicking when there is not enough data,
+/// use these instead of the bytes::Buf getters when reading packets.
 pub trait BytesMutExt {
-    fn peek_u8(&mut self) -> u8;
-    //fn peek_u16_le(&mut self) -> u16;
+    fn ensure_remaining(&self, needed: usize) -> Result<(), PacketError>;
+    fn skip(&mut self, n: usize) -> Result<(), PacketError>;
+
+    fn peek_u8(&mut self) -> Result<u8, PacketError>;
+    fn peek_u16_le(&mut self) -> Result<u16, PacketError>;
+    fn read_u8(&mut self) -> Result<u8, PacketError>;
+    fn read_u16_le(&mut self) -> Result<u16, PacketError>;
+    fn read_u32_le(&mut self) -> Result<u32, PacketError>;
+
     fn get_string(&mut self) -> Result<String, PacketError>;
     fn put_string(&mut self, s: &str);
 
-    fn get_double(&mut self) -> f64;
+    fn get_double(&mut self) -> Result<f64, PacketError>;
     fn put_double(&mut self, value: f64, precision: u8);
 
-    fn get_t<T: PacketRead + Default>(&mut self) -> Result<T, PacketError>;
-    fn put_t<T: PacketWrite>(&mut self, writable: &T) -> Result<(), PacketError>;
+    fn get_t<T: PacketRead + Default>(&mut self, ctx: &DecodeContext) -> Result<T, PacketError>;
+    fn put_t<T: PacketWrite>(&mut self, writable: &T, ctx: &EncodeContext) -> Result<(), PacketError>;
 }
 
 impl BytesMutExt for BytesMut {
-    fn peek_u8(&mut self) -> u8 {
-        *self.as_ref().first().unwrap()
+    fn ensure_remaining(&self, needed: usize) -> Result<(), PacketError> {
+        if self.len() < needed {
+            // The offset is filled in by the packet enum reader, which knows where the packet started
+            return Err(PacketError::UnexpectedEof { offset: 0, needed });
+        }
+        Ok(())
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), PacketError> {
+        self.ensure_remaining(n)?;
+        self.advance(n);
+        Ok(())
+    }
+
+    fn peek_u8(&mut self) -> Result<u8, PacketError> {
+        self.ensure_remaining(1)?;
+        Ok(self[0])
+    }
+
+    fn peek_u16_le(&mut self) -> Result<u16, PacketError> {
+        self.ensure_remaining(2)?;
+        Ok(u16::from_le_bytes(self[..2].try_into().unwrap()))
     }
 
-    /*fn peek_u16_le(&mut self) -> u16 {
-        u16::from_le_bytes(self[..2].try_into().unwrap())
-    }*/
+    fn read_u8(&mut self) -> Result<u8, PacketError> {
+        self.ensure_remaining(1)?;
+        Ok(self.get_u8())
+    }
+
+    fn read_u16_le(&mut self) -> Result<u16, PacketError> {
+        self.ensure_remaining(2)?;
+        Ok(self.get_u16_le())
+    }
+
+    fn read_u32_le(&mut self) -> Result<u32, PacketError> {
+        self.ensure_remaining(4)?;
+        Ok(self.get_u32_le())
+    }
 
     fn get_string(&mut self) -> Result<String, PacketError> {
-        let len = self.get_u16_le() as usize;
+        let len = self.peek_u16_le()? as usize;
+        self.ensure_remaining(2 + len)?;
+        self.advance(2);
         let result = match String::from_utf8(self[..len].to_vec()) {
             Ok(s) => Ok(s),
             Err(_) => Err(PacketError::InvalidString),
@@ -40,23 +84,24 @@ impl BytesMutExt for BytesMut {
         self.put(s.as_bytes());
     }
 
-    fn get_double(&mut self) -> f64 {
+    fn get_double(&mut self) -> Result<f64, PacketError> {
+        self.ensure_remaining(5)?;
         let precision = self.get_u8();
-        let v = Wrapping(self.get_u32_le() as i32) - Wrapping(std::i32::MAX);
-        v.0 as f64 / 10f64.powi(precision as i32)
+        let v = Wrapping(self.get_u32_le() as i32) - Wrapping(i32::MAX);
+        Ok(v.0 as f64 / 10f64.powi(precision as i32))
     }
 
     fn put_double(&mut self, value: f64, precision: u8) {
         self.put_u8(precision);
-        self.put_u32_le(((value * 10f64.powi(precision as i32)) + std::i32::MAX as f64) as u32);
+        self.put_u32_le(((value * 10f64.powi(precision as i32)) + i32::MAX as f64) as u32);
     }
 
-    fn get_t<T: PacketRead + Default>(&mut self) -> Result<T, PacketError> {
-        T::read_from(self)
+    fn get_t<T: PacketRead + Default>(&mut self, ctx: &DecodeContext) -> Result<T, PacketError> {
+        T::read_from(self, ctx)
     }
 
-    fn put_t<T: PacketWrite>(&mut self, writable: &T) -> Result<(), PacketError> {
-        writable.write_to(self)
+    fn put_t<T: PacketWrite>(&mut self, writable: &T, ctx: &EncodeContext) -> Result<(), PacketError> {
+        writable.write_to(self, ctx)
     }
 }
 
@@ -73,6 +118,23 @@ mod tests {
         assert_eq!(result, s);
     }
 
+    #[test]
+    fn test_unexpected_eof() {
+        let mut b = BytesMut::new();
+        b.put_string("Hello World!");
+        b.truncate(8);
+        assert!(matches!(b.get_string(), Err(PacketError::UnexpectedEof { needed: 14, .. })));
+        assert_eq!(b.len(), 8, "failed read should not consume");
+
+        let mut b = BytesMut::from(&[1u8, 2, 3][..]);
+        assert!(matches!(b.read_u32_le(), Err(PacketError::UnexpectedEof { needed: 4, .. })));
+        assert_eq!(b.read_u16_le().unwrap(), 0x0201);
+        assert!(b.peek_u16_le().is_err());
+        assert_eq!(b.read_u8().unwrap(), 3);
+        assert!(b.peek_u8().is_err());
+        assert!(b.get_double().is_err());
+    }
+
     #[test]
     #[allow(clippy::float_cmp)]
     fn test_double_roundtrip() {
@@ -80,7 +142,7 @@ mod tests {
         for test in tests.iter() {
             let mut b = BytesMut::with_capacity(5);
             b.put_double(*test, 3);
-            assert_eq!(*test, b.get_double());
+            assert_eq!(*test, b.get_double().unwrap());
         }
     }
 
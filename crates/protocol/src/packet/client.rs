@@ -1,6 +1,8 @@
 use bytes::{Buf, BufMut, BytesMut};
 use std::num::Wrapping;
-use super::{PacketError, BytesMutExt, util, PacketRead, PacketWrite, PacketPayload};
+use super::{PacketError, BytesMutExt, DecodeContext, EncodeContext, ProtocolVersion, PacketRead, PacketWrite, PacketPayload};
+use crate::util::rsa::RsaKey;
+use base::Direction;
 
 use crate::gen_packet_types;
 
@@ -10,12 +12,53 @@ gen_packet_types!(ClientPacket; ClientPacketKind;
     ( Ping,         29 ),
     ( Pong,         30 ),
 
+    ( AutoWalk,     100 ),
     ( WalkNorth,    101 ),
     ( WalkEast,     102 ),
     ( WalkSouth,    103 ),
-    ( WalkWest,     104 )
+    ( WalkWest,     104 ),
+
+    ( WalkNorthEast, 106 ),
+    ( WalkSouthEast, 107 ),
+    ( WalkSouthWest, 108 ),
+    ( WalkNorthWest, 109 ),
+
+    ( TurnNorth,    111 ),
+    ( TurnEast,     112 ),
+    ( TurnSouth,    113 ),
+    ( TurnWest,     114 ),
+
+    ( StopWalk,     190 )
 );
 
+impl ClientPacket {
+    /// Returns the direction of a single step walk packet
+    pub fn walk_direction(&self) -> Option<Direction> {
+        Some(match self {
+            ClientPacket::WalkNorth(_) => Direction::North,
+            ClientPacket::WalkEast(_) => Direction::East,
+            ClientPacket::WalkSouth(_) => Direction::South,
+            ClientPacket::WalkWest(_) => Direction::West,
+            ClientPacket::WalkNorthEast(_) => Direction::NorthEast,
+            ClientPacket::WalkSouthEast(_) => Direction::SouthEast,
+            ClientPacket::WalkSouthWest(_) => Direction::SouthWest,
+            ClientPacket::WalkNorthWest(_) => Direction::NorthWest,
+            _ => return None,
+        })
+    }
+
+    /// Returns the direction of a turn packet
+    pub fn turn_direction(&self) -> Option<Direction> {
+        Some(match self {
+            ClientPacket::TurnNorth(_) => Direction::North,
+            ClientPacket::TurnEast(_) => Direction::East,
+            ClientPacket::TurnSouth(_) => Direction::South,
+            ClientPacket::TurnWest(_) => Direction::West,
+            _ => return None,
+        })
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Ping;
 impl PacketRead for Ping {}
@@ -47,6 +90,118 @@ impl PacketRead for WalkWest {}
 impl PacketWrite for WalkWest {}
 
 #[derive(Debug, Default)]
+pub struct WalkNorthEast;
+impl PacketRead for WalkNorthEast {}
+impl PacketWrite for WalkNorthEast {}
+
+#[derive(Debug, Default)]
+pub struct WalkSouthEast;
+impl PacketRead for WalkSouthEast {}
+impl PacketWrite for WalkSouthEast {}
+
+#[derive(Debug, Default)]
+pub struct WalkSouthWest;
+impl PacketRead for WalkSouthWest {}
+impl PacketWrite for WalkSouthWest {}
+
+#[derive(Debug, Default)]
+pub struct WalkNorthWest;
+impl PacketRead for WalkNorthWest {}
+impl PacketWrite for WalkNorthWest {}
+
+/// Walks a path of steps, sent when the player clicks a tile to walk to it
+///
+/// The steps have values of their own, counter clockwise starting at east.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AutoWalk {
+    pub directions: Vec<Direction>,
+}
+
+/// Directions of auto walk steps, by their value minus one
+const AUTO_WALK_STEPS: [Direction; 8] = [
+    Direction::East,
+    Direction::NorthEast,
+    Direction::North,
+    Direction::NorthWest,
+    Direction::West,
+    Direction::SouthWest,
+    Direction::South,
+    Direction::SouthEast,
+];
+
+impl PacketRead for AutoWalk {
+    fn read_from(data: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, PacketError>
+    where Self: std::marker::Sized {
+        let count = data.read_u8()?;
+        let directions = (0..count).map(|_| {
+            let value = data.read_u8()?;
+            value.checked_sub(1)
+                .and_then(|index| AUTO_WALK_STEPS.get(index as usize))
+                .copied()
+                .ok_or(PacketError::InvalidDirection(value))
+        }).collect::<Result<_, _>>()?;
+        Ok(AutoWalk { directions })
+    }
+}
+
+impl PacketWrite for AutoWalk {
+    fn write_to(&self, out: &mut BytesMut, _ctx: &EncodeContext) -> Result<(), PacketError> {
+        if self.directions.len() > u8::MAX as usize {
+            return Err(PacketError::PathTooLong(self.directions.len()));
+        }
+
+        out.put_u8(self.directions.len() as u8);
+        for &direction in self.directions.iter() {
+            match AUTO_WALK_STEPS.iter().position(|&step| step == direction) {
+                Some(index) => out.put_u8(index as u8 + 1),
+                None => return Err(PacketError::InvalidDirection(direction as u8)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StopWalk;
+impl PacketRead for StopWalk {}
+impl PacketWrite for StopWalk {}
+
+#[derive(Debug, Default)]
+pub struct TurnNorth;
+impl PacketRead for TurnNorth {}
+impl PacketWrite for TurnNorth {}
+
+#[derive(Debug, Default)]
+pub struct TurnEast;
+impl PacketRead for TurnEast {}
+impl PacketWrite for TurnEast {}
+
+#[derive(Debug, Default)]
+pub struct TurnSouth;
+impl PacketRead for TurnSouth {}
+impl PacketWrite for TurnSouth {}
+
+#[derive(Debug, Default)]
+pub struct TurnWest;
+impl PacketRead for TurnWest {}
+impl PacketWrite for TurnWest {}
+
+/// Reads the client version of a login packet without consuming it
+///
+/// Login packets start with the packet id, client os and client version,
+/// which lets the reader know the protocol version before reading the rest.
+pub fn peek_client_version(data: &[u8]) -> Option<ProtocolVersion> {
+    match data {
+        [_id, _os, _os2, low, high, ..] => Some(ProtocolVersion::new(u16::from_le_bytes([*low, *high]))),
+        _ => None,
+    }
+}
+
+/// Login to the login server
+///
+/// In 8.60 the protocol version, preview state and auth token don't exist,
+/// and content_revision is the dat signature.
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct AccountLogin {
     pub client_os: u16,
     pub client_version: u16,
@@ -62,38 +217,41 @@ pub struct AccountLogin {
 }
 
 impl PacketRead for AccountLogin {
-    fn read_from(data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        let client_os = data.get_u16_le();
-        let client_version = data.get_u16_le();
-        let protocol_version = data.get_u32_le();
-        let content_revision = data.get_u32_le();
-        let spr_signature = data.get_u32_le();
-        let pic_signature = data.get_u32_le();
-        let game_preview_state = data.get_u8();
+        let version = ctx.version();
+        let client_os = data.read_u16_le()?;
+        let client_version = data.read_u16_le()?;
+        let protocol_version = if version >= ProtocolVersion::V1098 { data.read_u32_le()? } else { 0 };
+        let content_revision = data.read_u32_le()?;
+        let spr_signature = data.read_u32_le()?;
+        let pic_signature = data.read_u32_le()?;
+        let game_preview_state = if version >= ProtocolVersion::V1098 { data.read_u8()? } else { 0 };
 
-        util::rsa::rsa_decrypt(&mut data[..128]);
-        if data.get_u8() != 0 {
-            return Err(PacketError::RsaCheckFailed);
-        }
+        decrypt_rsa_block(data, ctx.rsa_key())?;
 
         let xtea_key =  [
-            Wrapping(data.get_u32_le()),
-            Wrapping(data.get_u32_le()),
-            Wrapping(data.get_u32_le()),
-            Wrapping(data.get_u32_le()),
+            Wrapping(data.read_u32_le()?),
+            Wrapping(data.read_u32_le()?),
+            Wrapping(data.read_u32_le()?),
+            Wrapping(data.read_u32_le()?),
         ];
 
         let account_name = data.get_string()?;
         let password = data.get_string()?;
 
-        data.advance(data.remaining() - 128);
-        util::rsa::rsa_decrypt(&mut data[..128]);
-        if data.get_u8() != 0 {
-            return Err(PacketError::RsaCheckFailed);
-        }
+        let mut auth_token = String::new();
+        if version >= ProtocolVersion::V1098 {
+            // The auth token block is always last, skip anything in between
+            let block_size = ctx.rsa_key().block_size();
+            if data.remaining() < block_size {
+                return Err(PacketError::RsaInvalidLength { expected: block_size, actual: data.remaining() });
+            }
+            data.advance(data.remaining() - block_size);
+            decrypt_rsa_block(data, ctx.rsa_key())?;
 
-        let auth_token = data.get_string()?;
+            auth_token = data.get_string()?;
+        }
 
         Ok(AccountLogin {
             client_os,
@@ -111,13 +269,54 @@ impl PacketRead for AccountLogin {
     }
 }
 
+impl AccountLogin {
+    /// Offset of the first RSA block, counted from after the packet id
+    pub fn rsa_block_offset(version: ProtocolVersion) -> usize {
+        if version >= ProtocolVersion::V1098 { 21 } else { 16 }
+    }
+
+    /// Whether the packet ends with an RSA block containing the auth token
+    pub fn has_auth_token(version: ProtocolVersion) -> bool {
+        version >= ProtocolVersion::V1098
+    }
+}
+
 impl PacketWrite for AccountLogin {
-    fn write_to(&self, _out: &mut BytesMut) -> Result<(), PacketError> {
-        todo!()
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+        let version = ctx.version();
+        out.put_u16_le(self.client_os);
+        out.put_u16_le(self.client_version);
+        if version >= ProtocolVersion::V1098 {
+            out.put_u32_le(self.protocol_version);
+        }
+        out.put_u32_le(self.content_revision);
+        out.put_u32_le(self.spr_signature);
+        out.put_u32_le(self.pic_signature);
+        if version >= ProtocolVersion::V1098 {
+            out.put_u8(self.game_preview_state);
+        }
+
+        let mut block = BytesMut::new();
+        put_xtea_key(&mut block, &self.xtea_key);
+        block.put_string(&self.account_name);
+        block.put_string(&self.password);
+        put_rsa_block(out, block, ctx.rsa_key())?;
+
+        if Self::has_auth_token(version) {
+            let mut block = BytesMut::new();
+            block.put_string(&self.auth_token);
+            put_rsa_block(out, block, ctx.rsa_key())?;
+        }
+
+        Ok(())
     }
 }
 
-#[derive(Debug, Default)]
+/// Login to the game server
+///
+/// Before 10.98 there is no session key, the client sends account name, character name and password instead,
+/// followed by the challenge. The protocol version, client type and dat revision don't exist in 8.60.
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct GameLogin {
     pub client_os: u16,
     pub client_version: u16,
@@ -126,57 +325,314 @@ pub struct GameLogin {
     pub dat_revision: u16,
     pub xtea_key: [Wrapping<u32>; 4],
     pub gm_flag: u8,
+    /// Only sent since 10.98
     pub session_key: String,
+    /// Only sent before 10.98
+    pub account_name: String,
     pub character_name: String,
+    /// Only sent before 10.98
+    pub password: String,
     pub challenge_timestamp: u32,
     pub challenge_rand_num: u8,
 }
 
 impl PacketRead for GameLogin {
-    fn read_from(data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        let client_os = data.get_u16_le();
-        let client_version = data.get_u16_le();
-        let protocol_version = data.get_u32_le();
-        let client_type = data.get_u8();
-        let dat_revision = data.get_u16_le();
+        let version = ctx.version();
+        let mut login = GameLogin {
+            client_os: data.read_u16_le()?,
+            client_version: data.read_u16_le()?,
+            ..GameLogin::default()
+        };
 
-        util::rsa::rsa_decrypt(&mut data[..128]);
-        if data.get_u8() != 0 {
-            return Err(PacketError::RsaCheckFailed);
+        if version >= ProtocolVersion::V1098 {
+            login.protocol_version = data.read_u32_le()?;
+            login.client_type = data.read_u8()?;
+            login.dat_revision = data.read_u16_le()?;
         }
 
-        let xtea_key =  [
-            Wrapping(data.get_u32_le()),
-            Wrapping(data.get_u32_le()),
-            Wrapping(data.get_u32_le()),
-            Wrapping(data.get_u32_le()),
+        decrypt_rsa_block(data, ctx.rsa_key())?;
+
+        login.xtea_key =  [
+            Wrapping(data.read_u32_le()?),
+            Wrapping(data.read_u32_le()?),
+            Wrapping(data.read_u32_le()?),
+            Wrapping(data.read_u32_le()?),
         ];
 
-        let gm_flag = data.get_u8();
-        let session_key = data.get_string()?;
-        let character_name = data.get_string()?;
-        let challenge_timestamp = data.get_u32_le();
-        let challenge_rand_num = data.get_u8();
+        login.gm_flag = data.read_u8()?;
+        if version >= ProtocolVersion::V1098 {
+            login.session_key = data.get_string()?;
+            login.character_name = data.get_string()?;
+            login.challenge_timestamp = data.read_u32_le()?;
+            login.challenge_rand_num = data.read_u8()?;
+        } else {
+            login.account_name = data.get_string()?;
+            login.character_name = data.get_string()?;
+            login.password = data.get_string()?;
+            login.challenge_timestamp = data.read_u32_le()?;
+            login.challenge_rand_num = data.read_u8()?;
+        }
 
-        Ok(GameLogin {
-            client_os,
-            client_version,
-            protocol_version,
-            client_type,
-            dat_revision,
-            xtea_key,
-            gm_flag,
-            session_key,
-            character_name,
-            challenge_timestamp,
-            challenge_rand_num,
-        })
+        Ok(login)
+    }
+}
+
+impl GameLogin {
+    /// Offset of the RSA block, counted from after the packet id
+    pub fn rsa_block_offset(version: ProtocolVersion) -> usize {
+        if version >= ProtocolVersion::V1098 { 11 } else { 4 }
     }
 }
 
 impl PacketWrite for GameLogin {
-    fn write_to(&self, _out: &mut BytesMut) -> Result<(), PacketError> {
-        todo!()
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+        let version = ctx.version();
+        out.put_u16_le(self.client_os);
+        out.put_u16_le(self.client_version);
+        if version >= ProtocolVersion::V1098 {
+            out.put_u32_le(self.protocol_version);
+            out.put_u8(self.client_type);
+            out.put_u16_le(self.dat_revision);
+        }
+
+        let mut block = BytesMut::new();
+        put_xtea_key(&mut block, &self.xtea_key);
+        block.put_u8(self.gm_flag);
+        if version >= ProtocolVersion::V1098 {
+            block.put_string(&self.session_key);
+            block.put_string(&self.character_name);
+            block.put_u32_le(self.challenge_timestamp);
+            block.put_u8(self.challenge_rand_num);
+        } else {
+            block.put_string(&self.account_name);
+            block.put_string(&self.character_name);
+            block.put_string(&self.password);
+            block.put_u32_le(self.challenge_timestamp);
+            block.put_u8(self.challenge_rand_num);
+        }
+        put_rsa_block(out, block, ctx.rsa_key())?;
+
+        Ok(())
+    }
+}
+
+fn put_xtea_key(out: &mut BytesMut, key: &[Wrapping<u32>; 4]) {
+    for k in key.iter() {
+        out.put_u32_le(k.0);
+    }
+}
+
+/// Prepends the zero check byte, pads the block with zeroes and writes it RSA encrypted
+fn put_rsa_block(out: &mut BytesMut, data: BytesMut, rsa_key: &RsaKey) -> Result<(), PacketError> {
+    let block_size = rsa_key.block_size();
+    if data.len() >= block_size {
+        return Err(PacketError::RsaBlockOverflow);
+    }
+
+    let mut block = vec![0u8; block_size];
+    block[1..=data.len()].copy_from_slice(&data);
+    rsa_key.encrypt(&mut block)?;
+    out.put_slice(&block);
+    Ok(())
+}
+
+/// Decrypts the RSA block at the start of data in place and consumes the zero check byte
+fn decrypt_rsa_block(data: &mut BytesMut, rsa_key: &RsaKey) -> Result<(), PacketError> {
+    let block_size = rsa_key.block_size();
+    if data.len() < block_size {
+        return Err(PacketError::RsaInvalidLength { expected: block_size, actual: data.len() });
+    }
+
+    rsa_key.decrypt(&mut data[..block_size])?;
+    if data.read_u8()? != 0 {
+        return Err(PacketError::RsaCheckFailed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    const XTEA_KEY: [Wrapping<u32>; 4] = [Wrapping(1), Wrapping(0xDEAD_BEEF), Wrapping(3), Wrapping(u32::MAX)];
+
+    fn roundtrip(packet: ClientPacket, version: ProtocolVersion) -> ClientPacket {
+        let mut data = BytesMut::new();
+        packet.write_to(&mut data, &EncodeContext::with_version(version)).expect("failed to write packet");
+        assert_eq!(peek_client_version(&data), Some(ProtocolVersion::new(1100)));
+
+        let mut ctx = DecodeContext::new();
+        ctx.set_version(version);
+        ClientPacket::read_from(&mut data, &ctx).expect("failed to read packet")
+    }
+
+    #[test]
+    fn test_walk_direction() {
+        for (id, direction) in [(101, Direction::North), (106, Direction::NorthEast), (108, Direction::SouthWest), (109, Direction::NorthWest)].iter() {
+            let packet = ClientPacket::read_from(&mut BytesMut::from(&[*id][..]), &DecodeContext::new()).unwrap();
+            assert_eq!(packet.walk_direction(), Some(*direction));
+        }
+        assert_eq!(ClientPacket::from(Ping).walk_direction(), None);
+
+        let packet = ClientPacket::read_from(&mut BytesMut::from(&[113][..]), &DecodeContext::new()).unwrap();
+        assert_eq!(packet.turn_direction(), Some(Direction::South));
+        assert_eq!(packet.walk_direction(), None);
+        let packet = ClientPacket::read_from(&mut BytesMut::from(&[190][..]), &DecodeContext::new()).unwrap();
+        assert!(matches!(packet, ClientPacket::StopWalk(_)));
+    }
+
+    #[test]
+    fn test_auto_walk() {
+        let mut data = BytesMut::from(&[100, 4, 1, 2, 3, 7][..]);
+        let walk = match ClientPacket::read_from(&mut data, &DecodeContext::new()).unwrap() {
+            ClientPacket::AutoWalk(walk) => walk,
+            packet => panic!("expected AutoWalk, got {:?}", packet),
+        };
+        assert_eq!(walk.directions, vec![Direction::East, Direction::NorthEast, Direction::North, Direction::South]);
+        assert!(data.is_empty());
+
+        let mut out = BytesMut::new();
+        ClientPacket::from(walk).write_to(&mut out, &EncodeContext::new()).unwrap();
+        assert_eq!(&out[..], &[100, 4, 1, 2, 3, 7][..]);
+
+        assert!(matches!(
+            AutoWalk::read_from(&mut BytesMut::from(&[2, 8, 9][..]), &DecodeContext::new()),
+            Err(PacketError::InvalidDirection(9))
+        ));
+        assert!(matches!(
+            AutoWalk { directions: vec![Direction::None] }.write_to(&mut BytesMut::new(), &EncodeContext::new()),
+            Err(PacketError::InvalidDirection(8))
+        ));
+        assert!(matches!(
+            AutoWalk { directions: vec![Direction::North; 256] }.write_to(&mut BytesMut::new(), &EncodeContext::new()),
+            Err(PacketError::PathTooLong(256))
+        ));
+    }
+
+    #[test]
+    fn test_account_login_roundtrip() {
+        let login = AccountLogin {
+            client_os: 2,
+            client_version: 1100,
+            protocol_version: 1100,
+            content_revision: 0x4A10,
+            game_preview_state: 1,
+            xtea_key: XTEA_KEY,
+            account_name: "account".to_string(),
+            password: "secret".to_string(),
+            auth_token: "token".to_string(),
+            ..AccountLogin::default()
+        };
+
+        match roundtrip(login.clone().into(), ProtocolVersion::V1098) {
+            ClientPacket::AccountLogin(result) => assert_eq!(result, login),
+            packet => panic!("expected AccountLogin, got {:?}", packet),
+        }
+
+        // Fields that don't exist in 8.60 are lost
+        let expected = AccountLogin {
+            protocol_version: 0,
+            game_preview_state: 0,
+            auth_token: String::new(),
+            ..login.clone()
+        };
+        match roundtrip(login.into(), ProtocolVersion::V860) {
+            ClientPacket::AccountLogin(result) => assert_eq!(result, expected),
+            packet => panic!("expected AccountLogin, got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn test_game_login_roundtrip() {
+        let login = GameLogin {
+            client_os: 2,
+            client_version: 1100,
+            protocol_version: 1100,
+            xtea_key: XTEA_KEY,
+            session_key: "session".to_string(),
+            account_name: "account".to_string(),
+            character_name: "Rustia".to_string(),
+            password: "password".to_string(),
+            challenge_timestamp: 1_600_000_000,
+            challenge_rand_num: 42,
+            ..GameLogin::default()
+        };
+
+        // The session key replaces account name and password since 10.98
+        let expected = GameLogin {
+            account_name: String::new(),
+            password: String::new(),
+            ..login.clone()
+        };
+        match roundtrip(login.clone().into(), ProtocolVersion::V1098) {
+            ClientPacket::GameLogin(result) => assert_eq!(result, expected),
+            packet => panic!("expected GameLogin, got {:?}", packet),
+        }
+
+        let expected = GameLogin {
+            protocol_version: 0,
+            session_key: String::new(),
+            ..login.clone()
+        };
+        match roundtrip(login.into(), ProtocolVersion::V860) {
+            ClientPacket::GameLogin(result) => assert_eq!(result, expected),
+            packet => panic!("expected GameLogin, got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn test_custom_key() {
+        let key = Arc::new(RsaKey::from_hex("n = C9173F04B92EDAED9C58C672AE55021E01B529EA5BF9B1F32BF83CC8F4821413F2F369BFDA4579DCC1822B7362D674567437E80C6763172B142FE98CE75C4B797F06C1C9E040484F49EE16B9019BDC367628807E71E0806D415DE3633E1A3C06883B6D268E2A1E1738565C4C6EC4BCB590D8ECE7230F2EC724FF280049A72B17\ne = 10001\nd = 1D726F03050F2C7611281ADF5E520A7E5467B01CA69D5906B010A8EE10881FE28FF83F2B1E0E807AE847648F8010C1768B8C4849D0B5FD058CEE3B382C0522F4041DABED73EC7D4221396699ABD7091E418E63970CE1DE85EA3A72CA1F14A9EC913572289D160CF6BE31E6CF388F5AF80C3691B7C3818248499EF2B4E8CCDCF1").expect("failed to parse key"));
+        let login = GameLogin {
+            character_name: "Rustia".to_string(),
+            ..GameLogin::default()
+        };
+
+        let mut data = BytesMut::new();
+        let encode_ctx = EncodeContext::with_rsa_key(Arc::new(key.to_public()));
+        login.write_to(&mut data, &encode_ctx).expect("failed to write packet");
+
+        // The OpenTibia key can't read it
+        assert!(GameLogin::read_from(&mut data.clone(), &DecodeContext::new()).is_err());
+
+        let result = GameLogin::read_from(&mut data, &DecodeContext::with_rsa_key(key)).expect("failed to read packet");
+        assert_eq!(result, login);
+    }
+
+    #[test]
+    fn test_rsa_block_offsets() {
+        let key = RsaKey::open_tibia();
+
+        for &version in [ProtocolVersion::V860, ProtocolVersion::V1098].iter() {
+            let ctx = EncodeContext::with_version(version);
+
+            let mut data = BytesMut::new();
+            ClientPacket::from(AccountLogin::default()).write_to(&mut data, &ctx).unwrap();
+            let offset = 1 + AccountLogin::rsa_block_offset(version);
+            key.decrypt(&mut data[offset..offset + 128]).unwrap();
+            assert_eq!(data[offset], 0);
+
+            let mut data = BytesMut::new();
+            ClientPacket::from(GameLogin::default()).write_to(&mut data, &ctx).unwrap();
+            let offset = 1 + GameLogin::rsa_block_offset(version);
+            key.decrypt(&mut data[offset..offset + 128]).unwrap();
+            assert_eq!(data[offset], 0);
+        }
+    }
+
+    #[test]
+    fn test_rsa_block_overflow() {
+        let login = AccountLogin {
+            account_name: "a".repeat(100),
+            password: "p".repeat(20),
+            ..AccountLogin::default()
+        };
+
+        let mut data = BytesMut::new();
+        assert!(matches!(login.write_to(&mut data, &EncodeContext::new()), Err(PacketError::RsaBlockOverflow)));
     }
 }
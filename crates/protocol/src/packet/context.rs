@@ -0,0 +1,148 @@
+use std::{collections::HashMap, sync::Arc};
+
+use base::Position;
+
+use crate::util::rsa::RsaKey;
+
+use super::ProtocolVersion;
+
+/// Client-side properties of an item type that change how the item is laid out on the wire
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct ItemInfo {
+    /// Followed by a stack size byte
+    pub stackable: bool,
+    /// Splash or fluid container, followed by a fluid byte
+    pub fluid: bool,
+    /// Followed by an animation phase byte
+    pub animated: bool,
+}
+
+/// State needed to decode packets that are not self-describing
+///
+/// Login packets are partly RSA encrypted and need the private key to be read.
+/// Items only carry their client id, so the reader needs to know which item types have extra bytes.
+/// Map descriptions in WorldRow packets depend on the floor the player is standing on.
+/// Everything else depends on the protocol version.
+#[derive(Debug, Clone)]
+pub struct DecodeContext {
+    version: ProtocolVersion,
+    rsa_key: Arc<RsaKey>,
+    items: HashMap<u16, ItemInfo>,
+    player_position: Position,
+}
+
+impl Default for DecodeContext {
+    fn default() -> Self {
+        Self {
+            version: ProtocolVersion::default(),
+            rsa_key: RsaKey::open_tibia(),
+            items: HashMap::new(),
+            player_position: Position::default(),
+        }
+    }
+}
+
+impl DecodeContext {
+    /// Creates a context using the OpenTibia RSA key
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a context using the provided RSA key
+    pub fn with_rsa_key(rsa_key: Arc<RsaKey>) -> Self {
+        Self { rsa_key, ..Self::default() }
+    }
+
+    /// Returns the protocol version packets are read as
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// Sets the protocol version packets are read as, usually after reading the login packet
+    pub fn set_version(&mut self, version: ProtocolVersion) {
+        self.version = version;
+    }
+
+    /// Returns the private key used to decrypt login packets
+    pub fn rsa_key(&self) -> &RsaKey {
+        &self.rsa_key
+    }
+
+    /// Replaces the private key used to decrypt login packets
+    pub fn set_rsa_key(&mut self, rsa_key: Arc<RsaKey>) {
+        self.rsa_key = rsa_key;
+    }
+
+    /// Returns the item info for a client id, unknown items are assumed to have no extra bytes
+    pub fn item_info(&self, client_id: u16) -> ItemInfo {
+        self.items.get(&client_id).copied().unwrap_or_default()
+    }
+
+    /// Sets the item info for a client id
+    pub fn set_item_info(&mut self, client_id: u16, info: ItemInfo) {
+        self.items.insert(client_id, info);
+    }
+
+    /// Returns the last known position of the player
+    pub fn player_position(&self) -> Position {
+        self.player_position
+    }
+
+    /// Updates the last known position of the player
+    pub fn set_player_position(&mut self, position: Position) {
+        self.player_position = position;
+    }
+}
+
+/// State needed to encode packets
+#[derive(Debug, Clone)]
+pub struct EncodeContext {
+    version: ProtocolVersion,
+    rsa_key: Arc<RsaKey>,
+}
+
+impl Default for EncodeContext {
+    fn default() -> Self {
+        Self {
+            version: ProtocolVersion::default(),
+            rsa_key: RsaKey::open_tibia(),
+        }
+    }
+}
+
+impl EncodeContext {
+    /// Creates a context for the default protocol version using the OpenTibia RSA key
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a context for the provided protocol version using the OpenTibia RSA key
+    pub fn with_version(version: ProtocolVersion) -> Self {
+        Self { version, ..Self::default() }
+    }
+
+    /// Creates a context using the provided RSA key
+    pub fn with_rsa_key(rsa_key: Arc<RsaKey>) -> Self {
+        Self { rsa_key, ..Self::default() }
+    }
+
+    /// Returns the protocol version packets are written as
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// Sets the protocol version packets are written as
+    pub fn set_version(&mut self, version: ProtocolVersion) {
+        self.version = version;
+    }
+
+    /// Returns the public key used to encrypt login packets
+    pub fn rsa_key(&self) -> &RsaKey {
+        &self.rsa_key
+    }
+
+    /// Replaces the public key used to encrypt login packets
+    pub fn set_rsa_key(&mut self, rsa_key: Arc<RsaKey>) {
+        self.rsa_key = rsa_key;
+    }
+}
@@ -1,16 +1,18 @@
 
-use bytes::{Buf, BufMut, BytesMut};
-use super::{PacketError, BytesMutExt, PacketRead, PacketWrite, PacketPayload};
-use base::Position;
+use bytes::{BufMut, BytesMut};
+use super::{PacketError, BytesMutExt, DecodeContext, EncodeContext, ProtocolVersion, PacketRead, PacketWrite, PacketPayload};
+use base::{Direction, Position};
+use std::ops::RangeInclusive;
 
 use crate::gen_packet_types;
 
 gen_packet_types!(GameServerPacket; GameServerPacketKind;
-    ( Nonce,               31  ),
-    ( LoginSuccess,        23  ),
-    ( PendingStateEntered, 10  ),
-    ( EnterWorld,          15  ),
-    ( PlayerDataBasic,     159 ),
+    ( Nonce,               31, since 854  ),
+    ( LoginError,          20  ),
+    ( LoginSuccess,        23, 10 before 1098 ),
+    ( PendingStateEntered, 10, since 1098 ),
+    ( EnterWorld,          15, since 1098 ),
+    ( PlayerDataBasic,     159, since 1098 ),
     ( WorldLight,          130 ),
     ( CreatureLight,       141 ),
     ( Ping,                29  ),
@@ -23,10 +25,15 @@ gen_packet_types!(GameServerPacket; GameServerPacketKind;
     ( WorldRowWest,        104 ),
 
     ( AddTileThing,        106 ),
-    //( UpdateTileThing,     107 ),
+    ( UpdateTileThing,     107 ),
     ( DeleteTileThing,     108 ),
 
-    ( MoveCreature,        109 )
+    ( MoveCreature,        109 ),
+
+    ( CancelWalk,          181 ),
+
+    ( FloorChangeUp,       190 ),
+    ( FloorChangeDown,     191 )
 );
 
 #[derive(Debug, Default, Clone)]
@@ -49,124 +56,76 @@ pub struct EnterWorld;
 impl PacketRead for EnterWorld {}
 impl PacketWrite for EnterWorld {}
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PacketRead, PacketWrite)]
 pub struct MoveCreature {
     pub old_position: Position,
     pub old_stack_index: u8,
     pub new_position: Position,
 }
 
-impl PacketRead for MoveCreature {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
-    where Self: std::marker::Sized {
-        todo!()
-    }
+/// Cancels the step the client started, turning the player to direction
+#[derive(Debug, Default, Clone, PacketRead, PacketWrite)]
+pub struct CancelWalk {
+    pub direction: Direction,
 }
 
-impl PacketWrite for MoveCreature {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_t(&self.old_position)?;
-        out.put_u8(self.old_stack_index);
-        out.put_t(&self.new_position)?;
-        Ok(())
-    }
-}
+/// Disconnects the client during the game login with an error message
+#[derive(Debug, Default, Clone, PacketRead, PacketWrite)]
+pub struct LoginError(#[packet(string)] pub String);
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PacketRead, PacketWrite)]
 pub struct Nonce {
     pub timestamp: u32,
     pub random_number: u8,
 }
 
-impl PacketRead for Nonce {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
-    where Self: std::marker::Sized {
-        todo!()
-    }
-}
-
-impl PacketWrite for Nonce {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_u32_le(self.timestamp);
-        out.put_u8(self.random_number);
-        Ok(())
-    }
-}
-
-#[derive(Debug, Default, Clone)]
+/// Sent when the player enters the game, the first packet after GameLogin
+///
+/// Before 10.98 it only contains the player id, beat duration and is_tutor.
+#[derive(Debug, Default, Clone, PacketRead, PacketWrite)]
 pub struct LoginSuccess {
     pub player_id: u32,
     pub beat_duration: u16,
+    #[packet(double(precision = 3), since = 1098)]
     pub speed_a: f64,
+    #[packet(double(precision = 3), since = 1098)]
     pub speed_b: f64,
+    #[packet(double(precision = 3), since = 1098)]
     pub speed_c: f64,
+    #[packet(bool)]
     pub is_tutor: bool,
+    #[packet(bool, since = 1098)]
     pub pvp_framing: bool,
+    #[packet(bool, since = 1098)]
     pub expert_mode: bool,
+    #[packet(string, since = 1098)]
     pub store_img_url: String,
+    #[packet(since = 1098)]
     pub coin_package_size: u16,
 }
 
-impl PacketRead for LoginSuccess {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
-    where Self: std::marker::Sized {
-        todo!()
-    }
-}
-
-impl PacketWrite for LoginSuccess {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_u32_le(self.player_id);
-        out.put_u16_le(self.beat_duration);
-        out.put_double(self.speed_a, 3);
-        out.put_double(self.speed_b, 3);
-        out.put_double(self.speed_c, 3);
-        out.put_u8(if self.is_tutor { 1 } else { 0 });
-        out.put_u8(if self.pvp_framing { 1 } else { 0 });
-        out.put_u8(if self.expert_mode { 1 } else { 0 });
-        out.put_string(&self.store_img_url);
-        out.put_u16_le(self.coin_package_size);
-        Ok(())
-    }
-}
-
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PacketRead, PacketWrite)]
 pub struct PlayerDataBasic {
+    #[packet(bool)]
     pub is_premium: bool,
     pub premium_until: u32,
     pub vocation_id: u8,
     pub known_spells: Vec<u8>,
 }
 
-impl PacketRead for PlayerDataBasic {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
-    where Self: std::marker::Sized {
-        todo!()
-    }
-}
-
-impl PacketWrite for PlayerDataBasic {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_u8(if self.is_premium { 1 } else { 0 });
-        out.put_u32_le(self.premium_until);
-        out.put_u8(self.vocation_id);
-        out.put_u16(self.known_spells.len() as u16);
-        for spell_id in self.known_spells.iter() {
-            out.put_u8(*spell_id);
-        }
-        Ok(())
-    }
-}
-
 impl PacketRead for Position {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        todo!()
+        Ok(Position {
+            x: data.read_u16_le()?,
+            y: data.read_u16_le()?,
+            z: data.read_u8()?,
+        })
     }
 }
 
 impl PacketWrite for Position {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
+    fn write_to(&self, out: &mut BytesMut, _ctx: &EncodeContext) -> Result<(), PacketError> {
         out.put_u16_le(self.x);
         out.put_u16_le(self.y);
         out.put_u8(self.z);
@@ -174,27 +133,27 @@ impl PacketWrite for Position {
     }
 }
 
-#[derive(Debug, Default, Clone)]
-pub struct LightInfo {
-    pub light_level: u8,
-    pub light_color: u8,
-}
-
-impl PacketRead for LightInfo {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+impl PacketRead for Direction {
+    fn read_from(data: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        todo!()
+        let value = data.read_u8()?;
+        Direction::try_from(value).map_err(|_| PacketError::InvalidDirection(value))
     }
 }
 
-impl PacketWrite for LightInfo {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_u8(self.light_level);
-        out.put_u8(self.light_color);
+impl PacketWrite for Direction {
+    fn write_to(&self, out: &mut BytesMut, _ctx: &EncodeContext) -> Result<(), PacketError> {
+        out.put_u8(*self as u8);
         Ok(())
     }
 }
 
+#[derive(Debug, Default, Clone, PacketRead, PacketWrite)]
+pub struct LightInfo {
+    pub light_level: u8,
+    pub light_color: u8,
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Item {
     pub client_id: u16,
@@ -204,16 +163,36 @@ pub struct Item {
 }
 
 impl PacketRead for Item {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        todo!()
+        let client_id = data.read_u16_le()?;
+        if has_item_mark(ctx.version()) {
+            data.read_u8()?; // MARK_UNMARKED
+        }
+
+        let info = ctx.item_info(client_id);
+        let mut item = Item { client_id, ..Item::default() };
+
+        if info.stackable {
+            item.stack_size = Some(data.read_u8()?);
+        } else if info.fluid {
+            item.fluid = Some(data.read_u8()?);
+        }
+
+        if info.animated && ctx.version() >= ProtocolVersion::V1098 {
+            item.animation = Some(data.read_u8()?);
+        }
+
+        Ok(item)
     }
 }
 
 impl PacketWrite for Item {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
         out.put_u16_le(self.client_id);
-        out.put_u8(0xFF); // MARK_UNMARKED  TODO: (from TFS, dont know what it means)
+        if has_item_mark(ctx.version()) {
+            out.put_u8(0xFF); // MARK_UNMARKED  TODO: (from TFS, dont know what it means)
+        }
         
         if let Some(stack_size) = self.stack_size {
             out.put_u8(stack_size);
@@ -222,14 +201,21 @@ impl PacketWrite for Item {
             out.put_u8(fluid);
         }
 
-        if let Some(animation) = self.animation {
-            out.put_u8(animation);
+        match self.animation {
+            Some(animation) if ctx.version() >= ProtocolVersion::V1098 => out.put_u8(animation),
+            _ => (),
         }
 
         Ok(())
     }
 }
 
+/// Items have a mark byte after the client id in 10.98, which doesn't exist in 8.60
+fn has_item_mark(version: ProtocolVersion) -> bool {
+    version >= ProtocolVersion::V1098
+}
+
+/// Mounts don't exist in 8.60
 #[derive(Debug, Clone)]
 pub enum Outfit {
     LookType {
@@ -254,15 +240,41 @@ impl Default for Outfit {
 }
 
 impl PacketRead for Outfit {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        todo!()
+        let look_type = data.read_u16_le()?;
+        let mut outfit = if look_type != 0 {
+            Outfit::LookType {
+                look_type,
+                head: data.read_u8()?,
+                body: data.read_u8()?,
+                legs: data.read_u8()?,
+                feet: data.read_u8()?,
+                addons: data.read_u8()?,
+                mount: 0,
+            }
+        } else {
+            Outfit::Item {
+                client_id: data.read_u16_le()?,
+                mount: 0,
+            }
+        };
+
+        if ctx.version() >= ProtocolVersion::V1098 {
+            match outfit {
+                Outfit::LookType { ref mut mount, .. } | Outfit::Item { ref mut mount, .. } => {
+                    *mount = data.read_u16_le()?;
+                },
+            }
+        }
+
+        Ok(outfit)
     }
 }
 
 impl PacketWrite for Outfit {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        match *self {
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+        let mount = match *self {
             Outfit::LookType { look_type, head, body, legs, feet, addons, mount } => {
                 out.put_u16_le(look_type);
                 out.put_u8(head);
@@ -270,20 +282,25 @@ impl PacketWrite for Outfit {
                 out.put_u8(legs);
                 out.put_u8(feet);
                 out.put_u8(addons);
-                out.put_u16_le(mount);
+                mount
             },
             Outfit::Item { client_id, mount } => {
                 out.put_u16_le(0);
                 out.put_u16_le(client_id);
-                out.put_u16_le(mount);
+                mount
             }
+        };
+
+        if ctx.version() >= ProtocolVersion::V1098 {
+            out.put_u16_le(mount);
         }
         Ok(())
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Default, Clone)]
 pub enum CreatureKnown {
+    #[default]
     Yes,
     No {
         remove: u32,
@@ -293,18 +310,15 @@ pub enum CreatureKnown {
     },
 }
 
-impl Default for CreatureKnown {
-    fn default() -> Self {
-        CreatureKnown::Yes
-    }
-}
-
+/// Creature as sent in map descriptions and tile updates
+///
+/// creature_type, summon_type, speech_bubble and helpers don't exist in 8.60.
 #[derive(Debug, Default, Clone)]
 pub struct Creature {
     pub id: u32,
     pub known: CreatureKnown,
     pub health: u8,
-    pub direction: u8,
+    pub direction: Direction,
     pub outfit: Outfit,
     pub light: LightInfo,
     pub speed: u16,
@@ -317,14 +331,59 @@ pub struct Creature {
 }
 
 impl PacketRead for Creature {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        todo!()
+        let (id, mut known) = match data.read_u16_le()? {
+            0x62 => (data.read_u32_le()?, CreatureKnown::Yes),
+            0x61 => {
+                let remove = data.read_u32_le()?;
+                let id = data.read_u32_le()?;
+                let creature_type = if ctx.version() >= ProtocolVersion::V1098 { data.read_u8()? } else { 0 };
+                let creature_name = data.get_string()?;
+                (id, CreatureKnown::No { remove, creature_type, creature_name, guild_emblem: 0 })
+            },
+            marker => return Err(PacketError::UnknownCreatureMarker(marker)),
+        };
+
+        let health = data.read_u8()?;
+        let direction = data.get_t(ctx)?;
+        let outfit = data.get_t(ctx)?;
+        let light = data.get_t(ctx)?;
+        let speed = data.read_u16_le()?;
+        let skull = data.read_u8()?;
+        let shield = data.read_u8()?;
+
+        if let CreatureKnown::No { ref mut guild_emblem, .. } = known {
+            *guild_emblem = data.read_u8()?;
+        }
+
+        let mut creature = Creature {
+            id,
+            known,
+            health,
+            direction,
+            outfit,
+            light,
+            speed,
+            skull,
+            shield,
+            ..Creature::default()
+        };
+
+        if ctx.version() >= ProtocolVersion::V1098 {
+            creature.summon_type = data.read_u8()?;
+            creature.speech_bubble = data.read_u8()?;
+            data.read_u8()?; // MARK_UNMARKED
+            creature.helpers = data.read_u16_le()?;
+        }
+
+        creature.walk_through = data.read_u8()? > 0;
+        Ok(creature)
     }
 }
 
 impl PacketWrite for Creature {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
         match self.known {
             CreatureKnown::Yes => {
                 out.put_u16_le(0x62);
@@ -334,15 +393,17 @@ impl PacketWrite for Creature {
                 out.put_u16_le(0x61);
                 out.put_u32_le(remove);
                 out.put_u32_le(self.id);
-                out.put_u8(creature_type);
+                if ctx.version() >= ProtocolVersion::V1098 {
+                    out.put_u8(creature_type);
+                }
                 out.put_string(creature_name);
             }
         }
 
         out.put_u8(self.health);
-        out.put_u8(self.direction);
-        out.put_t(&self.outfit)?;
-        out.put_t(&self.light)?;
+        out.put_t(&self.direction, ctx)?;
+        out.put_t(&self.outfit, ctx)?;
+        out.put_t(&self.light, ctx)?;
         out.put_u16_le(self.speed);
         out.put_u8(self.skull);
         out.put_u8(self.shield);
@@ -351,21 +412,61 @@ impl PacketWrite for Creature {
             out.put_u8(guild_emblem);
         }
 
-        out.put_u8(self.summon_type);
-        out.put_u8(self.speech_bubble);
-        out.put_u8(0xFF); // MARK_UNMARKED
+        if ctx.version() >= ProtocolVersion::V1098 {
+            out.put_u8(self.summon_type);
+            out.put_u8(self.speech_bubble);
+            out.put_u8(0xFF); // MARK_UNMARKED
+            out.put_u16_le(self.helpers);
+        }
 
-        out.put_u16_le(self.helpers);
         out.put_u8(if self.walk_through { 1 } else { 0 });
 
         Ok(())
     }
 }
 
+/// Creature already on the tile that only turned, as sent in tile updates
+///
+/// walk_through doesn't exist in 8.60.
+#[derive(Debug, Default, Clone)]
+pub struct CreatureTurn {
+    pub id: u32,
+    pub direction: Direction,
+    pub walk_through: bool,
+}
+
+impl PacketRead for CreatureTurn {
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
+    where Self: std::marker::Sized {
+        match data.read_u16_le()? {
+            0x63 => (),
+            marker => return Err(PacketError::UnknownCreatureMarker(marker)),
+        }
+
+        let id = data.read_u32_le()?;
+        let direction = data.get_t(ctx)?;
+        let walk_through = ctx.version() >= ProtocolVersion::V1098 && data.read_u8()? > 0;
+        Ok(CreatureTurn { id, direction, walk_through })
+    }
+}
+
+impl PacketWrite for CreatureTurn {
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+        out.put_u16_le(0x63);
+        out.put_u32_le(self.id);
+        out.put_t(&self.direction, ctx)?;
+        if ctx.version() >= ProtocolVersion::V1098 {
+            out.put_u8(if self.walk_through { 1 } else { 0 });
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Thing {
     Item(Item),
-    Creature(Creature),
+    Creature(Box<Creature>),
+    CreatureTurn(CreatureTurn),
 }
 
 impl Default for Thing {
@@ -375,66 +476,49 @@ impl Default for Thing {
 }
 
 impl PacketRead for Thing {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        todo!()
+        match data.peek_u16_le()? {
+            0x61 | 0x62 => Ok(Thing::Creature(Box::new(data.get_t(ctx)?))),
+            0x63 => Ok(Thing::CreatureTurn(data.get_t(ctx)?)),
+            _ => Ok(Thing::Item(data.get_t(ctx)?)),
+        }
     }
 }
 
 impl PacketWrite for Thing {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
         match self {
-            Thing::Item(item) => out.put_t(item)?,
-            Thing::Creature(creature) => out.put_t(creature)?,
+            Thing::Item(item) => out.put_t(item, ctx)?,
+            Thing::Creature(creature) => out.put_t(creature.as_ref(), ctx)?,
+            Thing::CreatureTurn(turn) => out.put_t(turn, ctx)?,
         };
         Ok(())
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PacketRead, PacketWrite)]
 pub struct AddTileThing {
     pub position: Position,
     pub stack_index: u8,
     pub thing: Thing,
 }
 
-impl PacketRead for AddTileThing {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
-    where Self: std::marker::Sized {
-        todo!()
-    }
-}
-
-impl PacketWrite for AddTileThing {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_t(&self.position)?;
-        out.put_u8(self.stack_index);
-        out.put_t(&self.thing)?;
-        Ok(())
-    }
+/// Replaces the thing at stack_index, e.g with a CreatureTurn when a creature turned
+#[derive(Debug, Default, Clone, PacketRead, PacketWrite)]
+pub struct UpdateTileThing {
+    pub position: Position,
+    pub stack_index: u8,
+    pub thing: Thing,
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PacketRead, PacketWrite)]
 pub struct DeleteTileThing {
     pub position: Position,
     pub stack_index: u8,
 }
 
-impl PacketRead for DeleteTileThing {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
-    where Self: std::marker::Sized {
-        todo!()
-    }
-}
-
-impl PacketWrite for DeleteTileThing {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_t(&self.position)?;
-        out.put_u8(self.stack_index);
-        Ok(())
-    }
-}
-
+/// A tile of a map description, the environmental effects don't exist in 8.60
 #[derive(Debug, Default, Clone)]
 pub struct Tile {
     pub environmental_effects: u16,
@@ -442,20 +526,35 @@ pub struct Tile {
 }
 
 impl PacketRead for Tile {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        todo!()
+        let mut tile = Tile::default();
+        if ctx.version() >= ProtocolVersion::V1098 {
+            tile.environmental_effects = data.read_u16_le()?;
+        }
+
+        // Things continue until the skip marker (0xFF in the high byte)
+        let mut count = 0;
+        while data.peek_u16_le()? < 0xFF00 {
+            if count == tile.things.len() {
+                return Err(PacketError::TileOverflow);
+            }
+            tile.things[count] = Some(data.get_t(ctx)?);
+            count += 1;
+        }
+
+        Ok(tile)
     }
 }
 
 impl PacketWrite for Tile {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_u16_le(self.environmental_effects);
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+        if ctx.version() >= ProtocolVersion::V1098 {
+            out.put_u16_le(self.environmental_effects);
+        }
 
-        for thing in self.things.iter() {
-            if let Some(thing) = thing {
-                out.put_t(thing)?;
-            }
+        for thing in self.things.iter().flatten() {
+            out.put_t(thing, ctx)?;
         }
 
         Ok(())
@@ -468,53 +567,121 @@ pub enum WorldData {
     Empty(usize),
 }
 
+/// Width of the map description sent in FullWorld, in tiles
+pub const VIEWPORT_WIDTH: usize = 18;
+/// Height of the map description sent in FullWorld, in tiles
+pub const VIEWPORT_HEIGHT: usize = 14;
+
+/// Floors included in a map description for a player on floor z
+/// Surface (7..0) or underground (z-2..z+2, capped at the lowest floor)
+fn description_floor_range(z: u8) -> RangeInclusive<u8> {
+    if z <= 7 {
+        0..=7
+    } else {
+        z - 2..=u8::min(z + 2, 15)
+    }
+}
+
+/// Number of floors included in a map description for a player on floor z
+pub fn description_floors(z: u8) -> usize {
+    description_floor_range(z).len()
+}
+
+/// Number of floors coming into sight when the player changes floor from z to new_z
+pub fn new_floors(z: u8, new_z: u8) -> usize {
+    let old = description_floor_range(z);
+    description_floor_range(new_z).filter(|floor| !old.contains(floor)).count()
+}
+
+impl WorldData {
+    /// Returns the number of tiles in a map description, including empty ones
+    pub fn tile_count(world_chunk: &[WorldData]) -> usize {
+        world_chunk.iter().map(|entry| match entry {
+            WorldData::Tile(_) => 1,
+            WorldData::Empty(n) => *n,
+        }).sum()
+    }
+
+    /// Reads a map description containing `tile_count` tiles (including empty ones)
+    pub fn read_description(data: &mut BytesMut, ctx: &DecodeContext, tile_count: usize) -> Result<Vec<WorldData>, PacketError> {
+        let mut entries: Vec<WorldData> = Vec::new();
+        let mut read = 0;
+
+        while read < tile_count {
+            if data.peek_u16_le()? >= 0xFF00 {
+                // Marker in place of a tile, this tile and n more are empty
+                let n = (data.read_u16_le()? & 0xFF) as usize + 1;
+                WorldData::push_empty(&mut entries, n);
+                read += n;
+            } else {
+                entries.push(WorldData::Tile(data.get_t(ctx)?));
+                read += 1;
+
+                // Marker ending the tile, n following tiles are empty
+                let n = (data.read_u16_le()? & 0xFF) as usize;
+                if n > 0 {
+                    WorldData::push_empty(&mut entries, n);
+                    read += n;
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn push_empty(entries: &mut Vec<WorldData>, n: usize) {
+        if let Some(WorldData::Empty(prev)) = entries.last_mut() {
+            *prev += n;
+        } else {
+            entries.push(WorldData::Empty(n));
+        }
+    }
+}
+
 impl PacketRead for Vec<WorldData> {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        todo!()
+        let position = ctx.player_position();
+        WorldData::read_description(data, ctx, VIEWPORT_WIDTH * VIEWPORT_HEIGHT * description_floors(position.z))
     }
 }
 
 impl PacketWrite for Vec<WorldData> {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        let mut empty_extra = 0;
-        
-        for (i, entry) in self.iter().enumerate() {
-            let next_is_empty =
-                matches!(self.get(i + 1), Some(WorldData::Empty(_)));
-            
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+        // Same state machine as the client reader:
+        // -1 means no pending skip, otherwise the number of empty tiles after the last tile/marker
+        let mut skip: i32 = -1;
+
+        for entry in self.iter() {
             match entry {
                 WorldData::Tile(tile) => {
-                    out.put_t(tile)?;
-                    if !next_is_empty {
-                        // Tiles has to be followed by a skip, even if its 0
-                        out.put_u8(0x00);
+                    if skip >= 0 {
+                        out.put_u8(skip as u8);
                         out.put_u8(0xFF);
                     }
+                    out.put_t(tile, ctx)?;
+                    // Tiles has to be followed by a skip, even if its 0
+                    skip = 0;
                 },
                 WorldData::Empty(n) => {
-                    if next_is_empty {
-                        empty_extra += n;
-                        continue;
-                    }
-
-                    let mut n = *n + empty_extra;
-                    empty_extra = 0;
-
-                    while n > 0xFF {
-                        out.put_u8(0xFF);
-                        out.put_u8(0xFF);
-                        n -= 0xFF;
-                    }
-
-                    if n > 0 {
-                        out.put_u8(n as u8);
-                        out.put_u8(0xFF);
+                    for _ in 0..*n {
+                        if skip == 0xFE {
+                            out.put_u8(0xFF);
+                            out.put_u8(0xFF);
+                            skip = -1;
+                        } else {
+                            skip += 1;
+                        }
                     }
                 }
             }
         }
 
+        if skip >= 0 {
+            out.put_u8(skip as u8);
+            out.put_u8(0xFF);
+        }
+
         Ok(())
     }
 }
@@ -526,16 +693,28 @@ pub struct FullWorld {
 }
 
 impl PacketRead for FullWorld {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        todo!()
+        let player_position: Position = data.get_t(ctx)?;
+        let tiles = VIEWPORT_WIDTH * VIEWPORT_HEIGHT * description_floors(player_position.z);
+        Ok(FullWorld {
+            player_position,
+            world_chunk: WorldData::read_description(data, ctx, tiles)?,
+        })
     }
 }
 
 impl PacketWrite for FullWorld {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_t(&self.player_position)?;
-        out.put_t(&self.world_chunk)?; // TODO: Check sum of data? Should always be 2016
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+        // The client reads the whole viewport, anything else desyncs the rest of the frame
+        let expected = VIEWPORT_WIDTH * VIEWPORT_HEIGHT * description_floors(self.player_position.z);
+        let actual = WorldData::tile_count(&self.world_chunk);
+        if actual != expected {
+            return Err(PacketError::InvalidDescription { expected, actual });
+        }
+
+        out.put_t(&self.player_position, ctx)?;
+        out.put_t(&self.world_chunk, ctx)?;
         Ok(())
     }
 }
@@ -546,15 +725,18 @@ pub struct WorldRowNorth {
 }
 
 impl PacketRead for WorldRowNorth {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        todo!()
+        let tiles = VIEWPORT_WIDTH * description_floors(ctx.player_position().z);
+        Ok(WorldRowNorth {
+            world_chunk: WorldData::read_description(data, ctx, tiles)?,
+        })
     }
 }
 
 impl PacketWrite for WorldRowNorth {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_t(&self.world_chunk)?;
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+        out.put_t(&self.world_chunk, ctx)?;
         Ok(())
     }
 }
@@ -565,15 +747,18 @@ pub struct WorldRowEast {
 }
 
 impl PacketRead for WorldRowEast {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        todo!()
+        let tiles = VIEWPORT_HEIGHT * description_floors(ctx.player_position().z);
+        Ok(WorldRowEast {
+            world_chunk: WorldData::read_description(data, ctx, tiles)?,
+        })
     }
 }
 
 impl PacketWrite for WorldRowEast {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_t(&self.world_chunk)?;
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+        out.put_t(&self.world_chunk, ctx)?;
         Ok(())
     }
 }
@@ -584,15 +769,18 @@ pub struct WorldRowWest {
 }
 
 impl PacketRead for WorldRowWest {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        todo!()
+        let tiles = VIEWPORT_HEIGHT * description_floors(ctx.player_position().z);
+        Ok(WorldRowWest {
+            world_chunk: WorldData::read_description(data, ctx, tiles)?,
+        })
     }
 }
 
 impl PacketWrite for WorldRowWest {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_t(&self.world_chunk)?;
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+        out.put_t(&self.world_chunk, ctx)?;
         Ok(())
     }
 }
@@ -603,55 +791,387 @@ pub struct WorldRowSouth {
 }
 
 impl PacketRead for WorldRowSouth {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        todo!()
+        let tiles = VIEWPORT_WIDTH * description_floors(ctx.player_position().z);
+        Ok(WorldRowSouth {
+            world_chunk: WorldData::read_description(data, ctx, tiles)?,
+        })
     }
 }
 
 impl PacketWrite for WorldRowSouth {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_t(&self.world_chunk)?;
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+        out.put_t(&self.world_chunk, ctx)?;
         Ok(())
     }
 }
 
+/// The player moved a floor up, with the floors coming into sight
+///
+/// Read with the player position before the change. Followed by WorldRowWest and WorldRowNorth,
+/// as the floors above are offset one tile towards the bottom right.
 #[derive(Debug, Default, Clone)]
-pub struct WorldLight {
-    pub light: LightInfo,
+pub struct FloorChangeUp {
+    pub world_chunk: Vec<WorldData>,
 }
 
-impl PacketRead for WorldLight {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+impl PacketRead for FloorChangeUp {
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        todo!()
+        let z = ctx.player_position().z;
+        let tiles = VIEWPORT_WIDTH * VIEWPORT_HEIGHT * new_floors(z, z.saturating_sub(1));
+        Ok(FloorChangeUp {
+            world_chunk: WorldData::read_description(data, ctx, tiles)?,
+        })
     }
 }
 
-impl PacketWrite for WorldLight {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_t(&self.light)?;
+impl PacketWrite for FloorChangeUp {
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+        out.put_t(&self.world_chunk, ctx)?;
         Ok(())
     }
 }
 
+/// The player moved a floor down, with the floors coming into sight
+///
+/// Read with the player position before the change. Followed by WorldRowEast and WorldRowSouth.
 #[derive(Debug, Default, Clone)]
-pub struct CreatureLight {
-    pub creature_id: u32,
-    pub light: LightInfo,
+pub struct FloorChangeDown {
+    pub world_chunk: Vec<WorldData>,
 }
 
-impl PacketRead for CreatureLight {
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+impl PacketRead for FloorChangeDown {
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        todo!()
+        let z = ctx.player_position().z;
+        let tiles = VIEWPORT_WIDTH * VIEWPORT_HEIGHT * new_floors(z, u8::min(z + 1, 15));
+        Ok(FloorChangeDown {
+            world_chunk: WorldData::read_description(data, ctx, tiles)?,
+        })
     }
 }
 
-impl PacketWrite for CreatureLight {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_u32(self.creature_id);
-        out.put_t(&self.light)?;
+impl PacketWrite for FloorChangeDown {
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+        out.put_t(&self.world_chunk, ctx)?;
         Ok(())
     }
 }
+
+#[derive(Debug, Default, Clone, PacketRead, PacketWrite)]
+pub struct WorldLight {
+    pub light: LightInfo,
+}
+
+#[derive(Debug, Default, Clone, PacketRead, PacketWrite)]
+pub struct CreatureLight {
+    pub creature_id: u32,
+    pub light: LightInfo,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::ItemInfo;
+
+    fn roundtrip<T: PacketRead + PacketWrite + Default>(packet: &T, ctx: &DecodeContext) -> T {
+        let encode_ctx = EncodeContext::with_version(ctx.version());
+        let mut first = BytesMut::new();
+        first.put_t(packet, &encode_ctx).expect("failed to write packet");
+        let mut data = first.clone();
+        let result: T = data.get_t(ctx).expect("failed to read packet");
+        assert!(data.is_empty(), "reader left unread bytes");
+
+        let mut second = BytesMut::new();
+        second.put_t(&result, &encode_ctx).expect("failed to write packet again");
+        assert_eq!(first, second);
+        result
+    }
+
+    fn creature() -> Creature {
+        Creature {
+            id: 0x1000_0001,
+            known: CreatureKnown::No {
+                remove: 0,
+                creature_type: 0,
+                creature_name: "Rustia".to_string(),
+                guild_emblem: 3,
+            },
+            health: 100,
+            direction: Direction::South,
+            outfit: Outfit::LookType { look_type: 128, head: 1, body: 2, legs: 3, feet: 4, addons: 0, mount: 0 },
+            speed: 220,
+            ..Creature::default()
+        }
+    }
+
+    #[test]
+    fn test_item_extra_bytes() {
+        let mut ctx = DecodeContext::new();
+        ctx.set_item_info(3031, ItemInfo { stackable: true, ..ItemInfo::default() });
+
+        let item = Item { client_id: 3031, stack_size: Some(57), ..Item::default() };
+        let result = roundtrip(&item, &ctx);
+        assert_eq!(result.stack_size, Some(57));
+    }
+
+    #[test]
+    fn test_creature_roundtrip() {
+        let result = roundtrip(&creature(), &DecodeContext::new());
+        match result.known {
+            CreatureKnown::No { ref creature_name, guild_emblem, .. } => {
+                assert_eq!(creature_name, "Rustia");
+                assert_eq!(guild_emblem, 3);
+            },
+            CreatureKnown::Yes => panic!("creature should be unknown"),
+        }
+        assert_eq!(result.speed, 220);
+    }
+
+    #[test]
+    fn test_full_world_roundtrip() {
+        let mut tile = Tile::default();
+        tile.things[0] = Some(Thing::Item(Item { client_id: 102, ..Item::default() }));
+        tile.things[1] = Some(Thing::Creature(Box::new(creature())));
+
+        let full_world = FullWorld {
+            player_position: Position { x: 100, y: 100, z: 7 },
+            world_chunk: vec![
+                WorldData::Empty(20),
+                WorldData::Tile(tile.clone()),
+                WorldData::Tile(tile.clone()),
+                WorldData::Empty(300),
+                WorldData::Tile(tile),
+                WorldData::Empty(2016 - 20 - 3 - 300),
+            ],
+        };
+
+        let result = roundtrip(&full_world, &DecodeContext::new());
+        assert_eq!(WorldData::tile_count(&result.world_chunk), 2016);
+        assert_eq!(result.world_chunk.len(), 6);
+
+        // Descriptions not covering the whole viewport are refused
+        let underground = FullWorld {
+            player_position: Position { x: 100, y: 100, z: 8 },
+            world_chunk: vec![WorldData::Empty(2016)],
+        };
+        assert!(matches!(
+            underground.write_to(&mut BytesMut::new(), &EncodeContext::new()),
+            Err(PacketError::InvalidDescription { expected: 1260, actual: 2016 })
+        ));
+    }
+
+    #[test]
+    fn test_creature_turn() {
+        let update = UpdateTileThing {
+            position: Position { x: 100, y: 100, z: 7 },
+            stack_index: 1,
+            thing: Thing::CreatureTurn(CreatureTurn { id: 0x1000_0001, direction: Direction::East, walk_through: false }),
+        };
+
+        let mut ctx = DecodeContext::new();
+        for (version, expected) in [
+            (ProtocolVersion::V860, &[107, 100, 0, 100, 0, 7, 1, 0x63, 0, 1, 0, 0, 0x10, 1][..]),
+            (ProtocolVersion::V1098, &[107, 100, 0, 100, 0, 7, 1, 0x63, 0, 1, 0, 0, 0x10, 1, 0][..]),
+        ] {
+            let mut data = BytesMut::new();
+            GameServerPacket::from(update.clone()).write_to(&mut data, &EncodeContext::with_version(version)).unwrap();
+            assert_eq!(&data[..], expected, "{}", version);
+
+            ctx.set_version(version);
+            match GameServerPacket::read_from(&mut data, &ctx).unwrap() {
+                GameServerPacket::UpdateTileThing(UpdateTileThing { thing: Thing::CreatureTurn(turn), .. }) => {
+                    assert_eq!(turn.id, 0x1000_0001);
+                    assert_eq!(turn.direction, Direction::East);
+                },
+                packet => panic!("expected a creature turn, got {:?}", packet),
+            }
+            assert!(data.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_new_floors() {
+        assert_eq!(new_floors(7, 6), 0);
+        assert_eq!(new_floors(8, 7), 6);
+        assert_eq!(new_floors(7, 8), 3);
+        assert_eq!(new_floors(9, 10), 1);
+        assert_eq!(new_floors(13, 14), 0);
+        assert_eq!(new_floors(14, 13), 1);
+    }
+
+    #[test]
+    fn test_floor_change_roundtrip() {
+        let mut tile = Tile::default();
+        tile.things[0] = Some(Thing::Item(Item { client_id: 102, ..Item::default() }));
+
+        // Up to the surface, floors 5 to 0 come into sight
+        let mut ctx = DecodeContext::new();
+        ctx.set_player_position(Position { x: 100, y: 100, z: 8 });
+        let up = FloorChangeUp {
+            world_chunk: vec![WorldData::Tile(tile.clone()), WorldData::Empty(VIEWPORT_WIDTH * VIEWPORT_HEIGHT * 6 - 1)],
+        };
+        let result = roundtrip(&up, &ctx);
+        assert_eq!(WorldData::tile_count(&result.world_chunk), VIEWPORT_WIDTH * VIEWPORT_HEIGHT * 6);
+
+        // Down on the surface, nothing new is seen
+        ctx.set_player_position(Position { x: 100, y: 100, z: 5 });
+        let mut data = BytesMut::new();
+        GameServerPacket::from(FloorChangeDown::default()).write_to(&mut data, &EncodeContext::new()).unwrap();
+        assert_eq!(&data[..], &[191][..]);
+        match GameServerPacket::read_from(&mut data, &ctx).unwrap() {
+            GameServerPacket::FloorChangeDown(down) => assert!(down.world_chunk.is_empty()),
+            packet => panic!("expected FloorChangeDown, got {:?}", packet),
+        }
+
+        // Down from the surface, floors 8 to 10 come into sight
+        ctx.set_player_position(Position { x: 100, y: 100, z: 7 });
+        let down = FloorChangeDown {
+            world_chunk: vec![WorldData::Empty(VIEWPORT_WIDTH * VIEWPORT_HEIGHT * 3 - 1), WorldData::Tile(tile)],
+        };
+        let result = roundtrip(&down, &ctx);
+        assert_eq!(WorldData::tile_count(&result.world_chunk), VIEWPORT_WIDTH * VIEWPORT_HEIGHT * 3);
+        assert!(matches!(result.world_chunk.last(), Some(WorldData::Tile(_))));
+    }
+
+    #[test]
+    fn test_truncated_packets() {
+        let packets: Vec<GameServerPacket> = vec![
+            LoginSuccess { store_img_url: "http://localhost".to_string(), ..LoginSuccess::default() }.into(),
+            AddTileThing { thing: Thing::Creature(Box::new(creature())), ..AddTileThing::default() }.into(),
+            FullWorld { world_chunk: vec![WorldData::Empty(2016)], ..FullWorld::default() }.into(),
+        ];
+
+        for packet in packets.iter() {
+            let mut full = BytesMut::new();
+            packet.write_to(&mut full, &EncodeContext::new()).unwrap();
+
+            for len in 0..full.len() {
+                let mut data = BytesMut::from(&full[..len]);
+                match GameServerPacket::read_from(&mut data, &DecodeContext::new()) {
+                    Err(PacketError::UnexpectedEof { offset, needed }) => {
+                        assert!(offset <= len && offset + needed > len, "{:?} at {}: offset {} needed {}", packet, len, offset, needed);
+                    },
+                    result => panic!("{:?} truncated at {} should fail with UnexpectedEof, got {:?}", packet, len, result),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_world_row_underground() {
+        let mut ctx = DecodeContext::new();
+        ctx.set_player_position(Position { x: 100, y: 100, z: 14 });
+
+        // Floors 12..15
+        let row = WorldRowEast {
+            world_chunk: vec![WorldData::Empty(VIEWPORT_HEIGHT * 4)],
+        };
+        let result = roundtrip(&row, &ctx);
+        assert_eq!(WorldData::tile_count(&result.world_chunk), VIEWPORT_HEIGHT * 4);
+    }
+
+    #[test]
+    fn test_versioned_packet_ids() {
+        let mut ctx = DecodeContext::new();
+        ctx.set_version(ProtocolVersion::V860);
+        let encode_ctx = EncodeContext::with_version(ProtocolVersion::V860);
+
+        let login = GameServerPacket::from(LoginSuccess { player_id: 5, speed_a: 857.36, ..LoginSuccess::default() });
+        let mut data = BytesMut::new();
+        login.write_to(&mut data, &encode_ctx).unwrap();
+        assert_eq!(&data[..], &[10, 5, 0, 0, 0, 0, 0, 0][..]);
+
+        match GameServerPacket::read_from(&mut data.clone(), &ctx).unwrap() {
+            GameServerPacket::LoginSuccess(result) => {
+                assert_eq!(result.player_id, 5);
+                assert_eq!(result.speed_a, 0.0);
+            },
+            packet => panic!("expected LoginSuccess, got {:?}", packet),
+        }
+
+        // The same id is PendingStateEntered in 10.98
+        assert!(matches!(
+            GameServerPacket::read_from(&mut data, &DecodeContext::new()),
+            Ok(GameServerPacket::PendingStateEntered(_))
+        ));
+
+        let mut data = BytesMut::new();
+        let result = GameServerPacket::from(EnterWorld).write_to(&mut data, &encode_ctx);
+        assert!(matches!(result, Err(PacketError::UnsupportedPacket(ProtocolVersion::V860))));
+
+        let mut data = BytesMut::from(&[15][..]);
+        assert!(matches!(GameServerPacket::read_from(&mut data, &ctx), Err(PacketError::UnknownPacket(15))));
+    }
+
+    #[test]
+    fn test_tile_860() {
+        let mut ctx = DecodeContext::new();
+        ctx.set_version(ProtocolVersion::V860);
+        let encode_ctx = EncodeContext::with_version(ProtocolVersion::V860);
+
+        let mut tile = Tile { environmental_effects: 7, ..Tile::default() };
+        tile.things[0] = Some(Thing::Item(Item { client_id: 102, ..Item::default() }));
+
+        // No environmental effects and no item mark
+        let mut data = BytesMut::new();
+        data.put_t(&tile, &encode_ctx).unwrap();
+        assert_eq!(&data[..], &[102, 0][..]);
+
+        let mut data = BytesMut::new();
+        data.put_t(&tile, &EncodeContext::new()).unwrap();
+        assert_eq!(&data[..], &[7, 0, 102, 0, 255][..]);
+
+        let full_world = GameServerPacket::from(FullWorld {
+            player_position: Position { x: 100, y: 100, z: 7 },
+            world_chunk: vec![WorldData::Tile(tile), WorldData::Empty(2015)],
+        });
+        let mut data = BytesMut::new();
+        full_world.write_to(&mut data, &encode_ctx).unwrap();
+        assert_eq!(&data[..9], &[100, 100, 0, 100, 0, 7, 102, 0, 255][..]);
+
+        match GameServerPacket::read_from(&mut data, &ctx).unwrap() {
+            GameServerPacket::FullWorld(result) => {
+                assert_eq!(WorldData::tile_count(&result.world_chunk), 2016);
+                match &result.world_chunk[0] {
+                    WorldData::Tile(tile) => {
+                        assert_eq!(tile.environmental_effects, 0);
+                        assert!(matches!(tile.things[0], Some(Thing::Item(Item { client_id: 102, .. }))));
+                        assert!(tile.things[1].is_none());
+                    },
+                    data => panic!("expected a tile, got {:?}", data),
+                }
+            },
+            packet => panic!("expected FullWorld, got {:?}", packet),
+        }
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_versioned_layouts() {
+        let expected_len = [
+            (ProtocolVersion::V860, 2, 35),
+            (ProtocolVersion::V1098, 3, 43),
+        ];
+
+        for &(version, item_len, creature_len) in expected_len.iter() {
+            let mut ctx = DecodeContext::new();
+            ctx.set_version(version);
+            let encode_ctx = EncodeContext::with_version(version);
+
+            let mut data = BytesMut::new();
+            data.put_t(&Item { client_id: 102, ..Item::default() }, &encode_ctx).unwrap();
+            assert_eq!(data.len(), item_len, "item in {}", version);
+
+            let mut data = BytesMut::new();
+            data.put_t(&creature(), &encode_ctx).unwrap();
+            assert_eq!(data.len(), creature_len, "creature in {}", version);
+
+            let result = roundtrip(&creature(), &ctx);
+            assert_eq!(result.speed, 220);
+            assert!(matches!(result.known, CreatureKnown::No { guild_emblem: 3, .. }));
+        }
+    }
+}
@@ -1,5 +1,7 @@
-use bytes::{Buf, BufMut, BytesMut};
-use super::{PacketError, BytesMutExt, PacketRead, PacketWrite, PacketPayload};
+use std::net::Ipv4Addr;
+
+use bytes::{BufMut, BytesMut};
+use super::{PacketError, BytesMutExt, DecodeContext, EncodeContext, ProtocolVersion, PacketRead, PacketWrite, PacketPayload};
 
 use crate::gen_packet_types;
 
@@ -7,71 +9,23 @@ gen_packet_types!(LoginServerPacket; LoginServerPacketKind;
     ( Error,          10  ),
     ( Error2,         11  ),
     ( Motd,           20  ),
-    ( SessionKey,     40  ),
+    ( SessionKey,     40, since 1098 ),
     ( CharacterList,  100 )
 );
 
-#[derive(Debug, Default)]
-pub struct Error(pub String);
-impl PacketRead for Error {
-    fn read_from(data: &mut BytesMut) -> Result<Self, PacketError>
-    where Self: std::marker::Sized + Default {
-        Ok(Self(data.get_string()?))
-    }
-}
-impl PacketWrite for Error {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_string(&self.0);
-        Ok(())
-    }
-}
+#[derive(Debug, Default, PacketRead, PacketWrite)]
+pub struct Error(#[packet(string)] pub String);
 
-#[derive(Debug, Default)]
-pub struct Error2(pub String);
-impl PacketRead for Error2 {
-    fn read_from(data: &mut BytesMut) -> Result<Self, PacketError>
-    where Self: std::marker::Sized + Default {
-        Ok(Self(data.get_string()?))
-    }
-}
-impl PacketWrite for Error2 {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_string(&self.0);
-        Ok(())
-    }
-}
+#[derive(Debug, Default, PacketRead, PacketWrite)]
+pub struct Error2(#[packet(string)] pub String);
 
-#[derive(Debug, Default)]
-pub struct Motd(pub String);
-impl PacketRead for Motd {
-    fn read_from(data: &mut BytesMut) -> Result<Self, PacketError>
-    where Self: std::marker::Sized + Default {
-        Ok(Self(data.get_string()?))
-    }
-}
-impl PacketWrite for Motd {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_string(&self.0);
-        Ok(())
-    }
-}
+#[derive(Debug, Default, PacketRead, PacketWrite)]
+pub struct Motd(#[packet(string)] pub String);
 
-#[derive(Debug, Default)]
-pub struct SessionKey(pub String);
-impl PacketRead for SessionKey {
-    fn read_from(data: &mut BytesMut) -> Result<Self, PacketError>
-    where Self: std::marker::Sized + Default {
-        Ok(Self(data.get_string()?))
-    }
-}
-impl PacketWrite for SessionKey {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
-        out.put_string(&self.0);
-        Ok(())
-    }
-}
+#[derive(Debug, Default, PacketRead, PacketWrite)]
+pub struct SessionKey(#[packet(string)] pub String);
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct World {
     pub id: u8,
     pub name: String,
@@ -79,7 +33,7 @@ pub struct World {
     pub port: u16,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Character {
     pub world_id: u8,
     pub name: String,
@@ -93,43 +47,84 @@ pub struct CharacterList {
     pub premium_days_left: u32,
 }
 
+/// The character list is sent per world since 10.98.
+/// Before that every character carries its world name and address, and the premium time is a u16.
 impl PacketRead for CharacterList {
-    fn read_from(data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
-        let worlds_len = data.get_u8();
+        if ctx.version() < ProtocolVersion::V1098 {
+            return read_old_character_list(data);
+        }
+
+        let worlds_len = data.read_u8()?;
         let mut worlds: Vec<World> = Vec::with_capacity(worlds_len as usize);
         for _ in 0..worlds_len {
             worlds.push(World {
-                id: data.get_u8(),
+                id: data.read_u8()?,
                 name: data.get_string()?,
                 ip: data.get_string()?,
-                port: data.get_u16_le(),
+                port: data.read_u16_le()?,
             });
-            data.get_u8(); // skip something, why?
+            data.read_u8()?; // skip something, why?
         }
 
-        let chars_len = data.get_u8();
+        let chars_len = data.read_u8()?;
         let mut characters: Vec<Character> = Vec::with_capacity(chars_len as usize);
         for _ in 0..chars_len {
             characters.push(Character {
-                world_id: data.get_u8(),
+                world_id: data.read_u8()?,
                 name: data.get_string()?,
             });
         }
 
-        data.get_u8(); // skip something
+        data.read_u8()?; // skip something
         
         Ok(CharacterList {
             worlds,
             characters,
-            has_premium: data.get_u8() > 0,
-            premium_days_left: data.get_u32_le(),
+            has_premium: data.read_u8()? > 0,
+            premium_days_left: data.read_u32_le()?,
         })
     }
 }
 
+/// Reads the character list before 10.98, characters on the same world and address share a World
+fn read_old_character_list(data: &mut BytesMut) -> Result<CharacterList, PacketError> {
+    let chars_len = data.read_u8()?;
+    let mut worlds: Vec<World> = Vec::new();
+    let mut characters: Vec<Character> = Vec::with_capacity(chars_len as usize);
+    for _ in 0..chars_len {
+        let name = data.get_string()?;
+        let world_name = data.get_string()?;
+        let ip = Ipv4Addr::from(data.read_u32_le()?.to_le_bytes()).to_string();
+        let port = data.read_u16_le()?;
+
+        let world_id = match worlds.iter().find(|w| w.name == world_name && w.ip == ip && w.port == port) {
+            Some(world) => world.id,
+            None => {
+                let id = worlds.len() as u8;
+                worlds.push(World { id, name: world_name, ip, port });
+                id
+            },
+        };
+        characters.push(Character { world_id, name });
+    }
+
+    let premium_days_left = data.read_u16_le()? as u32;
+    Ok(CharacterList {
+        worlds,
+        characters,
+        has_premium: premium_days_left > 0,
+        premium_days_left,
+    })
+}
+
 impl PacketWrite for CharacterList {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
+    fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+        if ctx.version() < ProtocolVersion::V1098 {
+            return self.write_old(out);
+        }
+
         out.put_u8(self.worlds.len() as u8);
         for world in self.worlds.iter() {
             out.put_u8(world.id);
@@ -152,3 +147,92 @@ impl PacketWrite for CharacterList {
         Ok(())
     }
 }
+
+impl CharacterList {
+    /// Writes the character list before 10.98, the world ips must be IPv4 addresses
+    fn write_old(&self, out: &mut BytesMut) -> Result<(), PacketError> {
+        out.put_u8(self.characters.len() as u8);
+        for c in self.characters.iter() {
+            let world = self.worlds.iter()
+                .find(|w| w.id == c.world_id)
+                .ok_or(PacketError::UnknownWorld(c.world_id))?;
+            let ip: Ipv4Addr = world.ip.parse().map_err(|_| PacketError::InvalidIpAddress)?;
+
+            out.put_string(&c.name);
+            out.put_string(&world.name);
+            out.put_u32_le(u32::from_le_bytes(ip.octets()));
+            out.put_u16_le(world.port);
+        }
+
+        let premium_days_left = if self.has_premium { self.premium_days_left } else { 0 };
+        out.put_u16_le(premium_days_left.min(u16::MAX as u32) as u16);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn character_list() -> CharacterList {
+        CharacterList {
+            worlds: vec![
+                World { id: 0, name: "Rustia".to_string(), ip: "127.0.0.1".to_string(), port: 7172 },
+                World { id: 1, name: "Antica".to_string(), ip: "10.0.0.2".to_string(), port: 7172 },
+            ],
+            characters: vec![
+                Character { world_id: 0, name: "Alice".to_string() },
+                Character { world_id: 1, name: "Bob".to_string() },
+                Character { world_id: 0, name: "Carol".to_string() },
+            ],
+            has_premium: true,
+            premium_days_left: 30,
+        }
+    }
+
+    #[test]
+    fn test_character_list_860() {
+        let mut ctx = DecodeContext::new();
+        ctx.set_version(ProtocolVersion::V860);
+        let encode_ctx = EncodeContext::with_version(ProtocolVersion::V860);
+
+        let mut data = BytesMut::new();
+        LoginServerPacket::from(character_list()).write_to(&mut data, &encode_ctx).unwrap();
+        assert_eq!(data[..2], [100, 3]);
+        assert_eq!(data[2..9], [5, 0, b'A', b'l', b'i', b'c', b'e']);
+        assert_eq!(data[9..17], [6, 0, b'R', b'u', b's', b't', b'i', b'a']);
+        assert_eq!(data[17..23], [127, 0, 0, 1, 0x04, 0x1c]);
+
+        let list = match LoginServerPacket::read_from(&mut data, &ctx).unwrap() {
+            LoginServerPacket::CharacterList(list) => list,
+            packet => panic!("expected CharacterList, got {:?}", packet),
+        };
+        assert!(data.is_empty());
+        assert_eq!(list.worlds.len(), 2);
+        assert_eq!((list.worlds[1].name.as_str(), list.worlds[1].ip.as_str(), list.worlds[1].port), ("Antica", "10.0.0.2", 7172));
+        let characters: Vec<_> = list.characters.iter().map(|c| (c.world_id, c.name.as_str())).collect();
+        assert_eq!(characters, [(0, "Alice"), (1, "Bob"), (0, "Carol")]);
+        assert!(list.has_premium);
+        assert_eq!(list.premium_days_left, 30);
+
+        let mut data = BytesMut::new();
+        let result = LoginServerPacket::from(SessionKey("key".to_string())).write_to(&mut data, &encode_ctx);
+        assert!(matches!(result, Err(PacketError::UnsupportedPacket(_))));
+        assert!(matches!(LoginServerPacket::read_from(&mut BytesMut::from(&[40u8][..]), &ctx), Err(PacketError::UnknownPacket(40))));
+    }
+
+    #[test]
+    fn test_character_list_1098() {
+        let mut data = BytesMut::new();
+        LoginServerPacket::from(character_list()).write_to(&mut data, &EncodeContext::new()).unwrap();
+        let list = match LoginServerPacket::read_from(&mut data, &DecodeContext::new()).unwrap() {
+            LoginServerPacket::CharacterList(list) => list,
+            packet => panic!("expected CharacterList, got {:?}", packet),
+        };
+        assert!(data.is_empty());
+        assert_eq!(list.worlds.len(), 2);
+        assert_eq!(list.characters.len(), 3);
+        assert_eq!(list.premium_days_left, 30);
+    }
+}
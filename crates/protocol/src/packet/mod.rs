@@ -1,32 +1,73 @@
-use bytes::BytesMut;
+use bytes::{BufMut, BytesMut};
 use thiserror::Error;
 
-use super::util;
-
 mod bytes_mut_ext;
+mod context;
+mod version;
 pub mod login;
 pub mod client;
 pub mod game;
 
 pub use bytes_mut_ext::*;
+pub use context::*;
+pub use version::*;
 pub use client::ClientPacket;
 pub use login::LoginServerPacket;
 pub use game::GameServerPacket;
+pub use derive::{PacketRead, PacketWrite};
 
 #[derive(Clone, Copy, Error, Debug)]
 pub enum PacketError {
     #[error("unknown packet id {0}")]
     UnknownPacket(u8),
+    #[error("unexpected end of packet at offset {offset}, needed {needed} bytes")]
+    UnexpectedEof { offset: usize, needed: usize },
     #[error("invalid string in packet")]
     InvalidString,
     #[error("RSA zero check failed")]
     RsaCheckFailed,
+    #[error("data does not fit in an RSA block")]
+    RsaBlockOverflow,
+    #[error("RSA block must be {expected} bytes, got {actual}")]
+    RsaInvalidLength { expected: usize, actual: usize },
+    #[error("RSA key has no private part, can't decrypt")]
+    RsaNoPrivateKey,
+    #[error("unknown creature marker {0:#x}")]
+    UnknownCreatureMarker(u16),
+    #[error("invalid direction {0}")]
+    InvalidDirection(u8),
+    #[error("auto walk path has {0} steps, at most 255 can be sent")]
+    PathTooLong(usize),
+    #[error("list has {len} values, at most {max} can be sent")]
+    ListTooLong { len: usize, max: usize },
+    #[error("too many things on tile")]
+    TileOverflow,
+    #[error("map description must have {expected} tiles, got {actual}")]
+    InvalidDescription { expected: usize, actual: usize },
+    #[error("no world with id {0}")]
+    UnknownWorld(u8),
+    #[error("invalid IPv4 address")]
+    InvalidIpAddress,
+    #[error("packet does not exist in protocol version {0}")]
+    UnsupportedPacket(ProtocolVersion),
+    #[error("field {0} must be set exactly when its condition is true")]
+    ConditionMismatch(&'static str),
+}
+
+impl PacketError {
+    /// Sets the offset of an UnexpectedEof error, other errors are returned unchanged
+    pub fn with_offset(self, offset: usize) -> Self {
+        match self {
+            PacketError::UnexpectedEof { needed, .. } => PacketError::UnexpectedEof { offset, needed },
+            err => err,
+        }
+    }
 }
 
 /// Ability to read an instance of Self from a BytesMut
 pub trait PacketRead {
     /// Reads the packet data from a BytesMut.
-    fn read_from(_data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(_data: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized + Default {
         Ok(Self::default())
     }
@@ -35,20 +76,46 @@ pub trait PacketRead {
 /// Ability to write an instance of Self to a BytesMut
 pub trait PacketWrite {
     /// Writes the packet data to a BytesMut
-    fn write_to(&self, _out: &mut BytesMut) -> Result<(), PacketError> {
+    fn write_to(&self, _out: &mut BytesMut, _ctx: &EncodeContext) -> Result<(), PacketError> {
         Ok(())
     }
 }
 
+macro_rules! impl_packet_primitive {
+    ($($ty:ty: $read:ident, $put:ident;)+) => {
+        $(
+            impl PacketRead for $ty {
+                fn read_from(data: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, PacketError>
+                where Self: std::marker::Sized {
+                    data.$read()
+                }
+            }
+
+            impl PacketWrite for $ty {
+                fn write_to(&self, out: &mut BytesMut, _ctx: &EncodeContext) -> Result<(), PacketError> {
+                    out.$put(*self);
+                    Ok(())
+                }
+            }
+        )+
+    };
+}
+
+impl_packet_primitive! {
+    u8: read_u8, put_u8;
+    u16: read_u16_le, put_u16_le;
+    u32: read_u32_le, put_u32_le;
+}
+
 impl PacketRead for String {
-    fn read_from(data: &mut BytesMut) -> Result<Self, PacketError>
+    fn read_from(data: &mut BytesMut, _ctx: &DecodeContext) -> Result<Self, PacketError>
     where Self: std::marker::Sized {
         data.get_string()
     }
 }
 
 impl PacketWrite for String {
-    fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
+    fn write_to(&self, out: &mut BytesMut, _ctx: &EncodeContext) -> Result<(), PacketError> {
         out.put_string(self);
         Ok(())
     }
@@ -57,14 +124,23 @@ impl PacketWrite for String {
 pub trait PacketPayload<T> {
     fn index() -> usize;
     fn kind() -> T;
+    /// The packet id used on the wire in a protocol version, None if the packet does not exist in it
+    fn id(version: ProtocolVersion) -> Option<u8>;
 }
 
 /// Generates an enum with packet types
 /// 
-/// The enum implements PacketRead and PacketWrite dispatched by packet id
+/// The enum implements PacketRead and PacketWrite dispatched by packet id.
+/// Packets that were added in a later version are declared with `since`,
+/// and packets that used another id in older versions with `old_id before version`:
+///
+/// ```ignore
+/// ( EnterWorld,   15, since 1098 ),
+/// ( LoginSuccess, 23, 10 before 1098 ),
+/// ```
 #[macro_export]
 macro_rules! gen_packet_types {
-    ($name:ident; $name_kind:ident; $(($var:ident, $id:literal)),+) => {
+    ($name:ident; $name_kind:ident; $(($var:ident, $id:literal $(, since $since:literal)? $(, $old_id:literal before $before:literal)?)),+) => {
         #[derive(Debug)]
         pub enum $name {
             $(
@@ -84,17 +160,27 @@ macro_rules! gen_packet_types {
             #[allow(dead_code)]
             pub const COUNT: usize = $name_kind::__CountKindsLast as usize;
 
-            pub fn read_from(data: &mut BytesMut) -> Result<Self, PacketError> {
-                let id = data.peek_u8();
-                match id {
-                    $($id => { data.advance(1); Ok($name::$var(<$var>::read_from(data)?)) }),+
-                    _ => Err(PacketError::UnknownPacket(id).into()),
-                }
+            pub fn read_from(data: &mut BytesMut, ctx: &DecodeContext) -> Result<Self, PacketError> {
+                let start = data.len();
+                let id = data.read_u8()?;
+                let version = ctx.version();
+                let result = match Some(id) {
+                    $(id if id == <$var as PacketPayload<$name_kind>>::id(version) => <$var>::read_from(data, ctx).map($name::$var),)+
+                    _ => Err(PacketError::UnknownPacket(id)),
+                };
+                // Failed reads don't consume, so the remaining length tells where it failed
+                result.map_err(|err| err.with_offset(start - data.len()))
             }
 
-            pub fn write_to(&self, out: &mut BytesMut) -> Result<(), PacketError> {
+            pub fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+                let version = ctx.version();
                 match self {
-                    $($name::$var(x) => { out.put_u8($id); <$var>::write_to(&x, out)?; Ok(()) },)+
+                    $($name::$var(x) => {
+                        let id = <$var as PacketPayload<$name_kind>>::id(version)
+                            .ok_or(PacketError::UnsupportedPacket(version))?;
+                        out.put_u8(id);
+                        <$var>::write_to(&x, out, ctx)
+                    },)+
                 }
             }
 
@@ -105,6 +191,12 @@ macro_rules! gen_packet_types {
             }
         }
 
+        impl PacketWrite for $name {
+            fn write_to(&self, out: &mut BytesMut, ctx: &EncodeContext) -> Result<(), PacketError> {
+                $name::write_to(self, out, ctx)
+            }
+        }
+
         use std::convert::TryFrom;
         $(
             impl TryFrom<$name> for $var {
@@ -118,9 +210,9 @@ macro_rules! gen_packet_types {
                 }
             }
 
-            impl Into<$name> for $var {
-                fn into(self) -> $name {
-                    $name::$var(self)
+            impl From<$var> for $name {
+                fn from(packet: $var) -> $name {
+                    $name::$var(packet)
                 }
             }
 
@@ -132,7 +224,121 @@ macro_rules! gen_packet_types {
                 fn kind() -> $name_kind {
                     $name_kind::$var
                 }
+
+                #[allow(unused_variables)]
+                fn id(version: ProtocolVersion) -> Option<u8> {
+                    $(if version < ProtocolVersion::new($since) {
+                        return None;
+                    })?
+                    $(if version < ProtocolVersion::new($before) {
+                        return Some($old_id);
+                    })?
+                    Some($id)
+                }
             }
         )+
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, PacketRead, PacketWrite)]
+    struct Derived {
+        id: u32,
+        #[packet(bool)]
+        has_speed: bool,
+        #[packet(if = "has_speed", double(precision = 2))]
+        speed: Option<f64>,
+        #[packet(len = "u8")]
+        ids: Vec<u16>,
+        names: Vec<String>,
+    }
+
+    #[derive(Debug, Default, PartialEq, PacketRead, PacketWrite)]
+    struct DerivedTuple(u8, #[packet(string)] String);
+
+    #[derive(Debug, Default, PartialEq, PacketRead, PacketWrite)]
+    struct DerivedVersioned {
+        #[packet(until = 1098)]
+        old: u8,
+        #[packet(since = 1098)]
+        new: u16,
+        #[packet(since = 1098, until = 1200)]
+        between: u32,
+    }
+
+    #[test]
+    fn test_derive_roundtrip() {
+        let ctx = DecodeContext::new();
+        let packet = Derived {
+            id: 1337,
+            has_speed: true,
+            speed: Some(857.36),
+            ids: vec![1, 2, 3],
+            names: vec!["foo".to_string(), "bar".to_string()],
+        };
+
+        let mut data = BytesMut::new();
+        data.put_t(&packet, &EncodeContext::new()).unwrap();
+        assert_eq!(data.len(), 4 + 1 + 5 + 1 + 6 + 2 + 10);
+        assert_eq!(data.get_t::<Derived>(&ctx).unwrap(), packet);
+        assert!(data.is_empty());
+
+        let tuple = DerivedTuple(7, "hello".to_string());
+        data.put_t(&tuple, &EncodeContext::new()).unwrap();
+        assert_eq!(data.get_t::<DerivedTuple>(&ctx).unwrap(), tuple);
+    }
+
+    #[test]
+    fn test_derive_condition() {
+        let ctx = DecodeContext::new();
+        let packet = Derived { id: 1, ..Default::default() };
+
+        let mut data = BytesMut::new();
+        data.put_t(&packet, &EncodeContext::new()).unwrap();
+        assert_eq!(data.len(), 4 + 1 + 1 + 2);
+        assert_eq!(data.get_t::<Derived>(&ctx).unwrap(), packet);
+
+        // Truncated length prefixed vectors fail instead of panicking
+        let mut data = BytesMut::from(&[1, 0, 0, 0, 0, 2, 1, 0][..]);
+        assert!(matches!(data.get_t::<Derived>(&ctx), Err(PacketError::UnexpectedEof { .. })));
+
+        // A bogus length doesn't reserve memory for values that aren't there
+        let mut data = BytesMut::from(&[1, 0, 0, 0, 0, 0, 0xff, 0xff][..]);
+        assert!(matches!(data.get_t::<Derived>(&ctx), Err(PacketError::UnexpectedEof { .. })));
+
+        let packet = Derived { ids: vec![0; 256], ..Default::default() };
+        let result = BytesMut::new().put_t(&packet, &EncodeContext::new());
+        assert!(matches!(result, Err(PacketError::ListTooLong { len: 256, max: 255 })));
+
+        // The condition is checked when writing, so the reader always gets the fields that were written
+        let packet = Derived { speed: Some(1.0), ..Default::default() };
+        let result = BytesMut::new().put_t(&packet, &EncodeContext::new());
+        assert!(matches!(result, Err(PacketError::ConditionMismatch("speed"))));
+        let packet = Derived { has_speed: true, ..Default::default() };
+        let result = BytesMut::new().put_t(&packet, &EncodeContext::new());
+        assert!(matches!(result, Err(PacketError::ConditionMismatch("speed"))));
+    }
+
+    #[test]
+    fn test_derive_versions() {
+        let packet = DerivedVersioned { old: 1, new: 2, between: 3 };
+        let expected = [
+            (ProtocolVersion::V860, 1, DerivedVersioned { old: 1, ..Default::default() }),
+            (ProtocolVersion::V1098, 6, DerivedVersioned { new: 2, between: 3, ..Default::default() }),
+            (ProtocolVersion::new(1200), 2, DerivedVersioned { new: 2, ..Default::default() }),
+        ];
+
+        for (version, len, result) in expected.iter() {
+            let mut ctx = DecodeContext::new();
+            ctx.set_version(*version);
+
+            let mut data = BytesMut::new();
+            data.put_t(&packet, &EncodeContext::with_version(*version)).unwrap();
+            assert_eq!(data.len(), *len);
+            assert_eq!(&data.get_t::<DerivedVersioned>(&ctx).unwrap(), result);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::fmt;
+
+/// Client protocol version, written as the client version without the dot (8.60 is 860)
+///
+/// Packet layouts are described for the supported versions below.
+/// Versions in between use the layout of the closest older supported version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProtocolVersion(u16);
+
+impl ProtocolVersion {
+    pub const V860: ProtocolVersion = ProtocolVersion(860);
+    pub const V1098: ProtocolVersion = ProtocolVersion(1098);
+
+    pub const fn new(version: u16) -> Self {
+        Self(version)
+    }
+
+    /// The version number as sent by the client in the login packets
+    pub fn number(self) -> u16 {
+        self.0
+    }
+
+    /// Clients since 12.00 use sequenced frames after the handshake, see FrameType::Sequenced
+    ///
+    /// Only the framing changes, packets are still read with the 10.98 layouts.
+    pub fn has_sequenced_frames(self) -> bool {
+        self.0 >= 1200
+    }
+}
+
+impl Default for ProtocolVersion {
+    fn default() -> Self {
+        Self::V1098
+    }
+}
+
+impl From<u16> for ProtocolVersion {
+    fn from(version: u16) -> Self {
+        Self(version)
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:02}", self.0 / 100, self.0 % 100)
+    }
+}
@@ -1,2 +1,3 @@
+pub mod otb;
 pub mod rsa;
-pub mod xtea;
\ No newline at end of file
+pub mod xtea;
@@ -0,0 +1,132 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use thiserror::Error;
+
+use crate::packet::ItemInfo;
+
+const NODE_START: u8 = 0xFE;
+const NODE_END: u8 = 0xFF;
+const ESCAPE: u8 = 0xFD;
+
+/// Item groups that carry a fluid byte
+const GROUP_SPLASH: u8 = 11;
+const GROUP_FLUID: u8 = 12;
+
+const FLAG_STACKABLE: u32 = 1 << 7;
+const FLAG_ANIMATION: u32 = 1 << 24;
+
+const ATTR_CLIENT_ID: u8 = 0x11;
+
+#[derive(Error, Debug)]
+pub enum OtbError {
+    #[error("failed to read items.otb")]
+    Io(#[from] io::Error),
+    #[error("invalid items.otb: {0}")]
+    Invalid(&'static str),
+}
+
+/// Loads the item info of all item types in an items.otb file, by client id
+pub fn load_item_info<P: AsRef<Path>>(path: P) -> Result<HashMap<u16, ItemInfo>, OtbError> {
+    read_item_info(&fs::read(path)?)
+}
+
+/// Reads the item info of all item types in the content of an items.otb file, by client id
+///
+/// Only the parts of items.otb the client layout depends on are read, the game server reads the rest.
+pub fn read_item_info(data: &[u8]) -> Result<HashMap<u16, ItemInfo>, OtbError> {
+    // Skip the file identifier
+    let mut bytes = data.get(4..).ok_or(OtbError::Invalid("truncated"))?.iter();
+    let mut items = HashMap::new();
+    let mut depth: i32 = 0;
+    // Group and unescaped props of the item node being read, the children of the root
+    let mut node: Option<(u8, Vec<u8>)> = None;
+
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            NODE_START => {
+                if let Some((group, props)) = node.take() {
+                    read_item(group, &props, &mut items)?;
+                }
+                let kind = *bytes.next().ok_or(OtbError::Invalid("truncated"))?;
+                depth += 1;
+                if depth == 2 {
+                    node = Some((kind, Vec::new()));
+                }
+            },
+            NODE_END => {
+                if let Some((group, props)) = node.take() {
+                    read_item(group, &props, &mut items)?;
+                }
+                depth -= 1;
+                match depth {
+                    0 => return Ok(items),
+                    d if d < 0 => return Err(OtbError::Invalid("unexpected node end")),
+                    _ => (),
+                }
+            },
+            byte => {
+                let byte = if byte == ESCAPE {
+                    *bytes.next().ok_or(OtbError::Invalid("truncated"))?
+                } else {
+                    byte
+                };
+                if let Some((_, props)) = node.as_mut() {
+                    props.push(byte);
+                }
+            },
+        }
+    }
+
+    Err(OtbError::Invalid("truncated"))
+}
+
+/// Reads an item node: u32 flags, then attributes of u8 id, u16 length and value
+fn read_item(group: u8, props: &[u8], items: &mut HashMap<u16, ItemInfo>) -> Result<(), OtbError> {
+    let flags = props.get(..4).ok_or(OtbError::Invalid("truncated item flags"))?;
+    let flags = u32::from_le_bytes([flags[0], flags[1], flags[2], flags[3]]);
+
+    let mut rest = &props[4..];
+    while !rest.is_empty() {
+        let header = rest.get(..3).ok_or(OtbError::Invalid("truncated item attribute"))?;
+        let len = u16::from_le_bytes([header[1], header[2]]) as usize;
+        let value = rest.get(3..3 + len).ok_or(OtbError::Invalid("truncated item attribute"))?;
+        rest = &rest[3 + len..];
+
+        if header[0] == ATTR_CLIENT_ID && len == 2 {
+            let client_id = u16::from_le_bytes([value[0], value[1]]);
+            // Several server ids may share a client id, the first one counts
+            items.entry(client_id).or_insert(ItemInfo {
+                stackable: flags & FLAG_STACKABLE != 0,
+                fluid: group == GROUP_SPLASH || group == GROUP_FLUID,
+                animated: flags & FLAG_ANIMATION != 0,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_item_info() {
+        let data = [
+            0, 0, 0, 0,
+            NODE_START, 0, 0, 0, 0, 0,
+                // Gold coin, stackable with server id 2148 and client id 3031
+                NODE_START, 0, 0x80, 0, 0, 0, 0x10, 2, 0, 0x64, 0x08, ATTR_CLIENT_ID, 2, 0, 0xD7, 0x0B, NODE_END,
+                // Splash with client id 0x0AFE, escaped
+                NODE_START, GROUP_SPLASH, 0, 0, 0, 1, ATTR_CLIENT_ID, 2, 0, ESCAPE, 0xFE, 0x0A, NODE_END,
+            NODE_END,
+        ];
+
+        let items = read_item_info(&data).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[&3031], ItemInfo { stackable: true, ..ItemInfo::default() });
+        assert_eq!(items[&0x0AFE], ItemInfo { fluid: true, animated: true, ..ItemInfo::default() });
+
+        assert!(matches!(read_item_info(&data[..data.len() - 1]), Err(OtbError::Invalid(_))));
+        assert!(matches!(read_item_info(&data[..14]), Err(OtbError::Invalid(_))));
+    }
+}
@@ -7,7 +7,7 @@ const ROUNDS: u32 = 32;
 // Port to Bytes to get rid of byteorder dependency? (or just bit shift)
 
 pub fn encrypt(data: &mut [u8], key: &[Wrapping<u32>]) {
-    if data.len() % 8 != 0 {
+    if !data.len().is_multiple_of(8) {
         panic!("xtea data not multiple of 8");
     }
 
@@ -32,7 +32,7 @@ fn encrypt_block(block: u64, key: &[Wrapping<u32>]) -> u64 {
 }
 
 pub fn decrypt(data: &mut [u8], key: &[Wrapping<u32>]) {
-    if data.len() % 8 != 0 {
+    if !data.len().is_multiple_of(8) {
         panic!("xtea data not multiple of 8");
     }
 
@@ -1,14 +1,17 @@
+use base::Position;
 use bytes::BytesMut;
-use protocol::{FrameType, packet::ClientPacket};
+use protocol::{FrameType, packet::{ClientPacket, DecodeContext, GameServerPacket}, packet::client::peek_client_version};
 
 use crate::{Origin, ProxyConnection, ProxyEventHandler};
 
 /// Event handler that will detect a game protocol handshake and enable XTEA with the correct key
+///
+/// 12.x clients switch to sequenced frames instead, the proxy decodes compressed frames but sends them uncompressed.
 #[derive(Default)]
 pub struct GameHandshaker;
 
 impl GameHandshaker {
-    pub fn new() -> Self { Self::default() }
+    pub fn new() -> Self { Self }
     pub fn new_boxed() -> Box<Self> {
         Box::new(Self::new())
     }
@@ -28,9 +31,17 @@ impl ProxyEventHandler for GameHandshaker {
             match from {
                 Origin::Client => {
                     let mut frame = frame.clone(); // clone really needed? BytesMut is very non-intuitive, Frame-abstraction?
-                    match ClientPacket::read_from(&mut frame)? {
+                    if let Some(version) = peek_client_version(&frame) {
+                        connection.decode_context_mut().set_version(version);
+                    }
+                    match ClientPacket::read_from(&mut frame, connection.decode_context())? {
                         ClientPacket::GameLogin(login_packet) => {
-                            connection.set_frame_type(FrameType::XTEA(login_packet.xtea_key));
+                            let xtea_key = login_packet.xtea_key;
+                            if connection.decode_context().version().has_sequenced_frames() {
+                                connection.set_frame_type(FrameType::Sequenced { xtea_key, compress: false });
+                            } else {
+                                connection.set_frame_type(FrameType::XTEA(xtea_key));
+                            }
                         },
                         packet => {
                             anyhow::bail!(format!("Wrong first packet from client, expected GameLogin, got {:?}", packet));
@@ -45,3 +56,137 @@ impl ProxyEventHandler for GameHandshaker {
         Ok(frame)
     }
 }
+
+/// Event handler that follows the player position in the server frames, map descriptions are read relative to it
+///
+/// The position is set by FullWorld. MoveCreature from the player position moves the player,
+/// if the map rows or floors coming into sight follow it in the same frame.
+/// Frames are forwarded unchanged, reading stops at the first packet the proxy can't read.
+#[derive(Default)]
+pub struct PositionTracker;
+
+impl PositionTracker {
+    pub fn new() -> Self { Self }
+    pub fn new_boxed() -> Box<Self> {
+        Box::new(Self::new())
+    }
+}
+
+impl ProxyEventHandler for PositionTracker {
+    fn on_frame(&self, connection: &mut ProxyConnection, from: Origin, frame: BytesMut) -> anyhow::Result<BytesMut> {
+        // Frames after the nonce and the login
+        if from == Origin::Server && connection.current_frame_id() > 1 {
+            track_position(connection.decode_context_mut(), frame.clone());
+        }
+        Ok(frame)
+    }
+}
+
+fn track_position(ctx: &mut DecodeContext, mut frame: BytesMut) {
+    let mut moved_to = None;
+    while !frame.is_empty() {
+        let packet = match GameServerPacket::read_from(&mut frame, ctx) {
+            Ok(packet) => packet,
+            Err(_) => return,
+        };
+
+        let position = ctx.player_position();
+        match packet {
+            GameServerPacket::FullWorld(world) => {
+                ctx.set_player_position(world.player_position);
+                moved_to = None;
+            },
+            GameServerPacket::MoveCreature(packet) if packet.old_position == position => {
+                moved_to = Some(packet.new_position);
+            },
+            // Read with the old floor, the rows after them with the new one.
+            // Moving underground deletes the player instead of moving it, so only the floor is known.
+            GameServerPacket::FloorChangeUp(_) => {
+                let z = position.z.saturating_sub(1);
+                ctx.set_player_position(moved_to.take().unwrap_or(Position { z, ..position }));
+            },
+            GameServerPacket::FloorChangeDown(_) => {
+                let z = u8::min(position.z + 1, 15);
+                ctx.set_player_position(moved_to.take().unwrap_or(Position { z, ..position }));
+            },
+            GameServerPacket::WorldRowNorth(_) | GameServerPacket::WorldRowEast(_)
+            | GameServerPacket::WorldRowSouth(_) | GameServerPacket::WorldRowWest(_) => {
+                if let Some(to) = moved_to.take() {
+                    ctx.set_player_position(to);
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::Wrapping;
+
+    use protocol::packet::{EncodeContext, ItemInfo, client::GameLogin};
+    use protocol::packet::game::{
+        FullWorld, Item, LoginSuccess, MoveCreature, Thing, Tile, WorldData, WorldRowEast, VIEWPORT_HEIGHT, VIEWPORT_WIDTH,
+    };
+
+    use crate::Proxy;
+
+    fn write(packets: Vec<GameServerPacket>) -> BytesMut {
+        let mut frame = BytesMut::new();
+        for packet in packets {
+            packet.write_to(&mut frame, &EncodeContext::new()).unwrap();
+        }
+        frame
+    }
+
+    /// A tile with a stack of coins, which can only be read knowing that coins are stackable
+    fn coins() -> WorldData {
+        let mut tile = Tile::default();
+        tile.things[0] = Some(Thing::Item(Item { client_id: 3031, stack_size: Some(100), ..Item::default() }));
+        WorldData::Tile(tile)
+    }
+
+    #[test]
+    fn test_track_position() {
+        let coin = ItemInfo { stackable: true, ..ItemInfo::default() };
+        let proxy = Proxy::builder(String::new(), String::new())
+            .with_item_info(vec![(3031, coin)])
+            .with_event_handler(GameHandshaker::new_boxed())
+            .with_event_handler(PositionTracker::new_boxed())
+            .build();
+        let mut connection = proxy.new_connection(0, String::new());
+
+        connection.on_frame(Origin::Server, BytesMut::from(&[31, 0, 0, 0, 0, 0][..])).unwrap();
+        let login = GameLogin {
+            client_version: 1098,
+            xtea_key: [Wrapping(1), Wrapping(2), Wrapping(3), Wrapping(4)],
+            character_name: "Rustia".to_string(),
+            ..GameLogin::default()
+        };
+        let mut frame = BytesMut::new();
+        ClientPacket::from(login).write_to(&mut frame, &EncodeContext::new()).unwrap();
+        connection.on_frame(Origin::Client, frame).unwrap();
+        assert!(matches!(connection.frame_type(), FrameType::XTEA(_)));
+
+        let start = Position::new(100, 100, 7);
+        let frame = write(vec![
+            LoginSuccess::default().into(),
+            FullWorld {
+                player_position: start,
+                world_chunk: vec![coins(), WorldData::Empty(VIEWPORT_WIDTH * VIEWPORT_HEIGHT * 8 - 1)],
+            }.into(),
+            MoveCreature { old_position: start, old_stack_index: 1, new_position: Position::new(101, 100, 7) }.into(),
+            WorldRowEast { world_chunk: vec![coins(), WorldData::Empty(VIEWPORT_HEIGHT * 8 - 1)] }.into(),
+        ]);
+        connection.on_frame(Origin::Server, frame).unwrap();
+        assert_eq!(connection.decode_context().player_position(), Position::new(101, 100, 7));
+
+        // Another creature leaving the player tile
+        let frame = write(vec![
+            MoveCreature { old_position: Position::new(101, 100, 7), old_stack_index: 2, new_position: Position::new(102, 100, 7) }.into(),
+        ]);
+        connection.on_frame(Origin::Server, frame).unwrap();
+        assert_eq!(connection.decode_context().player_position(), Position::new(101, 100, 7));
+    }
+}
@@ -2,13 +2,14 @@ use std::sync::Arc;
 
 use bytes::{BytesMut, Bytes};
 use futures::{SinkExt, StreamExt};
-use protocol::{FrameType, TibiaCodec};
+use protocol::{FrameType, TibiaCodec, packet::{DecodeContext, EncodeContext, ItemInfo}};
 use tokio::net::{TcpListener, TcpStream};
 use tokio_util::codec::Framed;
 
 pub mod debug;
 pub mod login;
 pub mod game;
+pub mod rsa;
 
 /// Event handler for extending the proxy functionality
 /// Proxy event handlers always run in the order they were added to the proxy
@@ -46,6 +47,7 @@ pub struct ProxyBuilder {
     listen_addr: String,
     server_addr: String,
     event_handlers: Vec<Box<dyn ProxyEventHandler + Send + Sync>>,
+    decode_context: DecodeContext,
 }
 
 impl ProxyBuilder {
@@ -55,6 +57,7 @@ impl ProxyBuilder {
             listen_addr,
             server_addr,
             event_handlers: vec![],
+            decode_context: DecodeContext::new(),
         }
     }
 
@@ -65,12 +68,28 @@ impl ProxyBuilder {
         self
     }
 
+    /// Sets the decode context each connection starts with, e.g to use another RSA key than the OpenTibia one
+    pub fn with_decode_context(mut self, decode_context: DecodeContext) -> ProxyBuilder {
+        self.decode_context = decode_context;
+        self
+    }
+
+    /// Adds item info by client id to the decode context, needed to read items in game packets
+    /// Call it after with_decode_context, which replaces the context
+    pub fn with_item_info(mut self, items: impl IntoIterator<Item = (u16, ItemInfo)>) -> ProxyBuilder {
+        for (client_id, info) in items {
+            self.decode_context.set_item_info(client_id, info);
+        }
+        self
+    }
+
     /// Build the proxy
     pub fn build(self) -> Proxy {
         Proxy {
             listen_addr: self.listen_addr,
             server_addr: self.server_addr,
             event_handlers: Arc::new(self.event_handlers),
+            decode_context: self.decode_context,
         }
     }
 
@@ -87,6 +106,7 @@ pub struct Proxy {
     listen_addr: String,
     server_addr: String,
     event_handlers: Arc<Vec<Box<dyn ProxyEventHandler + Send + Sync>>>,
+    decode_context: DecodeContext,
 }
 
 impl Proxy {
@@ -97,25 +117,30 @@ impl Proxy {
 
     /// Starts the proxy and consumes self
     pub async fn run(self) -> anyhow::Result<()> { // -> ProxyResult
-        let listener = TcpListener::bind(self.listen_addr).await?;
+        let listener = TcpListener::bind(&self.listen_addr).await?;
         
         let mut connection_id = 0;
         while let Ok((inbound, _)) = listener.accept().await {
-            let connection = ProxyConnection {
-                id: connection_id,
-                client_addr: inbound.peer_addr()?.to_string(),
-                server_addr: self.server_addr.clone(),
-                event_handlers: Arc::clone(&self.event_handlers),
-                frame_type: FrameType::Raw,
-                current_frame_id: 0,
-            };
-
+            let connection = self.new_connection(connection_id, inbound.peer_addr()?.to_string());
             tokio::spawn(connection.run(inbound));
             connection_id += 1;
         }
 
         Ok(())
     }
+
+    /// Creates the state of a new connection from a client
+    fn new_connection(&self, id: usize, client_addr: String) -> ProxyConnection {
+        ProxyConnection {
+            id,
+            client_addr,
+            server_addr: self.server_addr.clone(),
+            event_handlers: Arc::clone(&self.event_handlers),
+            frame_type: FrameType::Raw,
+            current_frame_id: 0,
+            decode_context: self.decode_context.clone(),
+        }
+    }
 }
 
 /// A proxy connection
@@ -126,6 +151,7 @@ pub struct ProxyConnection {
     event_handlers: Arc<Vec<Box<dyn ProxyEventHandler + Send + Sync>>>,
     frame_type: FrameType,
     current_frame_id: usize,
+    decode_context: DecodeContext,
 }
 
 impl ProxyConnection {
@@ -166,6 +192,21 @@ impl ProxyConnection {
         self.frame_type = frame_type;
     }
 
+    /// Returns the context used when decoding packets on this connection
+    pub fn decode_context(&self) -> &DecodeContext {
+        &self.decode_context
+    }
+
+    /// Returns the decode context mutably, e.g to track the player position for map descriptions
+    pub fn decode_context_mut(&mut self) -> &mut DecodeContext {
+        &mut self.decode_context
+    }
+
+    /// Returns a context for encoding packets with the protocol version of this connection
+    pub fn encode_context(&self) -> EncodeContext {
+        EncodeContext::with_version(self.decode_context.version())
+    }
+
     /// Runs the proxy by calling proxy()
     /// Triggers the on_disconnect handlers with the result
     async fn run(mut self, inbound: TcpStream) {
@@ -208,15 +249,9 @@ impl ProxyConnection {
             };
 
             if let Some(frame) = frame {
-                let mut frame = frame?;
-
-                // Call middleware
-                for event_handler in event_handlers.iter() {
-                    frame = event_handler.on_frame(self, origin, frame)?;
-                }
+                let frame: Bytes = self.on_frame(origin, frame?)?.into();
 
                 // Send the frame to its destination
-                let frame: Bytes = frame.into();
                 match origin {
                     Origin::Client => server.send(frame).await?,
                     Origin::Server => client.send(frame).await?,
@@ -225,8 +260,15 @@ impl ProxyConnection {
                 // Disconnect by <origin>
                 return Ok(origin);
             }
+        }
+    }
 
-            self.current_frame_id += 1;
+    /// Passes a frame through the event handlers and moves on to the next frame
+    fn on_frame(&mut self, origin: Origin, mut frame: BytesMut) -> anyhow::Result<BytesMut> {
+        for event_handler in self.event_handlers.clone().iter() {
+            frame = event_handler.on_frame(self, origin, frame)?;
         }
+        self.current_frame_id += 1;
+        Ok(frame)
     }
 }
@@ -1,6 +1,6 @@
 use bytes::{Buf, BytesMut};
 
-use protocol::{FrameType, packet::ClientPacket, packet::LoginServerPacket};
+use protocol::{FrameType, packet::ClientPacket, packet::LoginServerPacket, packet::client::peek_client_version};
 
 use crate::{Origin, ProxyConnection, ProxyEventHandler};
 
@@ -9,7 +9,7 @@ use crate::{Origin, ProxyConnection, ProxyEventHandler};
 pub struct LoginHandshaker;
 
 impl LoginHandshaker {
-    pub fn new() -> Self { Self::default() }
+    pub fn new() -> Self { Self }
     pub fn new_boxed() -> Box<Self> {
         Box::new(Self::new())
     }
@@ -21,7 +21,10 @@ impl ProxyEventHandler for LoginHandshaker {
             match from {
                 Origin::Client => {
                     let mut frame = frame.clone(); // clone really needed? BytesMut is very non-intuitive, Frame-abstraction?
-                    match ClientPacket::read_from(&mut frame)? {
+                    if let Some(version) = peek_client_version(&frame) {
+                        connection.decode_context_mut().set_version(version);
+                    }
+                    match ClientPacket::read_from(&mut frame, connection.decode_context())? {
                         ClientPacket::AccountLogin(login_packet) => {
                             connection.set_frame_type(FrameType::XTEA(login_packet.xtea_key));
                         },
@@ -65,16 +68,17 @@ impl ProxyEventHandler for GameServerInjector {
                     anyhow::bail!("The client sent more than one frame, which is wrong on a login connection.");
                 },
                 Origin::Server => {
+                    let encode_context = connection.encode_context();
                     let mut new_frame = BytesMut::new();
                     while frame.remaining() > 0 {
-                        let mut packet = LoginServerPacket::read_from(&mut frame)?;
+                        let mut packet = LoginServerPacket::read_from(&mut frame, connection.decode_context())?;
                         if let LoginServerPacket::CharacterList(ref mut charlist) = packet {
                             for world in charlist.worlds.iter_mut() {
                                 world.ip = self.server_ip.clone();
                                 world.port = self.server_port;
                             }
                         }
-                        packet.write_to(&mut new_frame)?;
+                        packet.write_to(&mut new_frame, &encode_context)?;
                     }
                     return Ok(new_frame)
                 }
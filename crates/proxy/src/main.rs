@@ -1,5 +1,6 @@
-use std::env;
+use std::{collections::HashMap, env, sync::Arc};
 
+use protocol::{packet::DecodeContext, util::{otb, rsa::RsaKey}};
 use rustia_proxy::*;
 
 #[tokio::main]
@@ -11,28 +12,58 @@ pub async fn main() -> anyhow::Result<()> {
         .nth(2)
         .unwrap_or_else(|| "127.0.0.1:7171".to_string());
 
-    // let game_listen_addr = env::args()
-    //     .nth(3)
-    //     .unwrap_or_else(|| "127.0.0.1:7174".to_string());
-    // let game_server_addr = env::args()
-    //     .nth(4)
-    //     .unwrap_or_else(|| "127.0.0.1:7172".to_string());
+    let game_listen_addr = env::args()
+        .nth(3)
+        .unwrap_or_else(|| "127.0.0.1:7174".to_string());
+    let game_server_addr = env::args()
+        .nth(4)
+        .unwrap_or_else(|| "127.0.0.1:7172".to_string());
 
-    let login = Proxy::builder("127.0.0.1:7173".to_string(), "127.0.0.1:7171".to_string())
+    // The character list points the client to the game proxy
+    let (game_listen_ip, game_listen_port) = match game_listen_addr.rsplit_once(':') {
+        Some((ip, port)) => (ip.to_string(), port.parse()?),
+        None => anyhow::bail!("Game listen address must be ip:port"),
+    };
+
+    // Optional key files, private key the client encrypts with and public key of the server
+    let client_key = match env::args().nth(5) {
+        Some(path) => Arc::new(RsaKey::from_file(path)?),
+        None => RsaKey::open_tibia(),
+    };
+    let server_key = match env::args().nth(6) {
+        Some(path) => Some(Arc::new(RsaKey::from_file(path)?)),
+        None => None,
+    };
+    // Optional items.otb, the game proxy needs it to read the items in map descriptions
+    let item_info = match env::args().nth(7) {
+        Some(path) => otb::load_item_info(path)?,
+        None => HashMap::new(),
+    };
+
+    let mut login = Proxy::builder(login_listen_addr, login_server_addr)
+        .with_decode_context(DecodeContext::with_rsa_key(client_key.clone()))
         .with_event_handler(debug::DebugEventHandler::new_boxed("Login".to_string()))
         .with_event_handler(login::LoginHandshaker::new_boxed())
-        .with_event_handler(login::GameServerInjector::new_boxed("127.0.0.1".to_string(), 7174))
-        .build();
+        .with_event_handler(login::GameServerInjector::new_boxed(game_listen_ip, game_listen_port));
 
-    let game = Proxy::builder("127.0.0.1:7174".to_string(), "127.0.0.1:7172".to_string())
+    let mut game = Proxy::builder(game_listen_addr, game_server_addr)
+        .with_decode_context(DecodeContext::with_rsa_key(client_key))
+        .with_item_info(item_info)
         .with_event_handler(debug::DebugEventHandler::new_boxed("Game".to_string()))
         .with_event_handler(game::GameHandshaker::new_boxed())
-        .build();
+        .with_event_handler(game::PositionTracker::new_boxed());
 
-    tokio::join!(
-        tokio::spawn(game.run()),
-        tokio::spawn(login.run()),
+    if let Some(server_key) = server_key {
+        login = login.with_event_handler(rsa::RsaReencryptor::new_boxed(server_key.clone()));
+        game = game.with_event_handler(rsa::RsaReencryptor::new_boxed(server_key));
+    }
+
+    let (game_result, login_result) = tokio::join!(
+        tokio::spawn(game.build().run()),
+        tokio::spawn(login.build().run()),
     );
-    
+    game_result??;
+    login_result??;
+
     Ok(())
 }
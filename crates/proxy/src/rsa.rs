@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use bytes::{BufMut, BytesMut};
+use protocol::{packet::{PacketPayload, client::{AccountLogin, ClientPacketKind, GameLogin}}, util::rsa::RsaKey};
+
+use crate::{Origin, ProxyConnection, ProxyEventHandler};
+
+/// Event handler that re-encrypts the RSA blocks of the client login packets for the upstream server
+///
+/// The client blocks are decrypted with the private key of the connection decode context,
+/// and encrypted again with the public key of the server.
+/// Must be added after LoginHandshaker/GameHandshaker, since they need to read the original frame
+/// and set the protocol version that decides where the blocks are.
+pub struct RsaReencryptor {
+    server_key: Arc<RsaKey>,
+}
+
+impl RsaReencryptor {
+    pub fn new(server_key: Arc<RsaKey>) -> Self {
+        Self { server_key }
+    }
+
+    pub fn new_boxed(server_key: Arc<RsaKey>) -> Box<Self> {
+        Box::new(Self::new(server_key))
+    }
+
+    fn reencrypt(&self, client_key: &RsaKey, frame: &[u8], offsets: &[usize]) -> anyhow::Result<BytesMut> {
+        let block_size = client_key.block_size();
+        let mut new_frame = BytesMut::with_capacity(frame.len());
+        let mut pos = 0;
+
+        for offset in offsets.iter().copied() {
+            if offset < pos || offset + block_size > frame.len() {
+                anyhow::bail!("Login packet too short for its RSA blocks");
+            }
+            new_frame.put_slice(&frame[pos..offset]);
+            new_frame.put_slice(&client_key.reencrypt(&frame[offset..offset + block_size], &self.server_key)?);
+            pos = offset + block_size;
+        }
+
+        new_frame.put_slice(&frame[pos..]);
+        Ok(new_frame)
+    }
+}
+
+impl ProxyEventHandler for RsaReencryptor {
+    fn on_frame(&self, connection: &mut ProxyConnection, from: Origin, frame: BytesMut) -> anyhow::Result<BytesMut> {
+        // Login packets are the first client frame on login connections, and the second (after nonce) on game connections
+        if from != Origin::Client || connection.current_frame_id() > 1 {
+            return Ok(frame);
+        }
+
+        let version = connection.decode_context().version();
+        let client_key = connection.decode_context().rsa_key();
+        let block_size = client_key.block_size();
+        let id = frame.first().copied();
+        match id {
+            Some(_) if id == <AccountLogin as PacketPayload<ClientPacketKind>>::id(version) => {
+                // Credentials block after the header, auth token block at the end
+                let offset = 1 + AccountLogin::rsa_block_offset(version);
+                if AccountLogin::has_auth_token(version) {
+                    self.reencrypt(client_key, &frame, &[offset, frame.len().saturating_sub(block_size)])
+                } else {
+                    self.reencrypt(client_key, &frame, &[offset])
+                }
+            },
+            Some(_) if id == <GameLogin as PacketPayload<ClientPacketKind>>::id(version) => {
+                self.reencrypt(client_key, &frame, &[1 + GameLogin::rsa_block_offset(version)])
+            },
+            _ => Ok(frame),
+        }
+    }
+}
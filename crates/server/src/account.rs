@@ -0,0 +1,135 @@
+use std::{collections::HashMap, fs, path::PathBuf, sync::RwLock};
+
+use anyhow::Context;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub name: String,
+    pub password: String,
+    pub premium_days: u32,
+    pub characters: Vec<String>,
+}
+
+/// Storage of accounts used by the login server
+pub trait AccountStore: Send + Sync {
+    /// Returns the account with the provided name, None if it doesn't exist
+    fn account(&self, name: &str) -> anyhow::Result<Option<Account>>;
+
+    /// Returns the account if it exists and the password matches
+    fn authenticate(&self, name: &str, password: &str) -> anyhow::Result<Option<Account>> {
+        Ok(self.account(name)?.filter(|account| account.password == password))
+    }
+}
+
+/// Accounts kept in memory, e.g for tests and development
+#[derive(Debug, Default)]
+pub struct MemoryAccountStore {
+    accounts: RwLock<HashMap<String, Account>>,
+}
+
+impl MemoryAccountStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an account, replacing any account with the same name
+    pub fn insert(&self, account: Account) {
+        self.accounts.write().unwrap().insert(account.name.clone(), account);
+    }
+}
+
+impl AccountStore for MemoryAccountStore {
+    fn account(&self, name: &str) -> anyhow::Result<Option<Account>> {
+        Ok(self.accounts.read().unwrap().get(name).cloned())
+    }
+}
+
+/// Accounts read from a text file, one account per line:
+///
+/// ```text
+/// # name:password:premium days:character, character
+/// rustia:secret:30:Rustia, Ferris
+/// ```
+///
+/// The file is read on every lookup, so accounts can be edited while the server is running.
+/// Passwords are stored in plain text.
+#[derive(Debug)]
+pub struct FileAccountStore {
+    path: PathBuf,
+}
+
+impl FileAccountStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Parses all accounts in the file
+    pub fn load(&self) -> anyhow::Result<Vec<Account>> {
+        let text = fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read accounts from {}", self.path.display()))?;
+        parse_accounts(&text)
+    }
+}
+
+impl AccountStore for FileAccountStore {
+    fn account(&self, name: &str) -> anyhow::Result<Option<Account>> {
+        Ok(self.load()?.into_iter().find(|account| account.name == name))
+    }
+}
+
+fn parse_accounts(text: &str) -> anyhow::Result<Vec<Account>> {
+    let mut accounts = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.splitn(4, ':').collect();
+        if fields.len() != 4 {
+            anyhow::bail!("line {}: expected name:password:premium days:characters", i + 1);
+        }
+
+        accounts.push(Account {
+            name: fields[0].to_string(),
+            password: fields[1].to_string(),
+            premium_days: fields[2].trim().parse().with_context(|| format!("line {}: invalid premium days", i + 1))?,
+            characters: fields[3].split(',')
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .collect(),
+        });
+    }
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_accounts() {
+        let accounts = parse_accounts("# comment\n\nrustia:secret:30:Rustia, Ferris the Crab\nempty:pw:0:\n").unwrap();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0], Account {
+            name: "rustia".to_string(),
+            password: "secret".to_string(),
+            premium_days: 30,
+            characters: vec!["Rustia".to_string(), "Ferris the Crab".to_string()],
+        });
+        assert!(accounts[1].characters.is_empty());
+
+        assert!(parse_accounts("rustia:secret").is_err());
+        assert!(parse_accounts("rustia:secret:many:Rustia").is_err());
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let store = MemoryAccountStore::new();
+        store.insert(Account { name: "rustia".to_string(), password: "secret".to_string(), ..Account::default() });
+
+        assert!(store.authenticate("rustia", "secret").unwrap().is_some());
+        assert!(store.authenticate("rustia", "wrong").unwrap().is_none());
+        assert!(store.authenticate("unknown", "secret").unwrap().is_none());
+    }
+}
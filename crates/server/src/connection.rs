@@ -0,0 +1,70 @@
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+use protocol::{FrameType, TibiaCodec, packet::{DecodeContext, EncodeContext, PacketWrite, ProtocolVersion}};
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+
+/// A client connection framed by the TibiaCodec
+pub struct Connection {
+    framed: Framed<TcpStream, TibiaCodec>,
+    addr: String,
+    decode_context: DecodeContext,
+    encode_context: EncodeContext,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream, decode_context: DecodeContext) -> anyhow::Result<Self> {
+        Ok(Self {
+            addr: stream.peer_addr()?.to_string(),
+            framed: Framed::new(stream, TibiaCodec::new()),
+            encode_context: EncodeContext::with_version(decode_context.version()),
+            decode_context,
+        })
+    }
+
+    /// Returns the address of the client
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// Changes the frame type of the TibiaCodec (for the next frame onwards)
+    pub fn set_frame_type(&mut self, frame_type: FrameType) {
+        self.framed.codec_mut().set_frame_type(frame_type);
+    }
+
+    /// Returns the context used when decoding packets from the client
+    pub fn decode_context(&self) -> &DecodeContext {
+        &self.decode_context
+    }
+
+    /// Returns the context used when encoding packets to the client
+    pub fn encode_context(&self) -> &EncodeContext {
+        &self.encode_context
+    }
+
+    /// Sets the protocol version used for both decoding and encoding
+    pub fn set_version(&mut self, version: ProtocolVersion) {
+        self.decode_context.set_version(version);
+        self.encode_context.set_version(version);
+    }
+
+    /// Reads the next frame, None if the client disconnected
+    ///
+    /// Cancel safe, no data is lost if the future is dropped before it completes, e.g in a select.
+    pub async fn read_frame(&mut self) -> anyhow::Result<Option<BytesMut>> {
+        match self.framed.next().await {
+            Some(frame) => Ok(Some(frame?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes the packets to a single frame and sends it
+    pub async fn send<P: PacketWrite>(&mut self, packets: &[P]) -> anyhow::Result<()> {
+        let mut frame = BytesMut::new();
+        for packet in packets.iter() {
+            packet.write_to(&mut frame, &self.encode_context)?;
+        }
+        self.framed.send(frame.freeze()).await?;
+        Ok(())
+    }
+}
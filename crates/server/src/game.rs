@@ -0,0 +1,482 @@
+use std::{
+    sync::{Arc, atomic::{AtomicU32, Ordering}},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use base::Position;
+use game::{
+    creature::{Creature as MapCreature, Outfit as MapOutfit},
+    description::MapDescriber,
+    item::ItemTypes,
+    map::{MAX_CLIENT_STACK, Map},
+    movement::{SpeedFormula, StepResult, Walker},
+    thing::ThingId,
+};
+use protocol::{
+    FrameType,
+    packet::{ClientPacket, DecodeContext, GameServerPacket, PacketError, ProtocolVersion},
+    packet::client::{GameLogin, peek_client_version},
+    packet::game::*,
+};
+use tokio::net::TcpListener;
+
+use crate::{connection::Connection, session::SessionStore};
+
+/// Creature ids of players start here, like in TFS
+const PLAYER_ID_START: u32 = 0x1000_0000;
+
+/// Game server accepting game logins and placing the players in a world
+pub struct GameServer {
+    listen_addr: String,
+    map: Arc<Map>,
+    item_types: Arc<ItemTypes>,
+    spawn: Position,
+    sessions: Arc<SessionStore>,
+    decode_context: DecodeContext,
+}
+
+/// State shared by all connections of the game server
+struct Shared {
+    map: Arc<Map>,
+    item_types: Arc<ItemTypes>,
+    spawn: Position,
+    sessions: Arc<SessionStore>,
+}
+
+impl GameServer {
+    /// Creates a game server where all players spawn at the provided position
+    ///
+    /// Logins are only accepted with session keys issued by a login server sharing the session store.
+    pub fn new(listen_addr: String, map: Arc<Map>, spawn: Position, sessions: Arc<SessionStore>) -> Self {
+        Self {
+            listen_addr,
+            map,
+            item_types: Arc::new(ItemTypes::new()),
+            spawn,
+            sessions,
+            decode_context: DecodeContext::new(),
+        }
+    }
+
+    /// Sets the item types used to describe map items to the client
+    ///
+    /// Without item types, the server ids of map items are sent as client ids.
+    pub fn with_item_types(mut self, item_types: Arc<ItemTypes>) -> Self {
+        self.item_types = item_types;
+        self
+    }
+
+    /// Sets the decode context each connection starts with, e.g to use another RSA key than the OpenTibia one
+    pub fn with_decode_context(mut self, decode_context: DecodeContext) -> Self {
+        self.decode_context = decode_context;
+        self
+    }
+
+    /// Starts the game server and consumes self
+    pub async fn run(self) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(&self.listen_addr).await?;
+        let next_player_id = Arc::new(AtomicU32::new(PLAYER_ID_START));
+        let shared = Arc::new(Shared {
+            map: self.map,
+            item_types: self.item_types,
+            spawn: self.spawn,
+            sessions: self.sessions,
+        });
+
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    println!("Game: Failed to accept connection {:?}", err);
+                    continue;
+                },
+            };
+            let connection = match Connection::new(stream, self.decode_context.clone()) {
+                Ok(connection) => connection,
+                Err(err) => {
+                    println!("Game: Failed to accept connection {:?}", err);
+                    continue;
+                },
+            };
+
+            let player_id = next_player_id.fetch_add(1, Ordering::Relaxed);
+            let shared = Arc::clone(&shared);
+            tokio::spawn(async move {
+                let addr = connection.addr().to_string();
+                if let Err(err) = handle_game(connection, &shared, player_id).await {
+                    println!("Game:{} Error {:?}", addr, err);
+                }
+            });
+        }
+    }
+}
+
+async fn handle_game(mut connection: Connection, shared: &Shared, player_id: u32) -> anyhow::Result<()> {
+    let login = match handshake(&mut connection, &shared.sessions).await? {
+        Some(login) => login,
+        None => return Ok(()),
+    };
+    println!("Game:{} {} logged in", connection.addr(), login.character_name);
+
+    // The player is placed on the spawn tile and removed again when the connection ends
+    let spawn = shared.spawn;
+    let player = shared.map.things_mut().insert(player_creature(player_id, login.character_name));
+    if shared.map.place_creature(player, spawn, &shared.item_types).is_none() {
+        shared.map.things_mut().remove(player);
+        anyhow::bail!("No tile at the spawn {:?}", spawn);
+    }
+
+    let result = play(&mut connection, shared, player_id, player).await;
+
+    shared.map.remove_creature(player);
+    shared.map.things_mut().remove(player);
+    result
+}
+
+/// Sends the world to the player, then handles the packets of the client until it disconnects
+async fn play(connection: &mut Connection, shared: &Shared, player_id: u32, player: ThingId) -> anyhow::Result<()> {
+    let spawn = shared.spawn;
+    let light = LightInfo { light_level: 250, light_color: 215 };
+    let formula = SpeedFormula::default();
+    let mut walker = Walker::new(formula);
+    connection.send(&[
+        GameServerPacket::from(LoginSuccess {
+            player_id,
+            beat_duration: formula.beat_duration,
+            speed_a: formula.a,
+            speed_b: formula.b,
+            speed_c: formula.c,
+            ..LoginSuccess::default()
+        }),
+        PendingStateEntered.into(),
+        EnterWorld.into(),
+        MapDescriber::new(&shared.map, &shared.item_types).full_world(spawn).into(),
+        WorldLight { light: light.clone() }.into(),
+        CreatureLight { creature_id: player_id, light }.into(),
+    ]).await?;
+
+    loop {
+        // Steps of the walk are taken while waiting for packets
+        let next_step = walker.next_step().unwrap_or_else(Instant::now);
+        let mut frame = tokio::select! {
+            frame = connection.read_frame() => match frame? {
+                Some(frame) => frame,
+                None => break,
+            },
+            _ = tokio::time::sleep_until(next_step.into()), if walker.is_walking() => {
+                step(connection, shared, &mut walker, player).await?;
+                continue;
+            },
+        };
+
+        while !frame.is_empty() {
+            match ClientPacket::read_from(&mut frame, connection.decode_context()) {
+                Ok(ClientPacket::Ping(_)) => connection.send(&[GameServerPacket::from(Pong)]).await?,
+                Ok(ClientPacket::AutoWalk(auto_walk)) => walker.walk(auto_walk.directions),
+                Ok(ClientPacket::StopWalk(_)) => walker.stop(),
+                Ok(packet) => match (packet.walk_direction(), packet.turn_direction()) {
+                    (Some(direction), _) => walker.walk(Some(direction)),
+                    (_, Some(direction)) => {
+                        // The client only turns the player once it's told to
+                        if let Some((position, stack_index)) = shared.map.turn_creature(player, direction) {
+                            if stack_index < MAX_CLIENT_STACK {
+                                let turn = CreatureTurn { id: player_id, direction, walk_through: false };
+                                connection.send(&[GameServerPacket::from(UpdateTileThing {
+                                    position,
+                                    stack_index: stack_index as u8,
+                                    thing: Thing::CreatureTurn(turn),
+                                })]).await?;
+                            }
+                        }
+                    },
+                    _ => println!("Game:{} Unhandled packet {:?}", connection.addr(), packet),
+                },
+                Err(PacketError::UnknownPacket(id)) => {
+                    // The rest of the frame can't be read without knowing the packet
+                    println!("Game:{} Unknown packet {:#x}", connection.addr(), id);
+                    break;
+                },
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Takes the next step of the walk of the player, and sends the result to the client
+async fn step(connection: &mut Connection, shared: &Shared, walker: &mut Walker, player: ThingId) -> anyhow::Result<()> {
+    let describer = MapDescriber::new(&shared.map, &shared.item_types);
+    match walker.step_path(&shared.map, &shared.item_types, player, Instant::now()) {
+        Some(StepResult::Moved(creature_move, _)) => {
+            let packets = creature_move.own_packets(&describer);
+            connection.send(&packets).await
+        },
+        Some(StepResult::Cancel(err)) => {
+            println!("Game:{} Can't walk: {}", connection.addr(), err);
+            let direction = shared.map.things().creature(player).map(|creature| creature.direction).unwrap_or_default();
+            connection.send(&[GameServerPacket::from(CancelWalk { direction })]).await
+        },
+        Some(StepResult::Wait(_)) | None => Ok(()),
+    }
+}
+
+/// Sends the nonce and reads the GameLogin, then switches to encrypted frames
+///
+/// Returns None if the client disconnected or was refused because of an invalid session.
+async fn handshake(connection: &mut Connection, sessions: &SessionStore) -> anyhow::Result<Option<GameLogin>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let nonce = Nonce {
+        timestamp: now.as_secs() as u32,
+        random_number: rand::random(),
+    };
+
+    // The nonce is the only length prefixed frame, the login after it is raw
+    connection.set_frame_type(FrameType::LengthPrefixed);
+    connection.send(&[GameServerPacket::from(nonce.clone())]).await?;
+    connection.set_frame_type(FrameType::Raw);
+
+    let mut frame = match connection.read_frame().await? {
+        Some(frame) => frame,
+        None => return Ok(None),
+    };
+
+    match peek_client_version(&frame) {
+        Some(version) if version >= ProtocolVersion::V1098 => connection.set_version(version),
+        version => anyhow::bail!("Unsupported client version {:?}", version),
+    }
+
+    let login = match ClientPacket::read_from(&mut frame, connection.decode_context())? {
+        ClientPacket::GameLogin(login) => login,
+        packet => anyhow::bail!("Wrong first packet, expected GameLogin, got {:?}", packet),
+    };
+
+    if login.challenge_timestamp != nonce.timestamp || login.challenge_rand_num != nonce.random_number {
+        anyhow::bail!("Challenge does not match the nonce");
+    }
+
+    if connection.decode_context().version().has_sequenced_frames() {
+        connection.set_frame_type(FrameType::Sequenced { xtea_key: login.xtea_key, compress: true });
+    } else {
+        connection.set_frame_type(FrameType::XTEA(login.xtea_key));
+    }
+
+    if sessions.verify(&login.session_key, &login.character_name).is_none() {
+        let message = "Your session has expired. Please log in again.".to_string();
+        connection.send(&[GameServerPacket::from(LoginError(message))]).await?;
+        return Ok(None);
+    }
+
+    Ok(Some(login))
+}
+
+fn player_creature(id: u32, name: String) -> MapCreature {
+    MapCreature {
+        outfit: MapOutfit { look_type: 128, head: 78, body: 69, legs: 58, feet: 76, ..MapOutfit::default() },
+        ..MapCreature::new(id, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use futures::{SinkExt, StreamExt};
+    use game::{map::Tile as MapTile, test_util};
+    use base::Direction;
+    use protocol::packet::client::{AutoWalk, TurnNorth, WalkEast};
+    use crate::account::Account;
+    use protocol::{TibiaCodec, packet::EncodeContext};
+    use tokio::net::TcpStream;
+    use tokio_util::codec::Framed;
+
+    /// An empty map with a tile at the spawn
+    fn spawn_map() -> Map {
+        let mut map = Map::new(200, 200);
+        map.set_tile(Position { x: 100, y: 100, z: 7 }, MapTile::default());
+        map
+    }
+
+    /// Logs in to a game server on a new connection and returns the packets of the first frame after the login
+    async fn game_login(map: Arc<Map>, sessions: Arc<SessionStore>, session_key: String) -> Vec<GameServerPacket> {
+        let mut frames = game_session(map, Arc::new(ItemTypes::new()), sessions, session_key, Vec::new()).await;
+        frames.remove(0)
+    }
+
+    /// Logs in to a game server on a new connection, then sends each packet in a frame of its own
+    ///
+    /// Each packet is sent with the number of frames the server answers with. Returns the packets of the first frame
+    /// after the login and of the answers.
+    async fn game_session(
+        map: Arc<Map>,
+        item_types: Arc<ItemTypes>,
+        sessions: Arc<SessionStore>,
+        session_key: String,
+        client_packets: Vec<(ClientPacket, usize)>,
+    ) -> Vec<Vec<GameServerPacket>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let spawn = Position { x: 100, y: 100, z: 7 };
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let connection = Connection::new(stream, DecodeContext::new()).unwrap();
+            let shared = Shared {
+                map,
+                item_types,
+                spawn,
+                sessions,
+            };
+            handle_game(connection, &shared, PLAYER_ID_START).await
+        });
+
+        let mut client = Framed::new(TcpStream::connect(addr).await.unwrap(), TibiaCodec::new());
+        let ctx = DecodeContext::new();
+
+        client.codec_mut().set_frame_type(FrameType::LengthPrefixed);
+        let mut frame = client.next().await.unwrap().unwrap();
+        let nonce = match GameServerPacket::read_from(&mut frame, &ctx).unwrap() {
+            GameServerPacket::Nonce(nonce) => nonce,
+            packet => panic!("expected Nonce, got {:?}", packet),
+        };
+
+        let login = GameLogin {
+            client_version: 1098,
+            session_key,
+            character_name: "Rustia".to_string(),
+            challenge_timestamp: nonce.timestamp,
+            challenge_rand_num: nonce.random_number,
+            ..GameLogin::default()
+        };
+        let mut frame = BytesMut::new();
+        ClientPacket::from(login.clone()).write_to(&mut frame, &EncodeContext::new()).unwrap();
+        client.codec_mut().set_frame_type(FrameType::Raw);
+        client.send(frame.freeze()).await.unwrap();
+
+        client.codec_mut().set_frame_type(FrameType::XTEA(login.xtea_key));
+        let mut ctx = DecodeContext::new();
+        ctx.set_player_position(spawn);
+        let mut frames = Vec::new();
+        let answers = std::iter::once((None, 1)).chain(client_packets.into_iter().map(|(packet, answers)| (Some(packet), answers)));
+        for (client_packet, answers) in answers {
+            if let Some(client_packet) = client_packet {
+                let mut frame = BytesMut::new();
+                client_packet.write_to(&mut frame, &EncodeContext::new()).unwrap();
+                client.send(frame.freeze()).await.unwrap();
+            }
+
+            for _ in 0..answers {
+                let mut frame = match client.next().await {
+                    Some(frame) => frame.unwrap(),
+                    None => break,
+                };
+                let mut packets = Vec::new();
+                while !frame.is_empty() {
+                    packets.push(GameServerPacket::read_from(&mut frame, &ctx).unwrap());
+                }
+                frames.push(packets);
+            }
+        }
+
+        drop(client);
+        server.await.unwrap().unwrap();
+        frames
+    }
+
+    #[tokio::test]
+    async fn test_game_login() {
+        let sessions = Arc::new(SessionStore::new());
+        let key = sessions.issue(&Account {
+            name: "rustia".to_string(),
+            characters: vec!["Rustia".to_string()],
+            ..Account::default()
+        });
+
+        let map = Arc::new(spawn_map());
+        let packets = game_login(Arc::clone(&map), sessions, key).await;
+        let ids: Vec<usize> = packets.iter().map(|packet| packet.index()).collect();
+        assert_eq!(ids, vec![
+            GameServerPacketKind::LoginSuccess as usize,
+            GameServerPacketKind::PendingStateEntered as usize,
+            GameServerPacketKind::EnterWorld as usize,
+            GameServerPacketKind::FullWorld as usize,
+            GameServerPacketKind::WorldLight as usize,
+            GameServerPacketKind::CreatureLight as usize,
+        ]);
+
+        // The player is on the spawn tile while connected
+        match &packets[3] {
+            GameServerPacket::FullWorld(world) => assert!(world.world_chunk.iter().any(|data| matches!(
+                data,
+                WorldData::Tile(tile) if matches!(&tile.things[0], Some(Thing::Creature(creature)) if creature.id == PLAYER_ID_START)
+            ))),
+            packet => panic!("expected FullWorld, got {:?}", packet),
+        }
+
+        // And removed when disconnected
+        assert!(map.things().is_empty());
+        assert!(map.tile_at(Position { x: 100, y: 100, z: 7 }).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_walk() {
+        let sessions = Arc::new(SessionStore::new());
+        let key = sessions.issue(&Account {
+            name: "rustia".to_string(),
+            characters: vec!["Rustia".to_string()],
+            ..Account::default()
+        });
+
+        // Grass at the spawn and east of it
+        let map = Arc::new(test_util::map(Position { x: 100, y: 100, z: 7 }, &[".."]));
+
+        let walks = vec![
+            (ClientPacket::from(WalkEast), 1),
+            (ClientPacket::from(TurnNorth), 1),
+            (ClientPacket::from(AutoWalk { directions: vec![Direction::West, Direction::West] }), 2),
+        ];
+        let frames = game_session(Arc::clone(&map), Arc::new(test_util::item_types()), sessions, key, walks).await;
+        let kinds = |packets: &[GameServerPacket]| packets.iter().map(|packet| packet.index()).collect::<Vec<_>>();
+
+        assert_eq!(kinds(&frames[1]), vec![
+            GameServerPacketKind::MoveCreature as usize,
+            GameServerPacketKind::WorldRowEast as usize,
+        ]);
+        match &frames[1][0] {
+            GameServerPacket::MoveCreature(moved) => {
+                assert_eq!(moved.old_position, Position { x: 100, y: 100, z: 7 });
+                assert_eq!(moved.old_stack_index, 1);
+                assert_eq!(moved.new_position, Position { x: 101, y: 100, z: 7 });
+            },
+            packet => panic!("expected MoveCreature, got {:?}", packet),
+        }
+
+        match &frames[2][..] {
+            [GameServerPacket::UpdateTileThing(UpdateTileThing { position, stack_index: 1, thing: Thing::CreatureTurn(turn) })] => {
+                assert_eq!(*position, Position { x: 101, y: 100, z: 7 });
+                assert_eq!(turn.direction, Direction::North);
+            },
+            packets => panic!("expected the player to turn, got {:?}", packets),
+        }
+
+        // There is no tile further west, the walk is cancelled after the first step
+        assert_eq!(kinds(&frames[3]), vec![
+            GameServerPacketKind::MoveCreature as usize,
+            GameServerPacketKind::WorldRowWest as usize,
+        ]);
+        match &frames[4][..] {
+            [GameServerPacket::CancelWalk(cancel)] => assert_eq!(cancel.direction, Direction::West),
+            packets => panic!("expected CancelWalk, got {:?}", packets),
+        }
+
+        // The player is removed from where it walked to
+        assert_eq!(map.things().len(), 2);
+        assert_eq!(map.tile_at(Position { x: 100, y: 100, z: 7 }).unwrap().things_iter().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_game_login_invalid_session() {
+        let packets = game_login(Arc::new(spawn_map()), Arc::new(SessionStore::new()), "invalid".to_string()).await;
+        assert!(matches!(&packets[..], [GameServerPacket::LoginError(_)]));
+    }
+}
@@ -0,0 +1,5 @@
+pub mod account;
+pub mod connection;
+pub mod game;
+pub mod login;
+pub mod session;
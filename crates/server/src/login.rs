@@ -0,0 +1,233 @@
+use std::sync::Arc;
+
+use protocol::{
+    FrameType,
+    packet::{ClientPacket, DecodeContext, LoginServerPacket, ProtocolVersion},
+    packet::client::peek_client_version,
+    packet::login::{Character, CharacterList, Error, Error2, Motd, SessionKey, World},
+};
+use tokio::net::TcpListener;
+
+use crate::{account::AccountStore, connection::Connection, session::SessionStore};
+
+/// Login server answering account logins with the character list
+///
+/// Credentials are validated through the account store, and every successful login
+/// is issued a session key that the game server verifies.
+pub struct LoginServer {
+    listen_addr: String,
+    world: World,
+    accounts: Arc<dyn AccountStore>,
+    sessions: Arc<SessionStore>,
+    motd: String,
+    decode_context: DecodeContext,
+}
+
+impl LoginServer {
+    /// Creates a login server listing characters on the provided world
+    pub fn new(listen_addr: String, world: World, accounts: Arc<dyn AccountStore>, sessions: Arc<SessionStore>) -> Self {
+        Self {
+            listen_addr,
+            world,
+            accounts,
+            sessions,
+            motd: "1\nWelcome to Rustia!".to_string(),
+            decode_context: DecodeContext::new(),
+        }
+    }
+
+    /// Sets the message of the day, prefixed with its id and a newline
+    pub fn with_motd(mut self, motd: String) -> Self {
+        self.motd = motd;
+        self
+    }
+
+    /// Sets the decode context each connection starts with, e.g to use another RSA key than the OpenTibia one
+    pub fn with_decode_context(mut self, decode_context: DecodeContext) -> Self {
+        self.decode_context = decode_context;
+        self
+    }
+
+    /// Starts the login server and consumes self
+    pub async fn run(self) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(&self.listen_addr).await?;
+
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    println!("Login: Failed to accept connection {:?}", err);
+                    continue;
+                },
+            };
+            let connection = match Connection::new(stream, self.decode_context.clone()) {
+                Ok(connection) => connection,
+                Err(err) => {
+                    println!("Login: Failed to accept connection {:?}", err);
+                    continue;
+                },
+            };
+            let motd = self.motd.clone();
+            let world = self.world.clone();
+            let accounts = Arc::clone(&self.accounts);
+            let sessions = Arc::clone(&self.sessions);
+            tokio::spawn(async move {
+                let addr = connection.addr().to_string();
+                if let Err(err) = handle_login(connection, motd, world, accounts.as_ref(), &sessions).await {
+                    println!("Login:{} Error {:?}", addr, err);
+                }
+            });
+        }
+    }
+}
+
+async fn handle_login(
+    mut connection: Connection,
+    motd: String,
+    world: World,
+    accounts: &dyn AccountStore,
+    sessions: &SessionStore,
+) -> anyhow::Result<()> {
+    let mut frame = match connection.read_frame().await? {
+        Some(frame) => frame,
+        None => return Ok(()),
+    };
+
+    if let Some(version) = peek_client_version(&frame) {
+        connection.set_version(version);
+    }
+
+    let login = match ClientPacket::read_from(&mut frame, connection.decode_context())? {
+        ClientPacket::AccountLogin(login) => login,
+        packet => anyhow::bail!("Wrong first packet, expected AccountLogin, got {:?}", packet),
+    };
+    connection.set_frame_type(FrameType::XTEA(login.xtea_key));
+
+    // The game server only accepts the session key logins of 10.98
+    if connection.encode_context().version() < ProtocolVersion::V1098 {
+        let message = format!("Only clients {} and newer are supported.", ProtocolVersion::V1098);
+        return send_error(&mut connection, &message).await;
+    }
+
+    if login.account_name.is_empty() {
+        return send_error(&mut connection, "You must enter your account name.").await;
+    }
+
+    let account = match accounts.authenticate(&login.account_name, &login.password)? {
+        Some(account) => account,
+        None => return send_error(&mut connection, "Account name or password is not correct.").await,
+    };
+    println!("Login:{} {} logged in", connection.addr(), account.name);
+
+    let packets = vec![
+        LoginServerPacket::from(Motd(motd)),
+        SessionKey(sessions.issue(&account)).into(),
+        CharacterList {
+        characters: account.characters.iter()
+            .map(|name| Character { world_id: world.id, name: name.clone() })
+            .collect(),
+        worlds: vec![world],
+        has_premium: account.premium_days > 0,
+        premium_days_left: account.premium_days,
+        }.into(),
+    ];
+
+    connection.send(&packets).await
+}
+
+/// Sends a login error, the client shows it and disconnects
+async fn send_error(connection: &mut Connection, message: &str) -> anyhow::Result<()> {
+    let packet = if connection.encode_context().version() >= ProtocolVersion::V1098 {
+        LoginServerPacket::from(Error2(message.to_string()))
+    } else {
+        LoginServerPacket::from(Error(message.to_string()))
+    };
+    connection.send(&[packet]).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use futures::{SinkExt, StreamExt};
+    use protocol::{TibiaCodec, packet::EncodeContext, packet::client::AccountLogin};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_util::codec::Framed;
+    use crate::account::{Account, MemoryAccountStore};
+
+    /// Logs in to a login server on a new connection and returns the packets it answered with
+    async fn login(
+        accounts: Arc<MemoryAccountStore>,
+        sessions: Arc<SessionStore>,
+        version: ProtocolVersion,
+        account_name: &str,
+        password: &str,
+    ) -> Vec<LoginServerPacket> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let connection = Connection::new(stream, DecodeContext::new()).unwrap();
+            let world = World { id: 0, name: "Rustia".to_string(), ip: "127.0.0.1".to_string(), port: 7172 };
+            handle_login(connection, "1\nHello".to_string(), world, accounts.as_ref(), &sessions).await
+        });
+
+        let mut client = Framed::new(TcpStream::connect(addr).await.unwrap(), TibiaCodec::new());
+        let login = AccountLogin {
+            client_version: version.number(),
+            account_name: account_name.to_string(),
+            password: password.to_string(),
+            ..AccountLogin::default()
+        };
+        let mut frame = BytesMut::new();
+        ClientPacket::from(login.clone()).write_to(&mut frame, &EncodeContext::with_version(version)).unwrap();
+        client.send(frame.freeze()).await.unwrap();
+
+        client.codec_mut().set_frame_type(FrameType::XTEA(login.xtea_key));
+        let mut frame = client.next().await.unwrap().unwrap();
+        let mut ctx = DecodeContext::new();
+        ctx.set_version(version);
+        let mut packets = Vec::new();
+        while !frame.is_empty() {
+            packets.push(LoginServerPacket::read_from(&mut frame, &ctx).unwrap());
+        }
+
+        server.await.unwrap().unwrap();
+        packets
+    }
+
+    #[tokio::test]
+    async fn test_login() {
+        let accounts = Arc::new(MemoryAccountStore::new());
+        accounts.insert(Account {
+            name: "rustia".to_string(),
+            password: "secret".to_string(),
+            premium_days: 30,
+            characters: vec!["Rustia".to_string(), "Ferris".to_string()],
+        });
+        let sessions = Arc::new(SessionStore::new());
+
+        let packets = login(Arc::clone(&accounts), Arc::clone(&sessions), ProtocolVersion::V1098, "rustia", "secret").await;
+        assert_eq!(packets.len(), 3);
+        assert!(matches!(&packets[0], LoginServerPacket::Motd(Motd(motd)) if motd == "1\nHello"));
+        match (&packets[1], &packets[2]) {
+            (LoginServerPacket::SessionKey(SessionKey(key)), LoginServerPacket::CharacterList(list)) => {
+                assert_eq!(sessions.verify(key, "Ferris"), Some("rustia".to_string()));
+                assert_eq!(list.characters.len(), 2);
+                assert!(list.has_premium);
+                assert_eq!(list.premium_days_left, 30);
+            },
+            packets => panic!("expected SessionKey and CharacterList, got {:?}", packets),
+        }
+
+        let packets = login(Arc::clone(&accounts), Arc::clone(&sessions), ProtocolVersion::V1098, "rustia", "wrong").await;
+        assert!(matches!(&packets[..], [LoginServerPacket::Error2(_)]));
+
+        let packets = login(Arc::clone(&accounts), Arc::clone(&sessions), ProtocolVersion::V1098, "", "").await;
+        assert!(matches!(&packets[..], [LoginServerPacket::Error2(Error2(message))] if message.contains("account name")));
+
+        // Older clients can't enter the game server, so they are refused with the error they know
+        let packets = login(accounts, sessions, ProtocolVersion::V860, "rustia", "secret").await;
+        assert!(matches!(&packets[..], [LoginServerPacket::Error(Error(message))] if message.contains("10.98")));
+    }
+}
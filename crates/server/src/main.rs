@@ -0,0 +1,113 @@
+use std::{env, path::Path, sync::Arc};
+
+use base::Position;
+use game::{item::{Item, ItemTypes}, map::{Map, Tile}, otbm::OtbmLoader};
+use protocol::packet::login::World;
+use rustia_server::{
+    account::{Account, AccountStore, FileAccountStore, MemoryAccountStore},
+    game::GameServer,
+    login::LoginServer,
+    session::SessionStore,
+};
+
+/// Server id of the grass the generated map is made of
+const GROUND_ID: u16 = 102;
+
+#[tokio::main]
+pub async fn main() -> anyhow::Result<()> {
+    let login_addr = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:7171".to_string());
+    let game_addr = env::args()
+        .nth(2)
+        .unwrap_or_else(|| "127.0.0.1:7172".to_string());
+    let accounts: Arc<dyn AccountStore> = match env::args().nth(3) {
+        Some(path) => Arc::new(FileAccountStore::new(path)),
+        None => Arc::new(development_accounts()),
+    };
+
+    let (game_ip, game_port) = match game_addr.rsplit_once(':') {
+        Some((ip, port)) => (ip.to_string(), port.parse()?),
+        None => anyhow::bail!("Game address must be ip:port"),
+    };
+
+    let (map, spawn) = match env::args().nth(4) {
+        Some(path) => load_map(&path)?,
+        None => {
+            let spawn = Position { x: 100, y: 100, z: 7 };
+            (generate_map(spawn), spawn)
+        },
+    };
+    let map = Arc::new(map);
+
+    // Directory with items.otb and items.xml
+    let item_types = match env::args().nth(5) {
+        Some(dir) => {
+            let dir = Path::new(&dir);
+            ItemTypes::load(dir.join("items.otb"), dir.join("items.xml"))?
+        },
+        None => ItemTypes::new(),
+    };
+
+    let sessions = Arc::new(SessionStore::new());
+    let world = World { id: 0, name: "Rustia".to_string(), ip: game_ip, port: game_port };
+    let login = LoginServer::new(login_addr, world, accounts, Arc::clone(&sessions));
+    let game = GameServer::new(game_addr, map, spawn, sessions)
+        .with_item_types(Arc::new(item_types));
+
+    let (login_result, game_result) = tokio::join!(
+        tokio::spawn(login.run()),
+        tokio::spawn(game.run()),
+    );
+    login_result??;
+    game_result??;
+
+    Ok(())
+}
+
+/// Accounts used when no accounts file is provided
+fn development_accounts() -> MemoryAccountStore {
+    let accounts = MemoryAccountStore::new();
+    accounts.insert(Account {
+        name: "rustia".to_string(),
+        password: "rustia".to_string(),
+        premium_days: 0,
+        characters: vec!["Rustia".to_string()],
+    });
+    accounts
+}
+
+/// Loads an OTBM map, players spawn at the temple of the first town
+fn load_map(path: &str) -> anyhow::Result<(Map, Position)> {
+    let loaded = OtbmLoader::new()
+        .with_progress(|progress| {
+            if let Some(total) = progress.total_bytes {
+                println!("Loading map: {}% ({} tiles)", progress.bytes_read * 100 / total.max(1), progress.tiles);
+            }
+        })
+        .load_file(path)?;
+
+    for error in loaded.errors.iter() {
+        println!("Map: Skipped node at offset {} {:?}: {:?}", error.offset, error.position, error.error);
+    }
+
+    let spawn = match loaded.map.towns().first() {
+        Some(town) => town.temple,
+        None => anyhow::bail!("The map has no towns to spawn in"),
+    };
+    Ok((loaded.map, spawn))
+}
+
+/// Generates a flat map of ground around the spawn
+fn generate_map(spawn: Position) -> Map {
+    let mut map = Map::new(spawn.x * 2, spawn.y * 2);
+
+    for x in spawn.x - 30..=spawn.x + 30 {
+        for y in spawn.y - 30..=spawn.y + 30 {
+            let mut ground = Tile::default();
+            ground.push(map.add_item(Item::new(GROUND_ID)));
+            map.set_tile(Position { x, y, z: spawn.z }, ground);
+        }
+    }
+    map
+}
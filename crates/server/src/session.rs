@@ -0,0 +1,100 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+use crate::account::Account;
+
+/// Sessions are valid for one hour by default, the client logs in to the game server right after the login server
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone)]
+struct Session {
+    account_name: String,
+    characters: Vec<String>,
+    expires: Instant,
+}
+
+/// Session keys issued by the login server and verified by the game server
+#[derive(Debug)]
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+    lifetime: Duration,
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        Self::with_lifetime(DEFAULT_LIFETIME)
+    }
+
+    /// Creates a session store where sessions expire after the provided duration
+    pub fn with_lifetime(lifetime: Duration) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            lifetime,
+        }
+    }
+
+    /// Issues a new session key for the account, allowing it to log in with any of its characters
+    pub fn issue(&self, account: &Account) -> String {
+        let bytes: [u8; 16] = rand::thread_rng().gen();
+        let key: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(key.clone(), Session {
+            account_name: account.name.clone(),
+            characters: account.characters.clone(),
+            expires: now + self.lifetime,
+        });
+
+        key
+    }
+
+    /// Returns the account name if the session key is valid and the character belongs to the account
+    pub fn verify(&self, key: &str, character_name: &str) -> Option<String> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(key)?;
+        if session.expires <= Instant::now() || !session.characters.iter().any(|name| name == character_name) {
+            return None;
+        }
+        Some(session.account_name.clone())
+    }
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let account = Account {
+            name: "rustia".to_string(),
+            characters: vec!["Rustia".to_string()],
+            ..Account::default()
+        };
+
+        let sessions = SessionStore::new();
+        let key = sessions.issue(&account);
+        assert_eq!(key.len(), 32);
+        assert_ne!(key, sessions.issue(&account));
+
+        assert_eq!(sessions.verify(&key, "Rustia"), Some("rustia".to_string()));
+        assert_eq!(sessions.verify(&key, "Ferris"), None);
+        assert_eq!(sessions.verify("invalid", "Rustia"), None);
+
+        let expired = SessionStore::with_lifetime(Duration::from_secs(0));
+        let key = expired.issue(&account);
+        assert_eq!(expired.verify(&key, "Rustia"), None);
+    }
+}